@@ -0,0 +1,1293 @@
+//! The JSON HTTP API exposed by each node.
+
+use std::fmt::Write;
+use std::sync::{Arc, OnceLock};
+
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+
+use crate::bootstrap::PeerInfo;
+use crate::cli::client::{
+    AddressResponse, AddressesQuery, AddressesResponse, BalanceQuery, BlocksQuery,
+    CreateTransactionRequest, DrainRequest, DrainResponse, FeeEstimateQuery, FeeEstimateResponse,
+    FeeKind, IdentityResponse, PeerResponse, PeersResponse, SetStakeRequest, StakerResponse,
+    StakesResponse, StatsResponse, TransactionsQuery, TransactionsResponse,
+    ValidatorBlocksResponse, VerifyResponse,
+};
+use crate::crypto::{Address, Hash, Signed};
+use crate::error::Error;
+use crate::network::broadcast::PeerConnections;
+use crate::node::handle::NodeHandle;
+use crate::node::{Block, TxStatus};
+use crate::wallet::{Transaction, Wallet};
+
+/// The header carrying the key that identifies repeats of a request creating a transaction.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// The number of items returned by a paginated request when no limit is requested.
+const DEFAULT_PAGE_SIZE: usize = 100;
+/// The maximum number of items returned by a single paginated request.
+const MAX_PAGE_SIZE: usize = 1000;
+
+/// The state shared by all the HTTP handlers.
+#[derive(Clone)]
+pub struct ApiState {
+    /// The node serving the requests.
+    pub node: NodeHandle,
+    /// The index assigned to this node during bootstrap.
+    pub node_index: usize,
+    /// The peers discovered during bootstrap.
+    pub peers: Arc<Vec<PeerInfo>>,
+    /// Whether bootstrap has completed and the node is still connected to its peers.
+    pub ready: Readiness,
+}
+
+/// Tracks whether a node can serve requests. The HTTP API is served while the node bootstraps, so
+/// that probes can reach it, but the node only exists once bootstrap has completed.
+#[derive(Debug, Clone, Default)]
+pub struct Readiness(Arc<OnceLock<PeerConnections>>);
+
+impl Readiness {
+    /// Marks bootstrap as completed. From then on the node is ready while one of `connections` is
+    /// still open.
+    pub fn set_bootstrapped(&self, connections: PeerConnections) {
+        let _ = self.0.set(connections);
+    }
+
+    /// Reports whether bootstrap has completed.
+    pub fn is_bootstrapped(&self) -> bool {
+        self.0.get().is_some()
+    }
+
+    /// Reports whether bootstrap has completed and the node is connected to one of its `peers`,
+    /// unless it is the only one.
+    fn is_ready(&self, peers: usize) -> bool {
+        self.0
+            .get()
+            .is_some_and(|connections| peers <= 1 || connections.open() > 0)
+    }
+}
+
+/// Constructs the router serving the HTTP API.
+pub fn router(state: ApiState) -> Router {
+    Router::new()
+        .route("/addresses", get(get_addresses))
+        .route("/admin/drain", post(set_draining))
+        .route("/block", get(get_block))
+        .route("/blocks", get(get_blocks))
+        .route("/balance", get(get_balance))
+        .route("/fee/estimate", get(get_fee_estimate))
+        .route("/identity", get(get_identity))
+        .route("/metrics", get(get_metrics))
+        .route("/peers", get(get_peers))
+        .route("/stake", post(set_stake))
+        .route("/stakes", get(get_stakes))
+        .route("/stats", get(get_stats))
+        .route("/submit", post(submit_transaction))
+        .route("/transaction", post(create_transaction))
+        .route("/transaction/:hash", get(get_transaction_status))
+        .route("/transactions", get(get_transactions))
+        .route("/transactions/batch", post(create_transactions))
+        .route("/verify", get(get_verify))
+        // The requests to the node would wait until bootstrap has constructed it
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_bootstrap,
+        ))
+        .route("/health", get(get_health))
+        .route("/ready", get(get_ready))
+        .with_state(state)
+}
+
+/// Rejects the requests that need the node while it is bootstrapping.
+async fn require_bootstrap(
+    State(state): State<ApiState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.ready.is_bootstrapped() {
+        let msg = "the node is bootstrapping";
+        return (StatusCode::SERVICE_UNAVAILABLE, msg).into_response();
+    }
+    next.run(request).await
+}
+
+async fn get_health() -> StatusCode {
+    StatusCode::OK
+}
+
+async fn get_ready(State(state): State<ApiState>) -> StatusCode {
+    if !state.ready.is_ready(state.peers.len()) {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    }
+    let has_blocks = state.node.call(|node| !node.blockchain().is_empty()).await;
+    if has_blocks {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// The header of `GET /block` responses with the height of the blockchain.
+pub const CHAIN_HEIGHT_HEADER: &str = "x-chain-height";
+/// The header of `GET /block` responses with the hash of the returned tip.
+pub const TIP_HASH_HEADER: &str = "x-tip-hash";
+
+/// Returns the tip of the blockchain. The height and the tip's hash are also sent as headers so
+/// that clients polling for new blocks can skip decoding the body.
+async fn get_block(
+    State(state): State<ApiState>,
+) -> ([(&'static str, String); 2], Json<Signed<Block>>) {
+    let (height, tip) = state
+        .node
+        .call(|node| (node.height(), node.blockchain().last().cloned().unwrap()))
+        .await;
+    let headers = [
+        (CHAIN_HEIGHT_HEADER, height.to_string()),
+        (TIP_HASH_HEADER, tip.hash.to_string()),
+    ];
+    (headers, Json(tip))
+}
+
+/// Returns a range of blocks or, if a validator is requested, the heights of its blocks.
+async fn get_blocks(State(state): State<ApiState>, Query(query): Query<BlocksQuery>) -> Response {
+    if let Some(validator) = query.validator {
+        let heights = {
+            let validator = validator.clone();
+            state
+                .node
+                .call(move |node| node.blocks_by_validator(&validator))
+                .await
+        };
+        return Json(ValidatorBlocksResponse { validator, heights }).into_response();
+    }
+    let blocks = state
+        .node
+        .call(move |node| {
+            let blockchain = node.blockchain();
+            let end = query.end.unwrap_or(blockchain.len()).min(blockchain.len());
+            let start = query.start.min(end);
+            let end = end.min(start + MAX_PAGE_SIZE);
+            blockchain[start..end].to_vec()
+        })
+        .await;
+    Json(blocks).into_response()
+}
+
+/// Estimates the fees of a prospective transaction without creating it.
+async fn get_fee_estimate(
+    State(state): State<ApiState>,
+    Query(query): Query<FeeEstimateQuery>,
+) -> Result<Json<FeeEstimateResponse>, (StatusCode, String)> {
+    let fee_schedule = state.node.call(|node| node.fee_schedule()).await;
+    let fee = match (query.kind, query.amount, query.length) {
+        (FeeKind::Coin, Some(amount), _) => fee_schedule.coin_fee(amount),
+        (FeeKind::Message, _, Some(length)) => fee_schedule.message_fee(length),
+        (FeeKind::Coin, None, _) => {
+            return Err((StatusCode::BAD_REQUEST, "missing the amount".into()));
+        }
+        (FeeKind::Message, _, None) => {
+            return Err((StatusCode::BAD_REQUEST, "missing the length".into()));
+        }
+    };
+    Ok(Json(FeeEstimateResponse { fee }))
+}
+
+async fn get_balance(
+    State(state): State<ApiState>,
+    Query(query): Query<BalanceQuery>,
+) -> Result<Json<Wallet>, StatusCode> {
+    let wallet = if query.pending {
+        state.node.get_pending_balance(query.address).await
+    } else {
+        state.node.get_balance(query.address).await
+    };
+    match wallet {
+        Some(wallet) => Ok(Json(wallet)),
+        None => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+async fn get_peers(State(state): State<ApiState>) -> Json<PeersResponse> {
+    let peers = state
+        .peers
+        .iter()
+        .enumerate()
+        .map(|(index, info)| PeerResponse {
+            index,
+            listen_addr: info.listen_addr,
+            address: Address::from_public_key(&info.public_key),
+        })
+        .collect();
+    Json(PeersResponse {
+        node_index: state.node_index,
+        peers,
+    })
+}
+
+async fn get_identity(State(state): State<ApiState>) -> Json<IdentityResponse> {
+    let address = state.node.call(|node| node.address().clone()).await;
+    Json(IdentityResponse {
+        node_index: state.node_index,
+        address,
+        listen_addr: state
+            .peers
+            .get(state.node_index)
+            .map(|info| info.listen_addr),
+        peers: state.peers.len(),
+    })
+}
+
+async fn get_stats(State(state): State<ApiState>) -> Json<StatsResponse> {
+    let stats = state
+        .node
+        .call(|node| StatsResponse {
+            total_supply: node.total_supply(),
+            total_staked: node.total_staked(),
+            height: node.height(),
+            pending_transactions: node.pending_transaction_count(),
+        })
+        .await;
+    Json(stats)
+}
+
+async fn get_addresses(
+    State(state): State<ApiState>,
+    Query(query): Query<AddressesQuery>,
+) -> Json<AddressesResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let response = state
+        .node
+        .call(move |node| {
+            let known = node.known_addresses();
+            let addresses = known
+                .iter()
+                .skip(query.offset)
+                .take(limit)
+                .filter_map(|address| node.wallet_for(address))
+                .map(|wallet| AddressResponse {
+                    address: wallet.address.clone(),
+                    balance: wallet.balance,
+                    stake: wallet.staked_amount(),
+                })
+                .collect();
+            AddressesResponse {
+                total: known.len(),
+                offset: query.offset,
+                addresses,
+            }
+        })
+        .await;
+    Json(response)
+}
+
+async fn get_stakes(State(state): State<ApiState>) -> Json<StakesResponse> {
+    let stakers = state
+        .node
+        .call(|node| node.stakers())
+        .await
+        .into_iter()
+        .map(|(address, stake)| StakerResponse { address, stake })
+        .collect();
+    Json(StakesResponse { stakers })
+}
+
+async fn get_transaction_status(
+    State(state): State<ApiState>,
+    Path(hash): Path<Hash>,
+) -> Json<TxStatus> {
+    Json(
+        state
+            .node
+            .call(move |node| node.transaction_status(&hash))
+            .await,
+    )
+}
+
+async fn get_transactions(
+    State(state): State<ApiState>,
+    Query(query): Query<TransactionsQuery>,
+) -> Json<TransactionsResponse> {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let response = state
+        .node
+        .call(move |node| {
+            let address = query.address.unwrap_or_else(|| node.address().clone());
+            let (transactions, total) = node.transactions_for(&address, query.offset, limit);
+            TransactionsResponse {
+                total,
+                offset: query.offset,
+                transactions: transactions.into_iter().cloned().collect(),
+            }
+        })
+        .await;
+    Json(response)
+}
+
+async fn get_verify(State(state): State<ApiState>) -> Json<VerifyResponse> {
+    let result = state.node.call(|node| node.verify_chain()).await;
+    Json(VerifyResponse {
+        valid: result.is_ok(),
+        error: result.err().map(|err| err.to_string()),
+    })
+}
+
+/// Renders the metrics of the node in the Prometheus text exposition format.
+async fn get_metrics(
+    State(state): State<ApiState>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let (metrics, mempool_size, height, total_stake) = state
+        .node
+        .call(|node| {
+            let mempool_size = node.pending_transaction_count() as u64;
+            let height = node.height() as u64;
+            (node.metrics(), mempool_size, height, node.total_staked())
+        })
+        .await;
+    let samples = [
+        (
+            "blockchat_blocks_minted_total",
+            "counter",
+            "Blocks minted by this node.",
+            metrics.blocks_minted,
+        ),
+        (
+            "blockchat_blocks_accepted_total",
+            "counter",
+            "Blocks appended to the blockchain.",
+            metrics.blocks_accepted,
+        ),
+        (
+            "blockchat_transactions_processed_total",
+            "counter",
+            "Transactions confirmed in accepted blocks.",
+            metrics.transactions_processed,
+        ),
+        (
+            "blockchat_mempool_size",
+            "gauge",
+            "Transactions waiting to be included in a block.",
+            mempool_size,
+        ),
+        (
+            "blockchat_chain_height",
+            "gauge",
+            "Blocks in the blockchain, including the genesis block.",
+            height,
+        ),
+        (
+            "blockchat_total_stake",
+            "gauge",
+            "The sum of the stakes of all known wallets.",
+            total_stake,
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, kind, help, value) in samples {
+        writeln!(body, "# HELP {name} {help}").unwrap();
+        writeln!(body, "# TYPE {name} {kind}").unwrap();
+        writeln!(body, "{name} {value}").unwrap();
+    }
+
+    let name = "blockchat_block_time_milliseconds";
+    let block_times = metrics.block_times;
+    writeln!(
+        body,
+        "# HELP {name} Time between the timestamps of consecutive accepted blocks."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE {name} histogram").unwrap();
+    for (bound, count) in block_times.cumulative_counts() {
+        match bound {
+            Some(bound) => writeln!(body, "{name}_bucket{{le=\"{bound}\"}} {count}").unwrap(),
+            None => writeln!(body, "{name}_bucket{{le=\"+Inf\"}} {count}").unwrap(),
+        }
+    }
+    writeln!(body, "{name}_sum {}", block_times.sum_ms).unwrap();
+    writeln!(body, "{name}_count {}", block_times.count()).unwrap();
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// Maps an error applying a transaction to the response returned to the client.
+fn error_response(err: Error) -> (StatusCode, String) {
+    let status = match err {
+        Error::InsufficientFunds
+        | Error::CostExceedsFunds(_, _)
+        | Error::NonceReused(_, _)
+        | Error::InvalidSignature(_)
+        | Error::ForeignSigner
+        | Error::InvalidRecipient
+        | Error::SystemTransaction => StatusCode::BAD_REQUEST,
+        Error::ObserverStake => StatusCode::FORBIDDEN,
+        Error::NodeDraining | Error::MempoolFull => StatusCode::SERVICE_UNAVAILABLE,
+        Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, err.to_string())
+}
+
+/// Creates a transaction of the node's wallet. A request repeating the idempotency key of a recent
+/// one returns the transaction created for it instead of creating another one, so clients can
+/// safely retry requests whose response was lost.
+async fn create_transaction(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Json(req): Json<CreateTransactionRequest>,
+) -> Result<(StatusCode, Json<Signed<Transaction>>), (StatusCode, String)> {
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(key) => match key.to_str() {
+            Ok(key) => Some(key.to_owned()),
+            Err(_) => {
+                return Err((StatusCode::BAD_REQUEST, "invalid idempotency key".into()));
+            }
+        },
+        None => None,
+    };
+    let signed_tx = state
+        .node
+        .call(move |node| {
+            if let Some(key) = &idempotency_key {
+                if let Some(signed_tx) = node.idempotent_transaction(key) {
+                    return Ok(signed_tx.clone());
+                }
+            }
+            if node.is_draining() {
+                return Err(Error::NodeDraining);
+            }
+            let tx = build_transaction(node.wallet(), req);
+            node.wallet().check_cost(&tx)?;
+            let signed_tx = node.sign_transaction(tx);
+            node.send_transaction(signed_tx.clone())?;
+            if let Some(key) = idempotency_key {
+                node.remember_idempotency_key(key, signed_tx.clone());
+            }
+            Ok(signed_tx)
+        })
+        .await
+        .map_err(error_response)?;
+    Ok((StatusCode::CREATED, Json(signed_tx)))
+}
+
+/// Creates several transactions with consecutive nonces. Either all of them are broadcast or, if
+/// any of them is invalid, none is.
+async fn create_transactions(
+    State(state): State<ApiState>,
+    Json(reqs): Json<Vec<CreateTransactionRequest>>,
+) -> Result<(StatusCode, Json<Vec<Signed<Transaction>>>), (StatusCode, String)> {
+    let signed_txs = state
+        .node
+        .call(move |node| {
+            if node.is_draining() {
+                return Err(Error::NodeDraining);
+            }
+            // The transactions are applied to a copy of the wallet that is discarded on failure
+            let mut wallet = node.wallet().clone();
+            let mut signed_txs = Vec::with_capacity(reqs.len());
+            for req in reqs {
+                let tx = build_transaction(&wallet, req);
+                wallet.check_cost(&tx)?;
+                let signed_tx = node.sign_transaction(tx);
+                wallet.apply_tx(signed_tx.clone())?;
+                signed_txs.push(signed_tx);
+            }
+            node.broadcast_transactions(&signed_txs)?;
+            *node.wallet_mut() = wallet;
+            Ok(signed_txs)
+        })
+        .await
+        .map_err(error_response)?;
+    Ok((StatusCode::CREATED, Json(signed_txs)))
+}
+
+/// Builds the requested transaction of the provided wallet.
+fn build_transaction(wallet: &Wallet, req: CreateTransactionRequest) -> Transaction {
+    match req {
+        CreateTransactionRequest::Coin { recipient, amount } => {
+            wallet.create_coin_tx(recipient, amount)
+        }
+        CreateTransactionRequest::Message { recipient, message } => {
+            wallet.create_message_tx(recipient, message)
+        }
+    }
+}
+
+async fn set_stake(
+    State(state): State<ApiState>,
+    Json(req): Json<SetStakeRequest>,
+) -> Result<(StatusCode, Json<Signed<Transaction>>), (StatusCode, String)> {
+    let signed_tx = state
+        .node
+        .call(move |node| {
+            if node.is_observer() {
+                return Err(Error::ObserverStake);
+            }
+            if node.is_draining() {
+                return Err(Error::NodeDraining);
+            }
+            let tx = node.wallet().create_stake_tx(req.amount);
+            let signed_tx = node.sign_transaction(tx);
+            node.send_transaction(signed_tx.clone())?;
+            Ok(signed_tx)
+        })
+        .await
+        .map_err(error_response)?;
+    Ok((StatusCode::CREATED, Json(signed_tx)))
+}
+
+/// Broadcasts a transaction signed by the client. The node only verifies it, it never signs on
+/// behalf of the client.
+async fn submit_transaction(
+    State(state): State<ApiState>,
+    Json(tx): Json<Signed<Transaction>>,
+) -> Result<(StatusCode, Json<Signed<Transaction>>), (StatusCode, String)> {
+    state
+        .node
+        .submit_transaction(tx.clone())
+        .await
+        .map_err(error_response)?;
+    Ok((StatusCode::CREATED, Json(tx)))
+}
+
+/// Turns the drain mode of the node on or off. Reports the transactions still waiting to be minted
+/// so that an operator can tell when the mempool is drained.
+async fn set_draining(
+    State(state): State<ApiState>,
+    Json(req): Json<DrainRequest>,
+) -> Json<DrainResponse> {
+    let response = state
+        .node
+        .call(move |node| {
+            node.set_draining(req.draining);
+            DrainResponse {
+                draining: node.is_draining(),
+                pending_transactions: node.pending_transaction_count(),
+            }
+        })
+        .await;
+    Json(response)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use tokio::runtime::Runtime;
+
+    use crate::cli::client::{BlockchatClient, ClientError};
+    use crate::cluster::TestCluster;
+    use crate::crypto::{self, PrivateKey, SignatureScheme};
+    use crate::genesis::GenesisConfig;
+    use crate::network::broadcast::{Broadcaster, ConnectionRetries, ConnectionTimeouts};
+    use crate::network::{Network, TestNetwork};
+    use crate::node::{MempoolLimits, Message, Node};
+
+    use super::*;
+
+    /// Serves the API on an ephemeral port and returns its URL.
+    async fn serve(state: ApiState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(state)).await });
+        rpc_url
+    }
+
+    /// Spawns `node` and serves the API for it on an ephemeral port. The API is served by the
+    /// returned runtime so it must be kept alive for as long as the client is used.
+    fn test_api(node: Node) -> (NodeHandle, BlockchatClient, Runtime) {
+        let node = NodeHandle::spawn(node);
+        let state = ApiState {
+            node: node.clone(),
+            node_index: 0,
+            peers: Arc::new(vec![]),
+            ready: Readiness::default(),
+        };
+        state.ready.set_bootstrapped(PeerConnections::default());
+        let runtime = Runtime::new().unwrap();
+        let rpc_url = runtime.block_on(serve(state));
+        let client = BlockchatClient::new(rpc_url.parse().unwrap());
+        (node, client, runtime)
+    }
+
+    /// Constructs a node holding `funds` BCC in the genesis block, along with the `balances` of
+    /// other addresses.
+    fn test_node(funds: u64, balances: &[(&Address, u64)]) -> Node {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let mut genesis = GenesisConfig::new(Address::from_public_key(&public_key), funds, 5);
+        for &(address, balance) in balances {
+            genesis.balances.insert(address.clone(), balance);
+        }
+        Node::from_genesis("test_node".into(), public_key, private_key, genesis).unwrap()
+    }
+
+    /// Generates the key of a user and returns it along with the user's address.
+    fn test_user() -> (PrivateKey, Address) {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        (private_key, Address::from_public_key(&public_key))
+    }
+
+    #[test]
+    fn health_and_readiness() {
+        let (_, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let listeners = [(); 2].map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap());
+        let addrs = listeners
+            .each_ref()
+            .map(|listener| listener.local_addr().unwrap());
+        let peers = addrs
+            .iter()
+            .map(|&listen_addr| PeerInfo {
+                listen_addr,
+                public_key: public_key.clone(),
+            })
+            .collect();
+        let node = test_node(1000, &[]);
+
+        // The API is served while the node is bootstrapping
+        let (handle, deferred_node) = NodeHandle::deferred();
+        let ready = Readiness::default();
+        let state = ApiState {
+            node: handle,
+            node_index: 1,
+            peers: Arc::new(peers),
+            ready: ready.clone(),
+        };
+        let runtime = Runtime::new().unwrap();
+        let rpc_url = runtime.block_on(serve(state));
+        let status = |path: &str| {
+            let url = format!("{rpc_url}/{path}");
+            runtime.block_on(async move { reqwest::get(url).await.unwrap().status().as_u16() })
+        };
+        assert_eq!(status("health"), StatusCode::OK.as_u16());
+        assert_eq!(status("ready"), StatusCode::SERVICE_UNAVAILABLE.as_u16());
+        assert_eq!(status("balance"), StatusCode::SERVICE_UNAVAILABLE.as_u16());
+
+        // Bootstrap connects to the other peer, which never sends anything so the connection
+        // eventually times out
+        let timeouts = ConnectionTimeouts {
+            read: Some(Duration::from_secs(1)),
+            write: None,
+        };
+        let retries = ConnectionRetries::default();
+        let [peer_listener, listener] = listeners;
+        let peer = std::thread::spawn(move || {
+            Broadcaster::<Message>::with_timeouts(peer_listener, &addrs, 0, timeouts, retries)
+        });
+        let network = Broadcaster::with_timeouts(listener, &addrs, 1, timeouts, retries).unwrap();
+        let _peer = peer.join().unwrap().unwrap();
+        let connections = network.connections();
+        deferred_node.start_with_network(node, network);
+        ready.set_bootstrapped(connections);
+        assert_eq!(status("health"), StatusCode::OK.as_u16());
+        assert_eq!(status("ready"), StatusCode::OK.as_u16());
+        assert_eq!(status("balance"), StatusCode::OK.as_u16());
+
+        // Without any connection to its peers the node is no longer ready
+        let unavailable = StatusCode::SERVICE_UNAVAILABLE.as_u16();
+        for _ in 0..100 {
+            if status("ready") == unavailable {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(status("ready"), unavailable);
+        assert_eq!(status("health"), StatusCode::OK.as_u16());
+    }
+
+    #[test]
+    fn balance_of_address() {
+        let (_, other_address) = test_user();
+        let (_, unknown_address) = test_user();
+        let node = test_node(1000, &[(&other_address, 500)]);
+        let node_address = node.address().clone();
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let own = client.get_balance().await.unwrap();
+            assert_eq!(own.address, node_address);
+            assert_eq!(own.balance, 1000);
+
+            let other = client.get_balance_of(&other_address).await.unwrap();
+            assert_eq!(other.address, other_address);
+            assert_eq!(other.balance, 500);
+
+            let err = client.get_balance_of(&unknown_address).await.unwrap_err();
+            assert!(
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 404),
+                "{err}"
+            );
+        });
+    }
+
+    #[test]
+    fn pending_balance() {
+        let (user_key, user_address) = test_user();
+        let node = test_node(1000, &[(&user_address, 500)]);
+        let node_address = node.address().clone();
+        let (node, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            // The node sends 100 BCC plus 3 BCC of fees to the user, who sends 50 BCC plus 1 BCC
+            // of fees back
+            client
+                .send_transaction(user_address.clone(), 100)
+                .await
+                .unwrap();
+            let user_wallet = client.get_balance_of(&user_address).await.unwrap();
+            let tx = user_wallet.create_coin_tx(node_address.clone(), 50);
+            let tx = user_wallet.sign_tx(tx, &user_key).unwrap();
+            client.submit_transaction(&tx).await.unwrap();
+
+            // Only the pending balances reflect the transfers before they are minted
+            let confirmed = client.get_balance_of(&node_address).await.unwrap();
+            assert_eq!(confirmed.balance, 1000);
+            let pending = client.get_pending_balance(None).await.unwrap();
+            assert_eq!(pending.address, node_address);
+            assert_eq!(pending.balance, 1000 - 103 + 50);
+            let confirmed = client.get_balance_of(&user_address).await.unwrap();
+            assert_eq!(confirmed.balance, 500);
+            let pending = client.get_pending_balance(Some(&user_address)).await;
+            assert_eq!(pending.unwrap().balance, 500 + 100 - 51);
+
+            // Once minted the confirmed balance catches up
+            node.call(|node| {
+                let block = node.mint_block();
+                node.handle_block(block).unwrap();
+            })
+            .await;
+            let confirmed = client.get_balance_of(&user_address).await.unwrap();
+            assert_eq!(confirmed.balance, 500 + 100 - 51);
+        });
+    }
+
+    #[test]
+    fn stakes_of_addresses() {
+        let (other_private_key, other_address) = test_user();
+        let mut node = test_node(1000, &[(&other_address, 500)]);
+        let node_address = node.address().clone();
+        let own_tx = node.sign_transaction(node.wallet().create_stake_tx(50));
+        node.wallet_mut().apply_tx(own_tx.clone()).unwrap();
+        node.handle_transaction(own_tx).unwrap();
+        let other_wallet = node.wallet_for(&other_address).unwrap();
+        let other_tx = other_private_key.sign(other_wallet.create_stake_tx(30));
+        node.handle_transaction(other_tx).unwrap();
+        let block = node.mint_block();
+        node.handle_block(block).unwrap();
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let stakes = client.get_stakes().await.unwrap();
+            let stakers: Vec<_> = stakes
+                .stakers
+                .into_iter()
+                .map(|staker| (staker.address, staker.stake))
+                .collect();
+            let mut expected = vec![(node_address, 50), (other_address, 30)];
+            expected.sort();
+            assert_eq!(stakers, expected);
+        });
+    }
+
+    #[test]
+    fn batch_transactions() {
+        let (_, other_address) = test_user();
+        let node = test_node(1000, &[]);
+        let (node, client, runtime) = test_api(node);
+        let txs = runtime.block_on(async {
+            let coin = |amount| CreateTransactionRequest::Coin {
+                recipient: other_address.clone(),
+                amount,
+            };
+            let message = CreateTransactionRequest::Message {
+                recipient: other_address.clone(),
+                message: "hello".into(),
+            };
+
+            let txs = client
+                .send_batch(&[coin(10), message, coin(20)])
+                .await
+                .unwrap();
+            let nonces: Vec<_> = txs.iter().map(|tx| tx.data.nonce).collect();
+            assert_eq!(nonces, [0, 1, 2]);
+
+            // The second transaction can't be afforded so the first is not sent either
+            let err = client
+                .send_batch(&[coin(10), coin(5000)])
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 400),
+                "{err}"
+            );
+            assert_eq!(client.get_balance().await.unwrap().nonce, 3);
+            txs
+        });
+
+        let (mut network, mut peer_network) = TestNetwork::new();
+        runtime.block_on(node.call(move |node| node.step(&mut network)));
+        let broadcast: Vec<_> = std::iter::from_fn(|| peer_network.recv())
+            .filter_map(|msg| match msg {
+                Message::Transaction(tx) => Some(tx),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(broadcast, txs);
+    }
+
+    #[test]
+    fn mempool_limits() {
+        let (_, other_address) = test_user();
+        let mut node = test_node(1000, &[]);
+        node.set_mempool_limits(MempoolLimits {
+            max_transactions: 3,
+            max_per_sender: 1,
+            max_per_interval: 1,
+            ..MempoolLimits::default()
+        });
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let coin = || CreateTransactionRequest::Coin {
+                recipient: other_address.clone(),
+                amount: 10,
+            };
+            // The per sender limits don't apply to the transactions of the node itself
+            client.send_batch(&[coin(), coin(), coin()]).await.unwrap();
+
+            // Once the mempool is full the node's transactions are rejected without using a nonce
+            let err = client
+                .send_transaction(other_address.clone(), 10)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 503),
+                "{err}"
+            );
+            let err = client.send_batch(&[coin()]).await.unwrap_err();
+            assert!(
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 503),
+                "{err}"
+            );
+            assert_eq!(client.get_balance().await.unwrap().nonce, 3);
+        });
+    }
+
+    #[test]
+    fn submit_signed_transaction() {
+        let (user_key, user_address) = test_user();
+        let (other_key, _) = test_user();
+        let node = test_node(1000, &[(&user_address, 500)]);
+        let node_address = node.address().clone();
+        let (node, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let is_rejected = |err: ClientError| {
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 400)
+            };
+
+            // The user signs with a key the node never sees
+            let wallet = client.get_balance_of(&user_address).await.unwrap();
+            let tx = wallet.create_coin_tx(node_address.clone(), 100);
+            assert!(wallet.sign_tx(tx.clone(), &other_key).is_err());
+            let signed_tx = wallet.sign_tx(tx, &user_key).unwrap();
+            let accepted = client.submit_transaction(&signed_tx).await.unwrap();
+            assert_eq!(accepted, signed_tx);
+            let status = client.transaction_status(&signed_tx.hash).await.unwrap();
+            assert_eq!(status, TxStatus::Pending);
+
+            // Signed by a key other than the sender's
+            let tx = wallet.create_coin_tx(node_address.clone(), 50);
+            let err = client
+                .submit_transaction(&other_key.sign(tx))
+                .await
+                .unwrap_err();
+            assert!(is_rejected(err));
+
+            // Tampered with after signing
+            let mut tampered_tx = wallet
+                .sign_tx(wallet.create_coin_tx(node_address.clone(), 1), &user_key)
+                .unwrap();
+            tampered_tx.data = wallet.create_coin_tx(node_address.clone(), 400);
+            let err = client.submit_transaction(&tampered_tx).await.unwrap_err();
+            assert!(is_rejected(err));
+
+            // More than the user owns
+            let tx = wallet.create_coin_tx(node_address.clone(), 1000);
+            let signed_tx = wallet.sign_tx(tx, &user_key).unwrap();
+            let err = client.submit_transaction(&signed_tx).await.unwrap_err();
+            assert!(is_rejected(err));
+        });
+        let pending = runtime.block_on(node.call(|node| node.pending_transaction_count()));
+        assert_eq!(pending, 1);
+    }
+
+    #[test]
+    fn paginated_transactions() {
+        let (_, other_address) = test_user();
+        let mut node = test_node(10_000, &[]);
+        for _ in 0..25 {
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(other_address.clone(), 1));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+        }
+        while node.has_pending_transactions() {
+            let block = node.mint_block();
+            node.handle_block(block).unwrap();
+        }
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let mut nonces = vec![];
+            for (offset, expected_len) in [(0, 10), (10, 10), (20, 5), (30, 0)] {
+                let page = client
+                    .get_transactions(&other_address, offset, 10)
+                    .await
+                    .unwrap();
+                assert_eq!(page.total, 25);
+                assert_eq!(page.offset, offset);
+                assert_eq!(page.transactions.len(), expected_len);
+                nonces.extend(page.transactions.iter().map(|tx| tx.data.nonce));
+            }
+            assert_eq!(nonces, (0..25).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn known_addresses() {
+        let receivers: Vec<_> = (0..2).map(|_| test_user().1).collect();
+        let mut node = test_node(10_000, &[]);
+        let node_address = node.address().clone();
+        for (amount, receiver) in [
+            (100, &receivers[0]),
+            (200, &receivers[1]),
+            (50, &receivers[0]),
+        ] {
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver.clone(), amount));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+        }
+        let block = node.mint_block();
+        node.handle_block(block).unwrap();
+
+        let mut expected = vec![
+            node_address.clone(),
+            receivers[0].clone(),
+            receivers[1].clone(),
+        ];
+        expected.sort();
+        assert_eq!(node.known_addresses(), expected);
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let mut addresses = vec![];
+            for (offset, expected_len) in [(0, 2), (2, 1), (4, 0)] {
+                let page = client.get_addresses(offset, 2).await.unwrap();
+                assert_eq!(page.total, 3);
+                assert_eq!(page.offset, offset);
+                assert_eq!(page.addresses.len(), expected_len);
+                addresses.extend(page.addresses);
+            }
+            let listed: Vec<_> = addresses.iter().map(|a| a.address.clone()).collect();
+            assert_eq!(listed, expected);
+
+            let balance = |address: &Address| {
+                let entry = addresses.iter().find(|a| &a.address == address).unwrap();
+                (entry.balance, entry.stake)
+            };
+            assert_eq!(balance(&receivers[0]), (150, 0));
+            assert_eq!(balance(&receivers[1]), (200, 0));
+            let (_, stake) = balance(&node_address);
+            assert_eq!(stake, 1);
+        });
+    }
+
+    #[test]
+    fn blocks_by_validator() {
+        let mut cluster = TestCluster::new(2, 10_000, 5);
+        for index in 0..2 {
+            let node = cluster.node_mut(index);
+            let tx = node.sign_transaction(node.wallet().create_stake_tx(100));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.broadcast_transaction(tx).unwrap();
+        }
+        // The validators are drawn at random so keep minting until both of them have
+        for _ in 0..100 {
+            cluster.step();
+            let node = &cluster.nodes()[0];
+            let minted = |index: usize| {
+                let validator = cluster.nodes()[index].address();
+                !node.blocks_by_validator(validator).is_empty()
+            };
+            if node.blockchain().len() > 5 && minted(0) && minted(1) {
+                break;
+            }
+        }
+        cluster.settle();
+
+        let validators: Vec<_> = cluster
+            .nodes()
+            .iter()
+            .map(|n| n.address().clone())
+            .collect();
+        let node = cluster.into_nodes().swap_remove(0);
+        let expected: Vec<Vec<usize>> = validators
+            .iter()
+            .map(|validator| {
+                (1..node.blockchain().len())
+                    .filter(|&height| &node.blockchain()[height].data.validator == validator)
+                    .collect()
+            })
+            .collect();
+        assert!(expected.iter().all(|heights| !heights.is_empty()));
+        assert_eq!(
+            expected[0].len() + expected[1].len(),
+            node.blockchain().len() - 1
+        );
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            for (validator, expected) in validators.iter().zip(&expected) {
+                let response = client.get_blocks_by_validator(validator).await.unwrap();
+                assert_eq!(&response.validator, validator);
+                assert_eq!(&response.heights, expected);
+            }
+            let response = client
+                .get_blocks_by_validator(&Address::invalid())
+                .await
+                .unwrap();
+            assert!(response.heights.is_empty());
+        });
+    }
+
+    #[test]
+    fn drain_mode() {
+        let (user_private_key, user_address) = test_user();
+        let mut node = test_node(10_000, &[(&user_address, 1000)]);
+        let node_address = node.address().clone();
+        let pending = node.sign_transaction(node.wallet().create_coin_tx(user_address.clone(), 10));
+        node.wallet_mut().apply_tx(pending.clone()).unwrap();
+        node.handle_transaction(pending.clone()).unwrap();
+        let (node, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let drain = client.set_draining(true).await.unwrap();
+            assert!(drain.draining);
+            assert_eq!(drain.pending_transactions, 1);
+
+            // New transactions are rejected whether they come from clients or peers
+            let is_unavailable = |err: ClientError| {
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 503)
+            };
+            let err = client.send_transaction(user_address.clone(), 20).await;
+            assert!(is_unavailable(err.unwrap_err()));
+            let err = client.stake(100).await;
+            assert!(is_unavailable(err.unwrap_err()));
+            let user_wallet = client.get_balance_of(&user_address).await.unwrap();
+            let tx = user_wallet.create_coin_tx(node_address.clone(), 30);
+            let tx = user_wallet.sign_tx(tx, &user_private_key).unwrap();
+            let err = client.submit_transaction(&tx).await;
+            assert!(is_unavailable(err.unwrap_err()));
+            let result = node.call(move |node| node.handle_transaction(tx)).await;
+            assert!(matches!(result, Err(Error::NodeDraining)));
+            // The rejected requests did not touch the wallet of the node
+            assert_eq!(node.get_balance(None).await.unwrap().nonce, 1);
+
+            // The pending transaction is still minted
+            node.call(|node| {
+                let block = node.mint_block();
+                node.handle_block(block).unwrap();
+            })
+            .await;
+            assert!(matches!(
+                client.transaction_status(&pending.hash).await.unwrap(),
+                TxStatus::Confirmed { .. }
+            ));
+            let drain = client.set_draining(false).await.unwrap();
+            assert!(!drain.draining);
+            assert_eq!(drain.pending_transactions, 0);
+            client.send_transaction(user_address, 20).await.unwrap();
+        });
+    }
+
+    #[test]
+    fn tip_headers() {
+        let (_, other_address) = test_user();
+        let mut node = test_node(10_000, &[]);
+        for _ in 0..2 {
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(other_address.clone(), 1));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+            let block = node.mint_block();
+            node.handle_block(block).unwrap();
+        }
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let url = client.rpc_url().join("block").unwrap();
+            let response = reqwest::get(url).await.unwrap();
+            assert_eq!(response.status().as_u16(), 200);
+            let header = |name| response.headers()[name].to_str().unwrap().to_owned();
+            let height = header(CHAIN_HEIGHT_HEADER);
+            let tip_hash = header(TIP_HASH_HEADER);
+            let tip: Signed<Block> = response.json().await.unwrap();
+
+            assert_eq!(height, "3");
+            assert_eq!(tip_hash, tip.hash.to_string());
+        });
+    }
+
+    #[test]
+    fn metrics() {
+        let (_, other_address) = test_user();
+        let mut node = test_node(10_000, &[]);
+        for _ in 0..8 {
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(other_address.clone(), 1));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+        }
+        // A full block is accepted and three transactions stay in the mempool
+        let block = node.mint_block();
+        node.handle_block(block).unwrap();
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let url = client.rpc_url().join("metrics").unwrap();
+            let response = reqwest::get(url).await.unwrap();
+            assert_eq!(response.status().as_u16(), 200);
+            let body = response.text().await.unwrap();
+
+            let samples: BTreeMap<_, _> = body
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .map(|line| {
+                    let (name, value) = line.split_once(' ').unwrap();
+                    (name.to_owned(), value.parse::<u64>().unwrap())
+                })
+                .collect();
+            assert_eq!(samples["blockchat_blocks_minted_total"], 1);
+            assert_eq!(samples["blockchat_blocks_accepted_total"], 1);
+            assert_eq!(samples["blockchat_transactions_processed_total"], 5);
+            assert_eq!(samples["blockchat_mempool_size"], 3);
+            assert_eq!(samples["blockchat_chain_height"], 2);
+            assert_eq!(samples["blockchat_total_stake"], 1);
+            assert!(body.contains("# TYPE blockchat_blocks_minted_total counter"));
+            assert!(body.contains("# TYPE blockchat_mempool_size gauge"));
+            assert!(body.contains("# TYPE blockchat_block_time_milliseconds histogram"));
+            assert_eq!(
+                samples["blockchat_block_time_milliseconds_bucket{le=\"+Inf\"}"],
+                1
+            );
+            assert_eq!(samples["blockchat_block_time_milliseconds_count"], 1);
+        });
+    }
+
+    #[test]
+    fn idempotent_transaction() {
+        let (_, other_address) = test_user();
+        let node = test_node(1000, &[]);
+        let (node, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let first = client
+                .send_transaction_with_key(other_address.clone(), 10, "retried")
+                .await
+                .unwrap();
+            let repeat = client
+                .send_transaction_with_key(other_address.clone(), 10, "retried")
+                .await
+                .unwrap();
+            assert_eq!(repeat.hash, first.hash);
+            assert_eq!(repeat.data.nonce, 0);
+            assert_eq!(node.call(|node| node.pending_transaction_count()).await, 1);
+            assert_eq!(node.get_balance(None).await.unwrap().nonce, 1);
+
+            // A different key creates a new transaction
+            let other = client
+                .send_transaction_with_key(other_address, 10, "another")
+                .await
+                .unwrap();
+            assert_eq!(other.data.nonce, 1);
+            assert_eq!(node.call(|node| node.pending_transaction_count()).await, 2);
+        });
+    }
+
+    #[test]
+    fn fee_estimate() {
+        let node = test_node(1000, &[]);
+        let node_address = node.address().clone();
+        let fee_schedule = node.fee_schedule();
+        let wallet = node.wallet().clone();
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            // The fees of small transfers round down to nothing and huge ones must not overflow
+            for amount in [0, 33, 34, 100, 1_000_000, u64::MAX] {
+                let tx = wallet.create_coin_tx(node_address.clone(), amount);
+                let estimate = client.estimate_coin_fee(amount).await.unwrap();
+                assert_eq!(estimate.fee, tx.fees(&fee_schedule), "amount {amount}");
+            }
+            assert_eq!(client.estimate_coin_fee(33).await.unwrap().fee, 0);
+            for length in [0, 1, 50, 4096] {
+                let tx = wallet.create_message_tx(node_address.clone(), "a".repeat(length));
+                let estimate = client.estimate_message_fee(length).await.unwrap();
+                assert_eq!(estimate.fee, tx.fees(&fee_schedule), "length {length}");
+            }
+
+            let url = |query| {
+                let path = format!("fee/estimate?{query}");
+                client.rpc_url().join(&path).unwrap()
+            };
+            let response = reqwest::get(url("kind=coin&amount=100")).await.unwrap();
+            let estimate: FeeEstimateResponse = response.json().await.unwrap();
+            assert_eq!(estimate.fee, 3);
+            for query in [
+                "kind=coin",
+                "kind=message&amount=10",
+                "kind=stake&amount=10",
+            ] {
+                let response = reqwest::get(url(query)).await.unwrap();
+                assert_eq!(response.status().as_u16(), 400, "{query}");
+            }
+        });
+    }
+
+    #[test]
+    fn over_balance_transfer() {
+        let (_, other_address) = test_user();
+        let node = test_node(1000, &[]);
+        let (_, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            let err = client
+                .send_transaction(other_address, 1_000_000)
+                .await
+                .unwrap_err();
+            let ClientError::Status { status, body } = err else {
+                panic!("unexpected error {err}");
+            };
+            assert_eq!(status.as_u16(), 400);
+            // The amount plus its 3% fee, while 1 BCC of the balance is staked
+            assert!(
+                body.contains("costs 1030000 BCC but only 999 BCC are available"),
+                "{body}"
+            );
+        });
+    }
+
+    #[test]
+    fn requests_after_panicking_request() {
+        let node = test_node(1000, &[]);
+        let node_address = node.address().clone();
+        let (node, client, runtime) = test_api(node);
+        runtime.block_on(async {
+            // A request panicking on the thread that owns the node only fails that request
+            let panicking = tokio::spawn(async move {
+                node.call(|_| panic!("failing the request")).await;
+            });
+            assert!(panicking.await.is_err());
+
+            let err = client
+                .send_transaction(node_address.clone(), 1_000_000)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(&err, ClientError::Status { status, .. } if status.as_u16() == 400),
+                "{err}"
+            );
+
+            let wallet = client.get_balance().await.unwrap();
+            assert_eq!(wallet.address, node_address);
+            assert_eq!(wallet.balance, 1000);
+        });
+    }
+}
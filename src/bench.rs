@@ -0,0 +1,314 @@
+//! The benchmark data and the results of a benchmark run in their structured output formats.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// A message of the benchmark data along with the index of the peer it is sent to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchMessage {
+    pub peer_index: usize,
+    pub message: String,
+}
+
+/// Parses benchmark data consisting of one `id<peer_index> <message>` line per message. Blank
+/// lines are skipped. Returns an error describing the first malformed line.
+pub fn parse_bench_data(data: &str) -> Result<Vec<BenchMessage>, String> {
+    let mut messages = vec![];
+    for (number, line) in data.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let malformed = || format!("line {}: expected `id<n> <message>`: {line:?}", number + 1);
+        let (id, message) = line.split_once(' ').ok_or_else(malformed)?;
+        let peer_index = id
+            .strip_prefix("id")
+            .and_then(|index| index.parse().ok())
+            .ok_or_else(malformed)?;
+        messages.push(BenchMessage {
+            peer_index,
+            message: message.to_owned(),
+        });
+    }
+    Ok(messages)
+}
+
+/// The path of the benchmark data of the node with the given index.
+pub fn data_path(data_dir: &Path, index: usize) -> PathBuf {
+    data_dir.join(format!("trans{index}.txt"))
+}
+
+/// Counts the messages sent during a benchmark of `peers` nodes. Each node sends the messages of
+/// its own data file that are addressed to one of the nodes.
+pub fn expected_messages(data_dir: &Path, peers: usize) -> Result<usize, String> {
+    let mut count = 0;
+    for index in 0..peers {
+        let path = data_path(data_dir, index);
+        let messages = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|data| parse_bench_data(&data))
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        count += messages
+            .iter()
+            .filter(|message| message.peer_index < peers)
+            .count();
+    }
+    Ok(count)
+}
+
+/// The results of a benchmark run as observed by a single node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BenchStats {
+    /// The confirmed transactions per second.
+    pub throughput: f64,
+    /// The minted blocks per second.
+    pub block_time: f64,
+    /// The number of transactions in the blockchain, including the bootstrap ones.
+    pub total_txs: usize,
+    /// The number of blocks in the blockchain, including the genesis block.
+    pub total_blocks: usize,
+    /// The duration of the benchmark in seconds.
+    pub elapsed_secs: f64,
+    /// The number of blocks minted by each node, indexed by the node index.
+    pub minted_blocks: Vec<usize>,
+    /// The time from submitting a transaction until it is included in an accepted block.
+    pub latency: LatencyPercentiles,
+}
+
+/// Percentiles of a latency distribution, in milliseconds.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+}
+
+impl LatencyPercentiles {
+    /// Computes the percentiles of the provided latencies using the nearest-rank method. Returns
+    /// all zeroes if there are no latencies.
+    pub fn from_latencies(latencies: &[Duration]) -> Self {
+        let mut sorted = latencies.to_vec();
+        sorted.sort();
+        let percentile = |p: usize| match sorted.len() {
+            0 => 0.0,
+            len => {
+                let rank = (p * len).div_ceil(100).max(1);
+                sorted[rank - 1].as_nanos() as f64 / 1_000_000.0
+            }
+        };
+        Self {
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+            p99_ms: percentile(99),
+        }
+    }
+}
+
+/// The file formats benchmark results can be written in.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum StatsFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl fmt::Display for StatsFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatsFormat::Json => write!(f, "json"),
+            StatsFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StatsFormat::Json),
+            "csv" => Ok(StatsFormat::Csv),
+            _ => Err(format!("unknown output format: {s}")),
+        }
+    }
+}
+
+impl BenchStats {
+    /// Writes the results to `path` in the provided format, replacing any existing file.
+    pub fn write_to_file(&self, path: &Path, format: StatsFormat) -> io::Result<()> {
+        let mut file = std::fs::File::create(path)?;
+        match format {
+            StatsFormat::Json => serde_json::to_writer_pretty(&mut file, self)?,
+            StatsFormat::Csv => self.write_csv(&mut file)?,
+        }
+        file.flush()
+    }
+
+    /// Writes the results as a CSV header followed by a single row. The minted blocks of each
+    /// node are written in a separate `minted_blocks_<index>` column.
+    fn write_csv(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut header = vec![
+            "throughput".to_owned(),
+            "block_time".to_owned(),
+            "total_txs".to_owned(),
+            "total_blocks".to_owned(),
+            "elapsed_secs".to_owned(),
+            "latency_p50_ms".to_owned(),
+            "latency_p95_ms".to_owned(),
+            "latency_p99_ms".to_owned(),
+        ];
+        let mut row = vec![
+            self.throughput.to_string(),
+            self.block_time.to_string(),
+            self.total_txs.to_string(),
+            self.total_blocks.to_string(),
+            self.elapsed_secs.to_string(),
+            self.latency.p50_ms.to_string(),
+            self.latency.p95_ms.to_string(),
+            self.latency.p99_ms.to_string(),
+        ];
+        for (index, count) in self.minted_blocks.iter().enumerate() {
+            header.push(format!("minted_blocks_{index}"));
+            row.push(count.to_string());
+        }
+        writeln!(out, "{}", header.join(","))?;
+        writeln!(out, "{}", row.join(","))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn synthetic_stats() -> BenchStats {
+        BenchStats {
+            throughput: 12.5,
+            block_time: 0.75,
+            total_txs: 250,
+            total_blocks: 15,
+            elapsed_secs: 20.0,
+            minted_blocks: vec![4, 6, 4],
+            latency: LatencyPercentiles {
+                p50_ms: 500.0,
+                p95_ms: 950.0,
+                p99_ms: 990.0,
+            },
+        }
+    }
+
+    #[test]
+    fn bench_data() {
+        let data = "id0 Hello!\n\nid12 Multiple words, ÜTF-8 too\r\n   \nid3 \n";
+        let messages = parse_bench_data(data).unwrap();
+        let message = |peer_index, message: &str| BenchMessage {
+            peer_index,
+            message: message.into(),
+        };
+        assert_eq!(
+            messages,
+            [
+                message(0, "Hello!"),
+                message(12, "Multiple words, ÜTF-8 too"),
+                message(3, ""),
+            ]
+        );
+        assert_eq!(parse_bench_data("").unwrap(), []);
+
+        for malformed in [
+            "id1",
+            "i",
+            "idx hello",
+            "1 hello",
+            "ïd1 hello",
+            "id-1 hello",
+        ] {
+            let data = format!("id0 Hello!\n\n{malformed}\n");
+            let err = parse_bench_data(&data).unwrap_err();
+            assert!(err.starts_with("line 3:"), "{err}");
+        }
+    }
+
+    #[test]
+    fn expected_message_count() {
+        let data_dir = std::env::temp_dir().join(format!("bench-data-{}", std::process::id()));
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let files = [
+            "id0 Hi\nid1 Hello\nid2 Too far\n",
+            "id0 Hey\n\nid1 Ho\n",
+            "id0 Not a node\n",
+        ];
+        for (index, data) in files.iter().enumerate() {
+            std::fs::write(data_path(&data_dir, index), data).unwrap();
+        }
+
+        let two_nodes = expected_messages(&data_dir, 2);
+        let three_nodes = expected_messages(&data_dir, 3);
+        let four_nodes = expected_messages(&data_dir, 4);
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        assert_eq!(two_nodes, Ok(4));
+        assert_eq!(three_nodes, Ok(6));
+        // The data of the fourth node is missing
+        assert!(four_nodes.unwrap_err().contains("trans3.txt"));
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        // Transactions submitted at 0ms and included at 10ms, 20ms, ..., 1000ms
+        let latencies: Vec<_> = (1..=100)
+            .rev()
+            .map(|i| Duration::from_millis(i * 10))
+            .collect();
+        let percentiles = LatencyPercentiles::from_latencies(&latencies);
+        assert_eq!(percentiles.p50_ms, 500.0);
+        assert_eq!(percentiles.p95_ms, 950.0);
+        assert_eq!(percentiles.p99_ms, 990.0);
+
+        let single = LatencyPercentiles::from_latencies(&[Duration::from_millis(7)]);
+        assert_eq!(single.p50_ms, 7.0);
+        assert_eq!(single.p99_ms, 7.0);
+
+        assert_eq!(
+            LatencyPercentiles::from_latencies(&[]),
+            LatencyPercentiles::default()
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let stats = synthetic_stats();
+        let path = std::env::temp_dir().join(format!("bench-{}.json", std::process::id()));
+        stats.write_to_file(&path, StatsFormat::Json).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let parsed: BenchStats = serde_json::from_str(&contents).unwrap();
+        assert_eq!(parsed, stats);
+    }
+
+    #[test]
+    fn csv_output() {
+        let stats = synthetic_stats();
+        let path = std::env::temp_dir().join(format!("bench-{}.csv", std::process::id()));
+        stats.write_to_file(&path, StatsFormat::Csv).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut lines = contents.lines();
+        let header: Vec<_> = lines.next().unwrap().split(',').collect();
+        let row: Vec<_> = lines.next().unwrap().split(',').collect();
+        assert_eq!(lines.next(), None);
+        assert_eq!(header.len(), row.len());
+
+        let column = |name: &str| row[header.iter().position(|h| *h == name).unwrap()];
+        assert_eq!(column("throughput").parse::<f64>().unwrap(), 12.5);
+        assert_eq!(column("total_txs").parse::<usize>().unwrap(), 250);
+        assert_eq!(column("elapsed_secs").parse::<f64>().unwrap(), 20.0);
+        assert_eq!(column("minted_blocks_1").parse::<usize>().unwrap(), 6);
+        assert_eq!(column("latency_p95_ms").parse::<f64>().unwrap(), 950.0);
+    }
+}
@@ -1,13 +1,19 @@
-use std::collections::HashMap;
-use std::net::{IpAddr, SocketAddr};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
 use clap::Parser;
+use tracing_subscriber::filter::LevelFilter;
 
+use blockchat::bench::{self, BenchStats, LatencyPercentiles, StatsFormat};
 use blockchat::bootstrap::{self, BootstrapConfig};
 use blockchat::crypto::{self, Address};
+use blockchat::error::Error;
+use blockchat::logging::{self, LogFormat};
+use blockchat::network::broadcast::ConnectionRetries;
 use blockchat::network::Network;
+use blockchat::node::Node;
+use blockchat::wallet::TransactionKind;
 
 /// A node for the BlockChat blockchain network.
 #[derive(Parser, Debug)]
@@ -20,27 +26,47 @@ struct Args {
     /// The number of expected peers in the network.
     #[arg(long)]
     peers: usize,
-    /// The address of the bootstrap server.
+    /// The address of the bootstrap server as `host:port`. The host can be a hostname, an IPv4
+    /// address or a bracketed IPv6 address.
     #[arg(long, default_value = "127.0.0.1:7000")]
-    bootstrap_addr: SocketAddr,
-    /// The IP address to bind to.
-    #[arg(long, default_value = "127.0.0.1")]
-    listen_ip: IpAddr,
+    bootstrap_addr: String,
+    /// The hostname or IP address to bind to.
+    #[arg(long, alias = "listen-ip", default_value = "127.0.0.1")]
+    listen_host: String,
     /// The maximum block capacity.
     #[arg(long, default_value = "5")]
     block_capacity: usize,
+    /// The time in milliseconds after which a validator mints a block even if it isn't full.
+    #[arg(long, default_value = "1000")]
+    block_time_ms: u64,
     /// The path of the benchmark data. Should contain the trans<id>.txt files.
     #[arg(long)]
     bench_data: PathBuf,
+    /// The number of benchmark messages sent by all the nodes, after which the benchmark ends.
+    /// Defaults to the messages of the data files of the nodes that are addressed to a node.
+    #[arg(long)]
+    expected_messages: Option<usize>,
     /// The stake amount this node should use.
     #[arg(long, default_value = "10")]
     stake: u64,
+    /// A file to write the results to, in addition to printing them.
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// The format of the `--output` file. One of `json` or `csv`.
+    #[arg(long, default_value = "json")]
+    format: StatsFormat,
+    /// The minimum level of the logged events. One of `trace`, `debug`, `info`, `warn`, `error` or
+    /// `off`.
+    #[arg(long, default_value = "info")]
+    log_level: LevelFilter,
+    /// The format of the logs. One of `text` or `json`.
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
 }
 
 fn main() {
-    tracing_subscriber::fmt::init();
-
     let args = Args::parse();
+    logging::init(args.log_level, args.log_format);
 
     let (private_key, public_key) = crypto::generate_keypair();
     let config = BootstrapConfig {
@@ -48,24 +74,47 @@ fn main() {
         capacity: args.block_capacity,
         peers: args.peers,
         bootstrap_addr: args.bootstrap_addr,
-        listen_ip: args.listen_ip,
+        listen_host: args.listen_host,
         public_key,
         private_key,
         // Give more initial funds so that the network can run through the required number of
         // transactions.
         genesis_funds_per_node: 10_000,
+        genesis_funds: BTreeMap::new(),
+        genesis_validator: None,
+        genesis: None,
+        mint_interval: Duration::from_millis(args.block_time_ms),
+        tls: None,
+        connection_retries: ConnectionRetries::default(),
     };
 
-    let (mut node, mut network, my_index, peers) = bootstrap::bootstrap(config);
+    let (mut node, mut network, my_index, peers) = match bootstrap::bootstrap(config) {
+        Ok(bootstrapped) => bootstrapped,
+        Err(err) => {
+            eprintln!("Bootstrap failed: {err}");
+            eprintln!("Hint: {}", err.hint());
+            std::process::exit(1);
+        }
+    };
 
-    let data_path = args.bench_data.join(format!("trans{my_index}.txt"));
-    let bench_data = std::fs::read_to_string(data_path).unwrap();
+    let expected_messages = match args.expected_messages {
+        Some(count) => count,
+        None => bench::expected_messages(&args.bench_data, args.peers).unwrap_or_else(|err| {
+            eprintln!("invalid benchmark data {err}");
+            std::process::exit(1);
+        }),
+    };
+    let data_path = bench::data_path(&args.bench_data, my_index);
+    let bench_data = std::fs::read_to_string(&data_path).unwrap();
+    let bench_messages = bench::parse_bench_data(&bench_data).unwrap_or_else(|err| {
+        eprintln!("invalid benchmark data {}: {err}", data_path.display());
+        std::process::exit(1);
+    });
     let mut messages = vec![];
-    for line in bench_data.lines() {
-        let peer_id = line[2..3].parse::<usize>().unwrap();
-        if let Some(info) = peers.get(peer_id) {
+    for bench_message in bench_messages {
+        if let Some(info) = peers.get(bench_message.peer_index) {
             let addr = Address::from_public_key(&info.public_key);
-            messages.push((addr, line[4..].to_owned()));
+            messages.push((addr, bench_message.message));
         }
     }
 
@@ -80,47 +129,96 @@ fn main() {
     println!("Setting up stake");
     let tx = node.wallet().create_stake_tx(args.stake);
     let signed_tx = node.sign_transaction(tx);
-    node.wallet_mut().apply_tx(signed_tx.clone()).unwrap();
-    node.broadcast_transaction(signed_tx.clone());
-
-    while node.total_transactions() != (2 * args.peers) {
+    node.send_transaction(signed_tx).unwrap();
+
+    // Wait until every node has staked so that all of them take part in the validator election
+    let stakers = |node: &Node| {
+        node.blockchain()
+            .iter()
+            .flat_map(|block| &block.data.transactions)
+            .filter(|tx| matches!(tx.data.kind, TransactionKind::Stake(_)))
+            .map(|tx| &tx.data.sender_address)
+            .collect::<HashSet<_>>()
+            .len()
+    };
+    while stakers(&node) < args.peers {
         node.step(&mut network);
         network.await_events(Some(Duration::from_millis(15)));
     }
 
     let start = Instant::now();
 
+    // The submission time of each transaction of this node that is not yet in a block
+    let mut submitted = HashMap::new();
     for (recipient, message) in messages {
         let tx = node.wallet().create_message_tx(recipient, message);
         let signed_tx = node.sign_transaction(tx);
-        node.wallet_mut().apply_tx(signed_tx.clone()).unwrap();
-        node.broadcast_transaction(signed_tx.clone());
+        // Keep running the node while its mempool is full so that blocks make room
+        while let Err(err) = node.send_transaction(signed_tx.clone()) {
+            assert!(matches!(err, Error::MempoolFull), "{err}");
+            node.step(&mut network);
+            network.await_events(Some(Duration::from_millis(15)));
+        }
+        submitted.insert(signed_tx.hash, Instant::now());
     }
 
-    while node.total_transactions() != (2 * args.peers + 240) {
+    let mut latencies = vec![];
+    let mut scanned_blocks = node.blockchain().len();
+    let mut confirmed_messages = 0;
+    while confirmed_messages < expected_messages {
         node.step(&mut network);
+        for block in &node.blockchain()[scanned_blocks..] {
+            for tx in block.data.transactions.iter() {
+                if matches!(tx.data.kind, TransactionKind::Message(_, _)) {
+                    confirmed_messages += 1;
+                }
+                if let Some(submitted_at) = submitted.remove(&tx.hash) {
+                    latencies.push(submitted_at.elapsed());
+                }
+            }
+        }
+        scanned_blocks = node.blockchain().len();
         network.await_events(Some(Duration::from_millis(15)));
     }
 
-    let mut block_counts = HashMap::new();
-    for block in node.blockchain() {
-        *block_counts.entry(block.public_key.clone()).or_insert(0) += 1;
-    }
-    for (i, (_, count)) in block_counts.into_iter().enumerate() {
+    let minted_blocks: Vec<usize> = peers
+        .iter()
+        .map(|peer| {
+            let validator = Address::from_public_key(&peer.public_key);
+            node.blocks_by_validator(&validator).len()
+        })
+        .collect();
+    for (i, count) in minted_blocks.iter().enumerate() {
         println!("Node {i} minted {count} blocks");
     }
 
-    let total_blocks = node.blockchain().len() as f64;
-    let total_txs: usize = node
-        .blockchain()
-        .iter()
-        .map(|block| block.data.transactions.len())
-        .sum();
-    let total_txs = total_txs as f64;
+    let total_blocks = node.blockchain().len();
+    let total_txs = node.total_transactions();
     let total_time = (start.elapsed().as_millis() as f64) / 1000.0;
-    let throughput = total_txs / total_time;
-    let block_time = total_blocks / total_time;
+    let throughput = total_txs as f64 / total_time;
+    let block_time = total_blocks as f64 / total_time;
 
     println!("Throughput {throughput}tx/s");
     println!("Block time {block_time}blocks/s");
+
+    let latency = LatencyPercentiles::from_latencies(&latencies);
+    println!(
+        "Latency p50 {}ms p95 {}ms p99 {}ms",
+        latency.p50_ms, latency.p95_ms, latency.p99_ms
+    );
+
+    if let Some(path) = args.output {
+        let stats = BenchStats {
+            throughput,
+            block_time,
+            total_txs,
+            total_blocks,
+            elapsed_secs: total_time,
+            minted_blocks,
+            latency,
+        };
+        stats
+            .write_to_file(&path, args.format)
+            .expect("failed to write the benchmark results");
+    }
 }
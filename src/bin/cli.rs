@@ -1,10 +1,14 @@
+use std::path::PathBuf;
+
 use clap::Parser;
 use reqwest::Url;
 use rustyline::error::ReadlineError;
 use rustyline::{DefaultEditor, Result};
 
-use blockchat::cli::client::BlockchatClient;
-use blockchat::cli::command::Command;
+use blockchat::cli::address_book::AddressBook;
+use blockchat::cli::client::{BlockchatClient, RetryPolicy};
+use blockchat::cli::command::{Command, OutputFormat};
+use blockchat::cli::history;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -12,16 +16,81 @@ struct Args {
     /// The URL of the RPC node.
     #[arg(long)]
     rpc_url: Url,
+    /// Print the results of the commands as JSON.
+    #[arg(long)]
+    json: bool,
+    /// A command to run non-interactively. Can be passed multiple times to run several commands
+    /// in order.
+    #[arg(long = "command", short = 'c')]
+    commands: Vec<String>,
+    /// A file with commands to run non-interactively, one per line. Runs after any `--command`.
+    #[arg(long)]
+    script: Option<PathBuf>,
+    /// The file used to persist the command history across sessions. Defaults to
+    /// `~/.blockchat_history`.
+    #[arg(long)]
+    history_file: Option<PathBuf>,
+    /// A JSON file with recipient aliases. Aliases defined with the `alias` command are saved to
+    /// it.
+    #[arg(long)]
+    address_book: Option<PathBuf>,
+    /// The number of attempts made for requests that only read the state of the node before
+    /// giving up.
+    #[arg(long, default_value_t = RetryPolicy::default().attempts)]
+    attempts: u32,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    let format = if args.json {
+        OutputFormat::Json
+    } else {
+        OutputFormat::Pretty
+    };
+
+    let retry_policy = RetryPolicy {
+        attempts: args.attempts.max(1),
+        ..RetryPolicy::default()
+    };
+
+    let mut address_book = match &args.address_book {
+        Some(path) => AddressBook::from_file(path)?,
+        None => AddressBook::new(),
+    };
+
+    if !args.commands.is_empty() || args.script.is_some() {
+        let client = BlockchatClient::new(args.rpc_url).with_retry_policy(retry_policy);
+        let script = match args.script {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => String::new(),
+        };
+        let commands = args
+            .commands
+            .iter()
+            .map(String::as_str)
+            .chain(Command::script_lines(&script));
+        let mut stdout = std::io::stdout();
+        let failures =
+            Command::run_all(commands, client, &mut address_book, format, &mut stdout).await;
+        if failures > 0 {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     println!("Using RPC at {}", args.rpc_url);
-    let client = BlockchatClient::new(args.rpc_url);
+    let client = BlockchatClient::new(args.rpc_url).with_retry_policy(retry_policy);
+
+    let history_path = args.history_file.or_else(history::default_history_path);
 
     let mut rl = DefaultEditor::new()?;
+    if let Some(path) = &history_path {
+        if let Err(err) = history::load_history(rl.history_mut(), path) {
+            println!("Failed to load history from {}: {err}", path.display());
+        }
+    }
     loop {
         let readline = rl.readline("blockchat> ");
         match readline {
@@ -29,7 +98,13 @@ async fn main() -> Result<()> {
                 rl.add_history_entry(line.as_str()).unwrap();
                 match line.parse::<Command>() {
                     Ok(cmd) => {
-                        cmd.run(client.clone()).await;
+                        let mut stdout = std::io::stdout();
+                        if let Err(err) = cmd
+                            .run_with_output(client.clone(), &mut address_book, format, &mut stdout)
+                            .await
+                        {
+                            println!("Error: {err}");
+                        }
                     }
                     Err(err) => println!("Error: {err:?}"),
                 }
@@ -48,5 +123,10 @@ async fn main() -> Result<()> {
             }
         }
     }
+    if let Some(path) = &history_path {
+        if let Err(err) = history::save_history(rl.history_mut(), path) {
+            println!("Failed to save history to {}: {err}", path.display());
+        }
+    }
     Ok(())
 }
@@ -1,21 +1,22 @@
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
-use axum::extract::State;
-use axum::http::StatusCode;
-use axum::routing::{get, post};
-use axum::{Json, Router};
 use clap::Parser;
 use tokio::net::TcpListener;
+use tracing_subscriber::filter::LevelFilter;
 
+use blockchat::api::{self, ApiState, Readiness};
 use blockchat::bootstrap::{self, BootstrapConfig};
-use blockchat::cli::client::{CreateTransactionRequest, SetStakeRequest};
-use blockchat::crypto;
-use blockchat::crypto::Signed;
-use blockchat::network::Network;
-use blockchat::node::{Block, Node};
-use blockchat::wallet::{Transaction, Wallet};
+use blockchat::crypto::{self, SignatureScheme};
+use blockchat::error::BootstrapError;
+use blockchat::genesis::GenesisConfig;
+use blockchat::logging::{self, LogFormat};
+use blockchat::network::broadcast::ConnectionRetries;
+use blockchat::network::tls::TlsConfig;
+use blockchat::node::handle::NodeHandle;
 
 /// A node for the BlockChat blockchain network.
 #[derive(Parser, Debug)]
@@ -28,56 +29,139 @@ struct Args {
     /// The number of expected peers in the network.
     #[arg(long)]
     peers: usize,
-    /// The address of the bootstrap server.
+    /// The address of the bootstrap server as `host:port`. The host can be a hostname, an IPv4
+    /// address or a bracketed IPv6 address.
     #[arg(long, default_value = "127.0.0.1:7000")]
-    bootstrap_addr: SocketAddr,
-    /// The IP address to bind to.
-    #[arg(long, default_value = "127.0.0.1")]
-    listen_ip: IpAddr,
+    bootstrap_addr: String,
+    /// The hostname or IP address to bind to.
+    #[arg(long, alias = "listen-ip", default_value = "127.0.0.1")]
+    listen_host: String,
     /// The base port for the HTTP API. Each node will start its HTTP server on
     /// `localhost:(api_base_port + node_index)`.
     #[arg(long, default_value = "10000")]
     api_base_port: u16,
-    /// The maximum block capacity.
+    /// The maximum block capacity. All the nodes must agree on it since they reject larger blocks,
+    /// so it is ignored when `--genesis` provides one.
     #[arg(long, default_value = "5")]
     block_capacity: usize,
+    /// The time in milliseconds after which a validator mints a block even if it isn't full.
+    #[arg(long, default_value = "1000")]
+    block_time_ms: u64,
+    /// The path of a JSON file describing the genesis block. When omitted the bootstrap leader
+    /// becomes the genesis validator and distributes the initial funds to all the peers.
+    #[arg(long)]
+    genesis: Option<PathBuf>,
+    /// Only follow the blockchain without ever minting blocks or staking, e.g. to serve a block
+    /// explorer. An observer must not be the genesis validator.
+    #[arg(long)]
+    observer: bool,
+    /// The amount this node stakes once it receives its genesis funds, so that it takes part in
+    /// the validator election without a manual `POST /stake`.
+    #[arg(long, conflicts_with = "observer")]
+    initial_stake: Option<u64>,
+    /// Acknowledge the messages sent to this node with a signed receipt for their senders.
+    #[arg(long)]
+    send_acks: bool,
+    /// The signature scheme of this node's keypair. One of `rsa` or `ed25519`.
+    #[arg(long, default_value = "rsa")]
+    signature_scheme: SignatureScheme,
+    /// A PEM file with the certificate this node presents to its peers. Enables TLS for the peer
+    /// connections.
+    #[arg(long, requires_all = ["tls_key", "tls_ca"])]
+    tls_cert: Option<PathBuf>,
+    /// A PEM file with the private key of `--tls-cert`.
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+    /// A PEM file with the certificate authority that issued the certificates of all the peers.
+    #[arg(long, requires = "tls_cert")]
+    tls_ca: Option<PathBuf>,
+    /// The name the certificates of all the peers are issued for.
+    #[arg(long, default_value = "blockchat")]
+    tls_server_name: String,
+    /// The number of attempts at connecting to each peer before giving up.
+    #[arg(long, default_value = "10")]
+    connect_attempts: u32,
+    /// The delay in milliseconds after the first failed attempt at connecting to a peer. It
+    /// doubles after every subsequent failure, up to `--connect-max-backoff-ms`.
+    #[arg(long, default_value = "200")]
+    connect_backoff_ms: u64,
+    /// The longest delay in milliseconds between two attempts at connecting to a peer.
+    #[arg(long, default_value = "5000")]
+    connect_max_backoff_ms: u64,
+    /// The minimum level of the logged events. One of `trace`, `debug`, `info`, `warn`, `error` or
+    /// `off`.
+    #[arg(long, default_value = "info")]
+    log_level: LevelFilter,
+    /// The format of the logs. One of `text` or `json`.
+    #[arg(long, default_value = "text")]
+    log_format: LogFormat,
 }
 
 #[tokio::main]
 async fn main() {
-    tracing_subscriber::fmt::init();
-
     let args = Args::parse();
+    logging::init(args.log_level, args.log_format);
+
+    let genesis = args.genesis.map(|path| {
+        let genesis = GenesisConfig::from_file(&path).unwrap_or_else(|err| {
+            eprintln!("Failed to load the genesis file {}: {err}", path.display());
+            std::process::exit(1);
+        });
+        if let Err(err) = genesis.validate() {
+            eprintln!("The genesis file {} is invalid: {err}", path.display());
+            std::process::exit(1);
+        }
+        genesis
+    });
+
+    let tls = args.tls_cert.map(|cert_path| TlsConfig {
+        cert_path,
+        key_path: args.tls_key.unwrap(),
+        ca_path: args.tls_ca.unwrap(),
+        server_name: args.tls_server_name,
+    });
 
-    let (private_key, public_key) = crypto::generate_keypair();
+    let (private_key, public_key) = crypto::generate_keypair_with(args.signature_scheme);
     let config = BootstrapConfig {
         bootstrap_leader: args.bootstrap_leader,
         capacity: args.block_capacity,
         peers: args.peers,
         bootstrap_addr: args.bootstrap_addr,
-        listen_ip: args.listen_ip,
+        listen_host: args.listen_host,
         public_key,
         private_key,
         genesis_funds_per_node: 1000,
+        genesis_funds: BTreeMap::new(),
+        genesis_validator: None,
+        genesis,
+        mint_interval: Duration::from_millis(args.block_time_ms),
+        tls,
+        connection_retries: ConnectionRetries {
+            attempts: args.connect_attempts,
+            initial_backoff: Duration::from_millis(args.connect_backoff_ms),
+            max_backoff: Duration::from_millis(args.connect_max_backoff_ms),
+        },
     };
 
-    let (node, mut network, my_index, _) = bootstrap::bootstrap(config);
+    let discovery = tokio::task::spawn_blocking(|| bootstrap::discover(config));
+    let discovery = match discovery.await.unwrap() {
+        Ok(discovery) => discovery,
+        Err(err) => exit_bootstrap_failed(err),
+    };
+    let my_index = discovery.index();
 
-    let shared_node = Arc::new(Mutex::new(node));
-    // Start a thread that will run the node
-    let node = Arc::clone(&shared_node);
-    std::thread::spawn(move || loop {
-        let _ = { node.lock().unwrap().step(&mut network) };
-        network.await_events(Some(Duration::from_millis(15)));
+    // The port of the API depends on the index assigned during discovery, so the API is served as
+    // soon as it is known. Connecting to the peers and distributing the funds can take a while,
+    // and serving the API meanwhile lets probes tell a bootstrapping node from a dead one.
+    let (handle, deferred_node) = NodeHandle::deferred();
+    let ready = Readiness::default();
+    let app = api::router(ApiState {
+        node: handle,
+        node_index: my_index,
+        peers: Arc::new(discovery.peers().to_vec()),
+        ready: ready.clone(),
     });
 
-    let app = Router::new()
-        .route("/block", get(get_block))
-        .route("/balance", get(get_balance))
-        .route("/stake", post(set_stake))
-        .route("/transaction", post(create_transaction))
-        .with_state(shared_node);
-
     let api_port = args.api_base_port + u16::try_from(my_index).unwrap();
     let listener = TcpListener::bind((Ipv4Addr::new(127, 0, 0, 1), api_port))
         .await
@@ -87,45 +171,29 @@ async fn main() {
         "Node HTTP API listening on {}",
         listener.local_addr().unwrap()
     );
-    axum::serve(listener, app).await.unwrap();
-}
+    let server = tokio::spawn(async move { axum::serve(listener, app).await });
 
-async fn get_block(State(node): State<Arc<Mutex<Node>>>) -> Json<Signed<Block>> {
-    Json(node.lock().unwrap().blockchain().last().cloned().unwrap())
-}
+    let bootstrapped = tokio::task::spawn_blocking(|| discovery.connect());
+    let (mut node, network, _, _) = match bootstrapped.await.unwrap() {
+        Ok(bootstrapped) => bootstrapped,
+        Err(err) => exit_bootstrap_failed(err),
+    };
+    node.set_observer(args.observer);
+    node.set_send_acks(args.send_acks);
+    if let Some(amount) = args.initial_stake {
+        node.set_initial_stake(amount);
+    }
 
-async fn get_balance(State(node): State<Arc<Mutex<Node>>>) -> Json<Wallet> {
-    Json(node.lock().unwrap().wallet().clone())
-}
+    // The node runs on its own thread and serves the HTTP handlers between steps
+    let connections = network.connections();
+    deferred_node.start_with_network(node, network);
+    ready.set_bootstrapped(connections);
 
-async fn create_transaction(
-    State(node): State<Arc<Mutex<Node>>>,
-    Json(req): Json<CreateTransactionRequest>,
-) -> (StatusCode, Json<Signed<Transaction>>) {
-    let mut node = node.lock().unwrap();
-    let wallet = node.wallet();
-    let tx = match req {
-        CreateTransactionRequest::Coin { recipient, amount } => {
-            wallet.create_coin_tx(recipient, amount)
-        }
-        CreateTransactionRequest::Message { recipient, message } => {
-            wallet.create_message_tx(recipient, message)
-        }
-    };
-    let signed_tx = node.sign_transaction(tx);
-    node.wallet_mut().apply_tx(signed_tx.clone()).unwrap();
-    node.broadcast_transaction(signed_tx.clone());
-    (StatusCode::CREATED, Json(signed_tx))
+    server.await.unwrap().unwrap();
 }
 
-async fn set_stake(
-    State(node): State<Arc<Mutex<Node>>>,
-    Json(req): Json<SetStakeRequest>,
-) -> (StatusCode, Json<Signed<Transaction>>) {
-    let mut node = node.lock().unwrap();
-    let tx = node.wallet().create_stake_tx(req.amount);
-    let signed_tx = node.sign_transaction(tx);
-    node.wallet_mut().apply_tx(signed_tx.clone()).unwrap();
-    node.broadcast_transaction(signed_tx.clone());
-    (StatusCode::CREATED, Json(signed_tx))
+fn exit_bootstrap_failed(err: BootstrapError) -> ! {
+    eprintln!("Bootstrap failed: {err}");
+    eprintln!("Hint: {}", err.hint());
+    std::process::exit(1);
 }
@@ -1,35 +1,77 @@
 //! Routines for bootstrapping a blockchat network of a given configuration.
 
-use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
 use crate::crypto::{Address, PrivateKey, PublicKey};
-use crate::network::broadcast::Broadcaster;
-use crate::network::discovery::{bootstrap_helper, discover_peers};
-use crate::node::{Message, Node};
+use crate::error::{BootstrapError, Error};
+use crate::genesis::GenesisConfig;
+use crate::network::broadcast::{Broadcaster, ConnectionRetries, ConnectionTimeouts};
+use crate::network::discovery::{bootstrap_helper, connect_to_bootstrap, exchange_peer_data};
+use crate::network::tls::{TlsConfig, TlsContext};
+use crate::network::Network;
+use crate::node::{Message, Node, TxStatus};
 
 pub struct BootstrapConfig {
     /// Whether this node is responsible for running the bootstrap helper
     pub bootstrap_leader: bool,
-    /// The capacity per block.
+    /// The capacity per block. It is a consensus parameter that must be the same on every peer,
+    /// since blocks with more transactions are rejected.
     pub capacity: usize,
     // The number of expected nodes in the system.
     pub peers: usize,
-    /// The socket address of the bootstrap helper.
-    pub bootstrap_addr: SocketAddr,
-    /// The socket address this node should listen to.
-    pub listen_ip: IpAddr,
+    /// The address of the bootstrap helper as `host:port`. The host can be a hostname, an IPv4
+    /// address or a bracketed IPv6 address.
+    pub bootstrap_addr: String,
+    /// The hostname or IP address this node should listen to.
+    pub listen_host: String,
     /// The public_key of this node.
     pub public_key: PublicKey,
     /// The private key of this node.
     pub private_key: PrivateKey,
     /// The amount of BCC that each node gets after bootstrap
     pub genesis_funds_per_node: u64,
+    /// The amount of BCC that specific nodes get after bootstrap instead of
+    /// `genesis_funds_per_node`. Every node must be configured with the same amounts since they
+    /// determine the genesis block.
+    pub genesis_funds: BTreeMap<Address, u64>,
+    /// The address of the genesis validator, which receives the initial funds and distributes
+    /// them to the peers. Only used by the bootstrap leader, which becomes the genesis validator
+    /// itself when unset. The address must belong to one of the peers.
+    pub genesis_validator: Option<Address>,
+    /// An explicit genesis configuration. When set, the blockchain starts from the described
+    /// genesis block and the bootstrap leader does not distribute any funds. The block capacity
+    /// of the genesis configuration takes precedence over `capacity`.
+    pub genesis: Option<GenesisConfig>,
+    /// The time after which a validator mints a block even if it isn't full. The read timeout of
+    /// the connections to the peers is extended to cover a few of these intervals.
+    pub mint_interval: Duration,
+    /// The certificates used to encrypt the connections to the peers. The connections are
+    /// plaintext when unset.
+    pub tls: Option<TlsConfig>,
+    /// How persistently to retry connecting to the peers that haven't started listening yet.
+    pub connection_retries: ConnectionRetries,
+}
+
+/// The number of block intervals a peer may stay silent before its connection times out.
+const SILENT_BLOCKS: u32 = 5;
+
+/// The timeouts of the connections to the peers. Peers that aren't minting may only send a
+/// message every few blocks, so the read timeout is never shorter than a few mint intervals.
+fn peer_timeouts(mint_interval: Duration) -> ConnectionTimeouts {
+    let timeouts = ConnectionTimeouts::default();
+    let silence = mint_interval.saturating_mul(SILENT_BLOCKS);
+    ConnectionTimeouts {
+        read: timeouts.read.map(|read| read.max(silence)),
+        ..timeouts
+    }
 }
 
 /// The peer info exchanged during discovery.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PeerInfo {
     /// The socket address the peer will listen on.
     pub listen_addr: SocketAddr,
@@ -37,72 +79,313 @@ pub struct PeerInfo {
     pub public_key: PublicKey,
 }
 
-pub fn bootstrap(config: BootstrapConfig) -> (Node, Broadcaster<Message>, usize, Vec<PeerInfo>) {
+/// Returns the index assigned during bootstrap to the peer with the given public key.
+pub fn peer_index_for(peers: &[PeerInfo], public_key: &PublicKey) -> Option<usize> {
+    peers.iter().position(|info| &info.public_key == public_key)
+}
+
+/// Discovers the peers through the bootstrap server, connects to all of them and constructs the
+/// node from the agreed genesis block. The bootstrap leader also distributes the genesis funds
+/// before returning. Returns the node, its network, its index and the info of all the peers.
+pub fn bootstrap(
+    config: BootstrapConfig,
+) -> Result<(Node, Broadcaster<Message>, usize, Vec<PeerInfo>), BootstrapError> {
+    discover(config)?.connect()
+}
+
+/// The peers discovered through the bootstrap server, before connecting to them.
+pub struct Discovery {
+    config: BootstrapConfig,
+    listener: TcpListener,
+    tls: Option<TlsContext>,
+    my_index: usize,
+    peer_infos: Vec<PeerInfo>,
+    genesis_validator: Address,
+}
+
+/// The first phase of `bootstrap`, which discovers the peers and assigns this node its index.
+/// Finishing the bootstrap with `Discovery::connect` can take much longer since it waits for all
+/// the peers to connect and for the genesis funds to be distributed.
+pub fn discover(config: BootstrapConfig) -> Result<Discovery, BootstrapError> {
+    // A peer that stops responding must not stall the discovery of the others
+    let timeouts = ConnectionTimeouts::default();
     if config.bootstrap_leader {
-        let genesis_validator = config.public_key.clone();
+        let genesis_validator = config
+            .genesis_validator
+            .clone()
+            .unwrap_or_else(|| Address::from_public_key(&config.public_key));
+        let bootstrap_addr = config.bootstrap_addr.clone();
         std::thread::spawn(move || {
-            bootstrap_helper::<PeerInfo, _>(config.bootstrap_addr, config.peers, genesis_validator)
+            let result = bootstrap_helper::<_, PeerInfo, _>(
+                &*bootstrap_addr,
+                config.peers,
+                genesis_validator,
+                timeouts,
+            );
+            if let Err(err) = result {
+                log::error!("Bootstrap helper failed: {err}");
+            }
         });
     }
 
-    let listener = TcpListener::bind((config.listen_ip, 0)).unwrap();
+    let listen_error = |err| BootstrapError::Listen(config.listen_host.clone(), err);
+    let listener = TcpListener::bind((&*config.listen_host, 0)).map_err(listen_error)?;
+    let tls = config.tls.as_ref().map(TlsConfig::load).transpose();
+    let tls = tls.map_err(BootstrapError::Tls)?;
 
     let peer_info = PeerInfo {
-        listen_addr: listener.local_addr().unwrap(),
+        listen_addr: listener.local_addr().map_err(listen_error)?,
         public_key: config.public_key.clone(),
     };
+    let retries = config.connection_retries;
+    let socket = connect_to_bootstrap(&*config.bootstrap_addr, retries)
+        .map_err(|err| BootstrapError::BootstrapUnreachable(config.bootstrap_addr.clone(), err))?;
     let (my_index, peer_infos, genesis_validator) =
-        discover_peers::<PeerInfo, PublicKey>(config.bootstrap_addr, peer_info);
+        exchange_peer_data::<PeerInfo, Address>(socket, peer_info, timeouts)
+            .map_err(BootstrapError::Discovery)?;
+    if peer_infos.len() != config.peers {
+        return Err(BootstrapError::PeerCount(config.peers, peer_infos.len()));
+    }
+    let validator_is_peer = peer_infos
+        .iter()
+        .any(|info| Address::from_public_key(&info.public_key) == genesis_validator);
+    if !validator_is_peer {
+        return Err(BootstrapError::UnknownGenesisValidator(genesis_validator));
+    }
+    Ok(Discovery {
+        config,
+        listener,
+        tls,
+        my_index,
+        peer_infos,
+        genesis_validator,
+    })
+}
 
-    let peer_addrs: Vec<_> = peer_infos.iter().map(|info| info.listen_addr).collect();
-    let mut network = Broadcaster::<Message>::new(listener, &peer_addrs, my_index);
+impl Discovery {
+    /// The index assigned to this node.
+    pub fn index(&self) -> usize {
+        self.my_index
+    }
 
-    let genesis_funds = config.genesis_funds_per_node * (config.peers as u64);
+    /// The info of all the peers, ordered by their index.
+    pub fn peers(&self) -> &[PeerInfo] {
+        &self.peer_infos
+    }
 
-    let mut node = Node::new(
-        format!("node-{my_index}"),
-        config.public_key,
-        config.private_key.clone(),
-        genesis_validator.clone(),
-        genesis_funds,
-        config.capacity,
-    );
+    /// Connects to all the discovered peers and constructs the node, see `bootstrap`.
+    pub fn connect(
+        self,
+    ) -> Result<(Node, Broadcaster<Message>, usize, Vec<PeerInfo>), BootstrapError> {
+        let Discovery {
+            config,
+            listener,
+            tls,
+            my_index,
+            peer_infos,
+            genesis_validator,
+        } = self;
+        let retries = config.connection_retries;
 
-    if config.bootstrap_leader {
-        for peer_info in peer_infos.iter() {
-            // No need to seed the genesis wallet.
-            if peer_info.public_key == genesis_validator {
-                continue;
+        let peer_addrs: Vec<_> = peer_infos.iter().map(|info| info.listen_addr).collect();
+        let timeouts = peer_timeouts(config.mint_interval);
+        let mut network =
+            Broadcaster::connect(listener, &peer_addrs, my_index, timeouts, retries, tls)
+                .map_err(BootstrapError::PeerConnection)?;
+
+        let funds_of = |peer_info: &PeerInfo| {
+            let address = Address::from_public_key(&peer_info.public_key);
+            let funds = config.genesis_funds.get(&address).copied();
+            funds.unwrap_or(config.genesis_funds_per_node)
+        };
+        let genesis_funds = peer_infos.iter().map(funds_of).sum();
+
+        let seed_genesis_funds = config.genesis.is_none()
+            && Address::from_public_key(&config.public_key) == genesis_validator;
+        let genesis = config.genesis.unwrap_or_else(|| {
+            GenesisConfig::new(genesis_validator.clone(), genesis_funds, config.capacity)
+        });
+        let mut node = Node::from_genesis(
+            format!("node-{my_index}"),
+            config.public_key,
+            config.private_key.clone(),
+            genesis,
+        )
+        .map_err(BootstrapError::Genesis)?;
+
+        node.set_mint_interval(config.mint_interval);
+
+        // The genesis validator pays the fees of the seeding transactions but, as the only staker, it
+        // also mints them and collects the same fees back. Every peer, including the validator, ends
+        // up with exactly its configured funds once all of them are confirmed.
+        if seed_genesis_funds {
+            let mut seeding_txs = vec![];
+            for peer_info in peer_infos.iter() {
+                // No need to seed the genesis wallet.
+                let address = Address::from_public_key(&peer_info.public_key);
+                if address == genesis_validator {
+                    continue;
+                }
+                let tx = node.wallet().create_coin_tx(address, funds_of(peer_info));
+                let signed_tx = node.sign_transaction(tx);
+                seeding_txs.push(signed_tx.hash.clone());
+                // With more peers than the mempool holds the leader mints the first ones to make room
+                loop {
+                    match node.send_transaction(signed_tx.clone()) {
+                        Ok(()) => break,
+                        Err(Error::MempoolFull) => {
+                            let timeout = node.step(&mut network);
+                            network.await_events(timeout);
+                        }
+                        Err(err) => panic!("known valid tx rejected: {err}"),
+                    }
+                }
+            }
+            // The seeding transactions may span several blocks if there are more peers than the
+            // capacity of a block, so the leader keeps minting until all of them are confirmed.
+            let is_confirmed = |node: &Node, hash| {
+                matches!(node.transaction_status(hash), TxStatus::Confirmed { .. })
+            };
+            loop {
+                let timeout = node.step(&mut network);
+                if seeding_txs.iter().all(|hash| is_confirmed(&node, hash)) {
+                    break;
+                }
+                network.await_events(timeout);
             }
-            let tx = node.wallet().create_coin_tx(
-                Address::from_public_key(&peer_info.public_key),
-                config.genesis_funds_per_node,
-            );
-            let signed_tx = node.sign_transaction(tx);
-            node.wallet_mut()
-                .apply_tx(signed_tx.clone())
-                .expect("known valid tx");
-            node.broadcast_transaction(signed_tx);
         }
-        node.step(&mut network);
-    }
 
-    (node, network, my_index, peer_infos)
+        Ok((node, network, my_index, peer_infos))
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use crate::api::{self, ApiState, Readiness};
+    use crate::cli::client::BlockchatClient;
     use crate::crypto;
-    use crate::network::Network;
+    use crate::network::broadcast::PeerConnections;
+    use crate::node::handle::NodeHandle;
+    use crate::node::DEFAULT_MINT_INTERVAL;
 
     use super::*;
 
+    /// Steps the node until `done` holds. What it waits for may have come with the last message of
+    /// the bootstrap, so it is checked before waiting for more events.
+    fn step_until(
+        node: &mut Node,
+        network: &mut impl Network<Message>,
+        done: impl Fn(&Node) -> bool,
+    ) {
+        loop {
+            let timeout = node.step(network);
+            if done(node) {
+                return;
+            }
+            network.await_events(timeout);
+        }
+    }
+
+    #[test]
+    fn read_timeout_follows_block_time() {
+        let default = ConnectionTimeouts::default();
+        assert_eq!(peer_timeouts(DEFAULT_MINT_INTERVAL), default);
+        let timeouts = peer_timeouts(Duration::from_secs(120));
+        assert_eq!(timeouts.read, Some(Duration::from_secs(600)));
+        assert_eq!(timeouts.write, default.write);
+    }
+
+    #[test]
+    fn peer_index_lookup() {
+        let keys: Vec<_> = (0..3)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519).1)
+            .collect();
+        let peers: Vec<_> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, public_key)| PeerInfo {
+                listen_addr: SocketAddr::from(([127, 0, 0, 1], 6000 + i as u16)),
+                public_key: public_key.clone(),
+            })
+            .collect();
+
+        for (i, public_key) in keys.iter().enumerate() {
+            assert_eq!(peer_index_for(&peers, public_key), Some(i));
+        }
+        let (_, stranger) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        assert_eq!(peer_index_for(&peers, &stranger), None);
+    }
+
+    /// The configuration of a node joining a bootstrap server it doesn't run.
+    fn follower_config(bootstrap_addr: String, peers: usize) -> BootstrapConfig {
+        let (private_key, public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        BootstrapConfig {
+            bootstrap_leader: false,
+            capacity: 5,
+            peers,
+            bootstrap_addr,
+            listen_host: "localhost".into(),
+            public_key,
+            private_key,
+            genesis_funds_per_node: 1000,
+            genesis_funds: BTreeMap::new(),
+            genesis_validator: None,
+            genesis: None,
+            mint_interval: DEFAULT_MINT_INTERVAL,
+            tls: None,
+            connection_retries: ConnectionRetries {
+                attempts: 3,
+                initial_backoff: Duration::from_millis(10),
+                max_backoff: Duration::from_millis(10),
+            },
+        }
+    }
+
+    #[test]
+    fn unreachable_bootstrap_server() {
+        // Nobody listens on a privileged port, unlike a released ephemeral one that another test
+        // may have started listening on
+        let bootstrap_addr = "127.0.0.1:1";
+        let config = follower_config(bootstrap_addr.into(), 2);
+        match bootstrap(config) {
+            Err(BootstrapError::BootstrapUnreachable(addr, _)) => assert_eq!(addr, bootstrap_addr),
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("bootstrapped without a bootstrap server"),
+        }
+    }
+
+    #[test]
+    fn wrong_peer_count() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let bootstrap_addr = listener.local_addr().unwrap();
+        drop(listener);
+        let config = follower_config(bootstrap_addr.to_string(), 2);
+        let address = Address::from_public_key(&config.public_key);
+
+        // The bootstrap server only expects this node
+        let helper = std::thread::spawn(move || {
+            let timeouts = ConnectionTimeouts::default();
+            bootstrap_helper::<_, PeerInfo, _>(bootstrap_addr, 1, address, timeouts)
+        });
+        match bootstrap(config) {
+            Err(BootstrapError::PeerCount(expected, actual)) => {
+                assert_eq!((expected, actual), (2, 1))
+            }
+            Err(err) => panic!("unexpected error: {err}"),
+            Ok(_) => panic!("bootstrapped with the wrong number of peers"),
+        }
+        helper.join().unwrap().unwrap();
+    }
+
     #[test]
     fn bootstrap_small_cluster() {
         tracing_subscriber::fmt().with_test_writer().init();
 
-        let bootstrap_addr = "127.0.0.1:13000".parse().unwrap();
-        let listen_ip = "127.0.0.1".parse().unwrap();
+        let bootstrap_addr = "localhost:13000";
+        let listen_host = "localhost";
 
         const PEERS: usize = 5;
         const CAPACITY: usize = 5;
@@ -111,52 +394,265 @@ mod test {
 
         // Start threads for the non-leader nodes
         for _ in 1..PEERS {
-            let (private_key, public_key) = crypto::generate_keypair();
+            let (private_key, public_key) = crypto::generate_test_keypair();
             let config = BootstrapConfig {
                 bootstrap_leader: false,
                 capacity: CAPACITY,
                 peers: PEERS,
-                bootstrap_addr,
-                listen_ip,
+                bootstrap_addr: bootstrap_addr.into(),
+                listen_host: listen_host.into(),
                 public_key,
                 private_key,
                 genesis_funds_per_node: 1000,
+                genesis_funds: BTreeMap::new(),
+                genesis_validator: None,
+                genesis: None,
+                mint_interval: DEFAULT_MINT_INTERVAL,
+                tls: None,
+                connection_retries: ConnectionRetries::default(),
             };
             let handle = std::thread::spawn(move || {
-                let (mut node, mut network, _, _) = bootstrap(config);
-                loop {
-                    let timeout = node.step(&mut network);
-                    if node.blockchain().len() > 2 {
-                        break;
-                    }
-                    network.await_events(timeout);
-                }
+                let (mut node, mut network, my_index, peer_infos) = bootstrap(config).unwrap();
+                step_until(&mut node, &mut network, |node| node.blockchain().len() > 2);
+                (node, my_index, peer_infos)
             });
             node_handles.push(handle);
         }
 
         // Start the leader node and verify its state
-        let (private_key, public_key) = crypto::generate_keypair();
+        let (private_key, public_key) = crypto::generate_test_keypair();
         let config = BootstrapConfig {
             bootstrap_leader: true,
             capacity: CAPACITY,
             peers: PEERS,
-            bootstrap_addr,
-            listen_ip,
+            bootstrap_addr: bootstrap_addr.into(),
+            listen_host: listen_host.into(),
             public_key,
             private_key,
             genesis_funds_per_node: 1000,
+            genesis_funds: BTreeMap::new(),
+            genesis_validator: None,
+            genesis: None,
+            mint_interval: DEFAULT_MINT_INTERVAL,
+            tls: None,
+            connection_retries: ConnectionRetries::default(),
         };
-        let (mut node, mut network, _, _) = bootstrap(config);
-        loop {
-            let timeout = node.step(&mut network);
-            if node.blockchain().len() > 2 {
-                break;
+        let (node, mut network, my_index, peer_infos) = bootstrap(config).unwrap();
+        let addresses: Vec<_> = peer_infos
+            .iter()
+            .map(|info| Address::from_public_key(&info.public_key))
+            .collect();
+
+        // Serves the HTTP API of a node and returns a client connected to it
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let serve = |node: Node, node_index, peers: Arc<Vec<PeerInfo>>| {
+            let state = ApiState {
+                node: NodeHandle::spawn(node),
+                node_index,
+                peers,
+                ready: Readiness::default(),
+            };
+            state.ready.set_bootstrapped(PeerConnections::default());
+            runtime.block_on(async {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+                tokio::spawn(async move { axum::serve(listener, api::router(state)).await });
+                BlockchatClient::new(rpc_url.parse().unwrap())
+            })
+        };
+        // Every node reports the index it was assigned and its own entry of the peer list
+        let assert_identity =
+            |client: &BlockchatClient, node_index: usize, peer_infos: &[PeerInfo]| {
+                let identity = runtime.block_on(client.get_identity()).unwrap();
+                let info = &peer_infos[node_index];
+                assert_eq!(identity.node_index, node_index);
+                assert_eq!(identity.address, Address::from_public_key(&info.public_key));
+                assert_eq!(identity.listen_addr, Some(info.listen_addr));
+                assert_eq!(identity.peers, PEERS);
+            };
+
+        let mut node = node;
+        step_until(&mut node, &mut network, |node| node.blockchain().len() > 2);
+
+        // Every peer, including the leader, ends up with exactly its genesis funds
+        let assert_funded = |node: &Node| {
+            assert_eq!(node.total_supply(), 1000 * PEERS as u64);
+            for address in addresses.iter() {
+                assert_eq!(node.wallet_for(address).unwrap().balance, 1000);
             }
+        };
+        assert_funded(&node);
+
+        // Serve the HTTP API of the leader and verify that it reports all the peers
+        let peer_infos = Arc::new(peer_infos);
+        let client = serve(node, my_index, Arc::clone(&peer_infos));
+        let peers = runtime.block_on(client.get_peers()).unwrap();
+        assert_eq!(peers.node_index, my_index);
+        assert_eq!(peers.peers.len(), PEERS);
+        assert_identity(&client, my_index, &peer_infos);
+
+        let mut indices = vec![my_index];
+        for handle in node_handles {
+            let (node, node_index, peer_infos) = handle.join().expect("node panicked");
+            assert_funded(&node);
+            let peer_infos = Arc::new(peer_infos);
+            let client = serve(node, node_index, Arc::clone(&peer_infos));
+            assert_identity(&client, node_index, &peer_infos);
+            indices.push(node_index);
+        }
+        indices.sort();
+        assert_eq!(indices, (0..PEERS).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn unequal_genesis_funds() {
+        let (leader_private_key, leader_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (peer_private_key, peer_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let leader = Address::from_public_key(&leader_public_key);
+        let peer = Address::from_public_key(&peer_public_key);
+        let genesis_funds = BTreeMap::from([(leader.clone(), 3000), (peer.clone(), 500)]);
+
+        let config = |bootstrap_leader, public_key, private_key| BootstrapConfig {
+            bootstrap_leader,
+            capacity: 5,
+            peers: 2,
+            bootstrap_addr: "localhost:13001".into(),
+            listen_host: "localhost".into(),
+            public_key,
+            private_key,
+            genesis_funds_per_node: 1000,
+            genesis_funds: genesis_funds.clone(),
+            genesis_validator: None,
+            genesis: None,
+            mint_interval: DEFAULT_MINT_INTERVAL,
+            tls: None,
+            connection_retries: ConnectionRetries::default(),
+        };
+        let peer_config = config(false, peer_public_key, peer_private_key);
+        let leader_config = config(true, leader_public_key, leader_private_key);
+
+        // Each node runs until it has accepted the block seeding the funds
+        let run = |config| {
+            let (mut node, mut network, _, _) = bootstrap(config).unwrap();
+            step_until(&mut node, &mut network, |node| node.blockchain().len() >= 2);
+            node
+        };
+        let handle = std::thread::spawn(move || run(peer_config));
+        let leader_node = run(leader_config);
+        let peer_node = handle.join().expect("node panicked");
+
+        for node in [leader_node, peer_node] {
+            assert_eq!(node.wallet_for(&leader).unwrap().balance, 3000);
+            assert_eq!(node.wallet_for(&peer).unwrap().balance, 500);
+            assert_eq!(node.total_supply(), 3500);
+        }
+    }
+
+    #[test]
+    fn more_peers_than_capacity() {
+        const PEERS: usize = 4;
+        const CAPACITY: usize = 1;
+
+        let keys: Vec<_> = (0..PEERS)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519))
+            .collect();
+        let addresses: Vec<_> = keys
+            .iter()
+            .map(|(_, public_key)| Address::from_public_key(public_key))
+            .collect();
+        let config = |bootstrap_leader, (private_key, public_key)| BootstrapConfig {
+            bootstrap_leader,
+            capacity: CAPACITY,
+            peers: PEERS,
+            bootstrap_addr: "localhost:13002".into(),
+            listen_host: "localhost".into(),
+            public_key,
+            private_key,
+            genesis_funds_per_node: 1000,
+            genesis_funds: BTreeMap::new(),
+            genesis_validator: None,
+            genesis: None,
+            mint_interval: DEFAULT_MINT_INTERVAL,
+            tls: None,
+            connection_retries: ConnectionRetries::default(),
+        };
+        let is_funded = move |node: &Node| {
+            addresses
+                .iter()
+                .all(|address| node.wallet_for(address).is_some_and(|w| w.balance == 1000))
+        };
+
+        let mut keys = keys.into_iter();
+        let leader_config = config(true, keys.next().unwrap());
+        let handles: Vec<_> = keys
+            .map(|keys| {
+                let config = config(false, keys);
+                let is_funded = is_funded.clone();
+                std::thread::spawn(move || {
+                    let (mut node, mut network, _, _) = bootstrap(config).unwrap();
+                    step_until(&mut node, &mut network, is_funded);
+                })
+            })
+            .collect();
+
+        // The leader only returns once every peer has been funded
+        let (mut node, mut network, _, _) = bootstrap(leader_config).unwrap();
+        assert!(node.blockchain().len() >= 1 + PEERS - 1);
+        assert!(is_funded(&node));
+
+        while !handles.iter().all(|handle| handle.is_finished()) {
+            let timeout = node.step(&mut network);
             network.await_events(timeout);
         }
-        for handle in node_handles {
+        for handle in handles {
             handle.join().expect("node panicked");
         }
     }
+
+    #[test]
+    fn non_leader_genesis_validator() {
+        let (leader_private_key, leader_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (peer_private_key, peer_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let leader = Address::from_public_key(&leader_public_key);
+        let peer = Address::from_public_key(&peer_public_key);
+
+        let config = |bootstrap_leader, public_key, private_key| BootstrapConfig {
+            bootstrap_leader,
+            capacity: 5,
+            peers: 2,
+            bootstrap_addr: "localhost:13003".into(),
+            listen_host: "localhost".into(),
+            public_key,
+            private_key,
+            genesis_funds_per_node: 1000,
+            genesis_funds: BTreeMap::new(),
+            genesis_validator: Some(peer.clone()),
+            genesis: None,
+            mint_interval: DEFAULT_MINT_INTERVAL,
+            tls: None,
+            connection_retries: ConnectionRetries::default(),
+        };
+        let peer_config = config(false, peer_public_key, peer_private_key);
+        let leader_config = config(true, leader_public_key, leader_private_key);
+
+        // Each node runs until it has accepted the block seeding the funds
+        let run = |config| {
+            let (mut node, mut network, _, _) = bootstrap(config).unwrap();
+            step_until(&mut node, &mut network, |node| node.blockchain().len() >= 2);
+            node
+        };
+        let handle = std::thread::spawn(move || run(peer_config));
+        let leader_node = run(leader_config);
+        let peer_node = handle.join().expect("node panicked");
+
+        for node in [leader_node, peer_node] {
+            assert_eq!(node.blockchain()[1].data.validator, peer);
+            assert_eq!(node.wallet_for(&leader).unwrap().balance, 1000);
+            assert_eq!(node.wallet_for(&peer).unwrap().staked_amount(), 1);
+        }
+    }
 }
@@ -1,4 +1,6 @@
 //! Types related to the CLI
 
+pub mod address_book;
 pub mod client;
 pub mod command;
+pub mod history;
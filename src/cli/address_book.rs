@@ -0,0 +1,113 @@
+//! An address book mapping human friendly aliases to wallet addresses.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::crypto::Address;
+
+/// Aliases are non-empty and consist of alphanumeric characters, `-` or `_`.
+fn is_valid_alias(alias: &str) -> bool {
+    !alias.is_empty()
+        && alias
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[derive(Debug, Default)]
+pub struct AddressBook {
+    /// The known aliases.
+    aliases: BTreeMap<String, Address>,
+    /// The file the aliases are persisted to, if any.
+    path: Option<PathBuf>,
+}
+
+impl AddressBook {
+    /// Creates an empty in-memory address book.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an address book persisted as JSON at the provided path, loading any aliases
+    /// already stored there.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let aliases = match std::fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self {
+            aliases,
+            path: Some(path),
+        })
+    }
+
+    /// Defines or overwrites an alias. Returns the address previously associated with it.
+    pub fn insert(&mut self, alias: String, address: Address) -> Result<Option<Address>, String> {
+        if !is_valid_alias(&alias) {
+            return Err(format!("invalid alias: {alias}"));
+        }
+        let previous = self.aliases.insert(alias, address);
+        if let Some(path) = &self.path {
+            let data = serde_json::to_string_pretty(&self.aliases).unwrap();
+            std::fs::write(path, data).map_err(|err| err.to_string())?;
+        }
+        Ok(previous)
+    }
+
+    /// Resolves a recipient given in a command, which is either an alias or an address. Aliases
+    /// take precedence.
+    pub fn resolve(&self, recipient: &str) -> Result<Address, String> {
+        match self.aliases.get(recipient) {
+            Some(address) => Ok(address.clone()),
+            None => recipient
+                .parse()
+                .map_err(|_| format!("unknown alias or invalid address: {recipient}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto;
+
+    use super::*;
+
+    fn random_address() -> Address {
+        let (_, public_key) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        Address::from_public_key(&public_key)
+    }
+
+    #[test]
+    fn define_resolve_overwrite() {
+        let mut book = AddressBook::new();
+        let bob = "bob";
+        assert!(book.resolve("not an alias").is_err());
+
+        let first = random_address();
+        assert_eq!(book.insert("bob".into(), first.clone()), Ok(None));
+        assert_eq!(book.resolve(bob), Ok(first.clone()));
+
+        let second = random_address();
+        assert_eq!(book.insert("bob".into(), second.clone()), Ok(Some(first)));
+        assert_eq!(book.resolve(bob), Ok(second.clone()));
+        // Literal addresses resolve to themselves
+        assert_eq!(book.resolve(&second.to_string()), Ok(second));
+
+        assert!(book.insert("no spaces".into(), random_address()).is_err());
+    }
+
+    #[test]
+    fn persisted_aliases() {
+        let path = std::env::temp_dir().join("blockchat_persisted_aliases.json");
+        let _ = std::fs::remove_file(&path);
+
+        let address = random_address();
+        let mut book = AddressBook::from_file(&path).unwrap();
+        book.insert("alice".into(), address.clone()).unwrap();
+
+        let book = AddressBook::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(book.resolve("alice"), Ok(address));
+    }
+}
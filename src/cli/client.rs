@@ -1,9 +1,14 @@
-use reqwest::{Client, Url};
-use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use reqwest::{Client, RequestBuilder, Response, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
 
 use crate::{
-    crypto::{Address, Signed},
-    node::Block,
+    api::IDEMPOTENCY_KEY_HEADER,
+    crypto::{Address, Hash, Signed},
+    node::{Block, TxStatus},
     wallet::{Transaction, Wallet},
 };
 
@@ -11,15 +16,202 @@ use crate::{
 pub struct BlockchatClient {
     rpc_url: Url,
     client: Client,
+    retry_policy: RetryPolicy,
+}
+
+/// How the requests that are safe to repeat are retried while the node is unavailable. Requests
+/// that create transactions are only retried when they carry an idempotency key, since they could
+/// otherwise be applied twice.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub attempts: u32,
+    /// The delay before the first retry. Every following retry waits twice as long.
+    pub initial_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that makes a single attempt.
+    pub fn none() -> Self {
+        Self {
+            attempts: 1,
+            initial_backoff: Duration::ZERO,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+/// The errors returned by the requests of `BlockchatClient`.
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("failed to reach the node: {0}")]
+    Connection(reqwest::Error),
+    #[error("the node responded with {status}: {body}")]
+    Status { status: StatusCode, body: String },
+    #[error("failed to decode the response of the node: {0}")]
+    Decode(reqwest::Error),
+    #[error("giving up after {attempts} attempts: {source}")]
+    RetriesExhausted {
+        attempts: u32,
+        source: Box<ClientError>,
+    },
+}
+
+impl ClientError {
+    /// Reports whether the request could succeed if it is repeated later.
+    fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Connection(_) => true,
+            ClientError::Status { status, .. } => status.is_server_error(),
+            ClientError::Decode(_) | ClientError::RetriesExhausted { .. } => false,
+        }
+    }
+}
+
+type Err = ClientError;
+
+/// The query parameters of `GET /balance`.
+#[derive(Serialize, Deserialize)]
+pub struct BalanceQuery {
+    /// The address to look up. Defaults to the wallet of the node.
+    pub address: Option<Address>,
+    /// Apply the effects of the pending transactions on the balance.
+    #[serde(default)]
+    pub pending: bool,
+}
+
+/// The result of checking the integrity of the blockchain as reported by `GET /verify`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerifyResponse {
+    /// Whether the whole blockchain is valid.
+    pub valid: bool,
+    /// The first violation found, if any.
+    pub error: Option<String>,
+}
+
+/// The query parameters of `GET /transactions`.
+#[derive(Serialize, Deserialize)]
+pub struct TransactionsQuery {
+    /// The address whose transactions are listed. Defaults to the wallet of the node.
+    pub address: Option<Address>,
+    /// The number of transactions to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// The maximum number of transactions returned.
+    pub limit: Option<usize>,
+}
+
+/// A page of the confirmed transactions of an address as reported by `GET /transactions`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransactionsResponse {
+    /// The total number of confirmed transactions of the address.
+    pub total: usize,
+    /// The number of transactions skipped before this page.
+    pub offset: usize,
+    /// The transactions of this page, oldest first.
+    pub transactions: Vec<Signed<Transaction>>,
+}
+
+/// The kinds of transactions whose fees can be estimated.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeKind {
+    Coin,
+    Message,
+}
+
+/// The query parameters of `GET /fee/estimate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeeEstimateQuery {
+    pub kind: FeeKind,
+    /// The amount transferred, required for coin transactions.
+    pub amount: Option<u64>,
+    /// The length of the message in bytes, required for message transactions.
+    pub length: Option<usize>,
+}
+
+/// The fees a prospective transaction would be charged as reported by `GET /fee/estimate`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeeEstimateResponse {
+    pub fee: u64,
+}
+
+/// The query parameters of `GET /blocks`.
+#[derive(Serialize, Deserialize)]
+pub struct BlocksQuery {
+    /// The height of the first block returned.
+    #[serde(default)]
+    pub start: usize,
+    /// The height after the last block returned. Defaults to the height of the blockchain.
+    pub end: Option<usize>,
+    /// Only report the heights of the blocks minted by this validator instead.
+    pub validator: Option<Address>,
+}
+
+/// The blocks minted by a validator as reported by `GET /blocks?validator=<address>`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ValidatorBlocksResponse {
+    pub validator: Address,
+    /// The heights of the blocks minted by the validator, in ascending order.
+    pub heights: Vec<usize>,
 }
 
-type Err = String;
+/// The query parameters of `GET /addresses`.
+#[derive(Serialize, Deserialize)]
+pub struct AddressesQuery {
+    /// The number of addresses to skip.
+    #[serde(default)]
+    pub offset: usize,
+    /// The maximum number of addresses returned.
+    pub limit: Option<usize>,
+}
+
+/// An address known to the node as reported by `GET /addresses`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddressResponse {
+    pub address: Address,
+    pub balance: u64,
+    pub stake: u64,
+}
+
+/// A page of the addresses known to the node as reported by `GET /addresses`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AddressesResponse {
+    /// The total number of known addresses.
+    pub total: usize,
+    /// The number of addresses skipped before this page.
+    pub offset: usize,
+    /// The addresses of this page, sorted by address.
+    pub addresses: Vec<AddressResponse>,
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct SetStakeRequest {
     pub amount: u64,
 }
 
+/// Turns the drain mode of a node on or off through `POST /admin/drain`.
+#[derive(Serialize, Deserialize)]
+pub struct DrainRequest {
+    pub draining: bool,
+}
+
+/// The drain mode of a node after a `POST /admin/drain` request.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DrainResponse {
+    pub draining: bool,
+    /// The transactions still waiting to be minted.
+    pub pending_transactions: usize,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum CreateTransactionRequest {
@@ -27,29 +219,258 @@ pub enum CreateTransactionRequest {
     Message { recipient: Address, message: String },
 }
 
+/// A peer of the network as reported by `GET /peers`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerResponse {
+    /// The index of the peer assigned during bootstrap.
+    pub index: usize,
+    /// The socket address the peer listens on.
+    pub listen_addr: SocketAddr,
+    /// The wallet address of the peer.
+    pub address: Address,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeersResponse {
+    /// The index of the node that served the request.
+    pub node_index: usize,
+    /// All the peers of the network, including the node that served the request.
+    pub peers: Vec<PeerResponse>,
+}
+
+/// The identity of the node that served `GET /identity`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityResponse {
+    /// The index assigned to the node during bootstrap. The node serves its HTTP API on
+    /// `api_base_port + node_index`.
+    pub node_index: usize,
+    /// The wallet address of the node.
+    pub address: Address,
+    /// The socket address the node listens on for its peers, if known.
+    pub listen_addr: Option<SocketAddr>,
+    /// The number of peers of the network, including the node.
+    pub peers: usize,
+}
+
+/// The node statistics reported by `GET /stats`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatsResponse {
+    /// The sum of the balances of all known wallets.
+    pub total_supply: u64,
+    /// The sum of the stakes of all known wallets.
+    pub total_staked: u64,
+    /// The number of blocks in the blockchain, including the genesis block.
+    pub height: usize,
+    /// The number of non-confirmed transactions.
+    pub pending_transactions: usize,
+}
+
+/// An address with a non-zero stake as reported by `GET /stakes`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StakerResponse {
+    pub address: Address,
+    pub stake: u64,
+}
+
+/// The stakers that take part in the validator election as reported by `GET /stakes`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StakesResponse {
+    /// The stakers sorted by address.
+    pub stakers: Vec<StakerResponse>,
+}
+
 impl BlockchatClient {
     pub fn new(rpc_url: Url) -> Self {
         BlockchatClient {
             rpc_url,
             client: Client::new(),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+
+    /// The base URL of the node's API.
+    pub fn rpc_url(&self) -> &Url {
+        &self.rpc_url
+    }
+
+    /// Replaces the policy used to retry the requests that only read the state of the node.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Sends a request that is safe to repeat, retrying it with exponential backoff on transient
+    /// failures.
+    async fn send_idempotent<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T, Err> {
+        let attempts = self.retry_policy.attempts;
+        let mut backoff = self.retry_policy.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            // Requests without a body or with a JSON body can always be cloned
+            let result = send(request.try_clone().unwrap()).await;
+            match result {
+                Err(err) if err.is_transient() && attempt < attempts => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(err) if err.is_transient() && attempts > 1 => {
+                    return Err(ClientError::RetriesExhausted {
+                        attempts,
+                        source: Box::new(err),
+                    });
+                }
+                result => return result,
+            }
         }
     }
 
     pub async fn get_balance(&self) -> Result<Wallet, Err> {
-        let request = self.client.get(self.rpc_url.join("balance").unwrap());
-        let response = request.send().await.unwrap();
-        let wallet = response.json::<Wallet>().await.unwrap();
+        let url = self.rpc_url.join("balance").unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
 
-        Ok(wallet)
+    pub async fn get_balance_of(&self, address: &Address) -> Result<Wallet, Err> {
+        let query = BalanceQuery {
+            address: Some(address.clone()),
+            pending: false,
+        };
+        let url = self.rpc_url.join("balance").unwrap();
+        self.send_idempotent(self.client.get(url).query(&query))
+            .await
+    }
+
+    /// Fetches the wallet of the provided address, or of the node if no address is provided, as
+    /// it will be once the pending transactions are minted.
+    pub async fn get_pending_balance(&self, address: Option<&Address>) -> Result<Wallet, Err> {
+        let query = BalanceQuery {
+            address: address.cloned(),
+            pending: true,
+        };
+        let url = self.rpc_url.join("balance").unwrap();
+        self.send_idempotent(self.client.get(url).query(&query))
+            .await
     }
 
     pub async fn get_last_block(&self) -> Result<Signed<Block>, Err> {
         let url = self.rpc_url.join("block").unwrap();
-        let request = self.client.get(url);
-        let response = request.send().await.unwrap();
-        let last_block = response.json().await.unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
+
+    /// Fetches the blocks from height `start` up to but excluding `end`, or up to the tip if no end
+    /// is provided.
+    pub async fn get_blocks(
+        &self,
+        start: usize,
+        end: Option<usize>,
+    ) -> Result<Vec<Signed<Block>>, Err> {
+        let url = self.rpc_url.join("blocks").unwrap();
+        let query = BlocksQuery {
+            start,
+            end,
+            validator: None,
+        };
+        self.send_idempotent(self.client.get(url).query(&query))
+            .await
+    }
+
+    /// Fetches the heights of the blocks minted by the provided validator.
+    pub async fn get_blocks_by_validator(
+        &self,
+        validator: &Address,
+    ) -> Result<ValidatorBlocksResponse, Err> {
+        let url = self.rpc_url.join("blocks").unwrap();
+        let query = BlocksQuery {
+            start: 0,
+            end: None,
+            validator: Some(validator.clone()),
+        };
+        self.send_idempotent(self.client.get(url).query(&query))
+            .await
+    }
+
+    /// Estimates the fees of a coin transaction transferring `amount`.
+    pub async fn estimate_coin_fee(&self, amount: u64) -> Result<FeeEstimateResponse, Err> {
+        let query = FeeEstimateQuery {
+            kind: FeeKind::Coin,
+            amount: Some(amount),
+            length: None,
+        };
+        self.estimate_fee(&query).await
+    }
+
+    /// Estimates the fees of a message of `length` bytes.
+    pub async fn estimate_message_fee(&self, length: usize) -> Result<FeeEstimateResponse, Err> {
+        let query = FeeEstimateQuery {
+            kind: FeeKind::Message,
+            amount: None,
+            length: Some(length),
+        };
+        self.estimate_fee(&query).await
+    }
+
+    async fn estimate_fee(&self, query: &FeeEstimateQuery) -> Result<FeeEstimateResponse, Err> {
+        let url = self.rpc_url.join("fee/estimate").unwrap();
+        self.send_idempotent(self.client.get(url).query(query))
+            .await
+    }
+
+    pub async fn get_peers(&self) -> Result<PeersResponse, Err> {
+        let url = self.rpc_url.join("peers").unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
+
+    pub async fn get_identity(&self) -> Result<IdentityResponse, Err> {
+        let url = self.rpc_url.join("identity").unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
 
-        Ok(last_block)
+    pub async fn get_stats(&self) -> Result<StatsResponse, Err> {
+        let url = self.rpc_url.join("stats").unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
+
+    pub async fn get_addresses(
+        &self,
+        offset: usize,
+        limit: usize,
+    ) -> Result<AddressesResponse, Err> {
+        let query = AddressesQuery {
+            offset,
+            limit: Some(limit),
+        };
+        let url = self.rpc_url.join("addresses").unwrap();
+        self.send_idempotent(self.client.get(url).query(&query))
+            .await
+    }
+
+    pub async fn get_stakes(&self) -> Result<StakesResponse, Err> {
+        let url = self.rpc_url.join("stakes").unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
+
+    pub async fn transaction_status(&self, hash: &Hash) -> Result<TxStatus, Err> {
+        let url = self.rpc_url.join(&format!("transaction/{hash}")).unwrap();
+        self.send_idempotent(self.client.get(url)).await
+    }
+
+    pub async fn get_transactions(
+        &self,
+        address: &Address,
+        offset: usize,
+        limit: usize,
+    ) -> Result<TransactionsResponse, Err> {
+        let query = TransactionsQuery {
+            address: Some(address.clone()),
+            offset,
+            limit: Some(limit),
+        };
+        let url = self.rpc_url.join("transactions").unwrap();
+        self.send_idempotent(self.client.get(url).query(&query))
+            .await
     }
 
     pub async fn send_transaction(
@@ -62,10 +483,25 @@ impl BlockchatClient {
             .client
             .post(url)
             .json(&CreateTransactionRequest::Coin { recipient, amount });
-        let response = request.send().await.unwrap();
-        let tx = response.json().await.unwrap();
+        send(request).await
+    }
 
-        Ok(tx)
+    /// Like `send_transaction` but tags the request with an idempotency key, so that it's retried
+    /// like the requests that are safe to repeat. Retries of a request the node already served
+    /// return the same transaction instead of creating another one.
+    pub async fn send_transaction_with_key(
+        &self,
+        recipient: Address,
+        amount: u64,
+        idempotency_key: &str,
+    ) -> Result<Signed<Transaction>, Err> {
+        let url = self.rpc_url.join("transaction").unwrap();
+        let request = self
+            .client
+            .post(url)
+            .header(IDEMPOTENCY_KEY_HEADER, idempotency_key)
+            .json(&CreateTransactionRequest::Coin { recipient, amount });
+        self.send_idempotent(request).await
     }
 
     pub async fn send_message(
@@ -78,18 +514,180 @@ impl BlockchatClient {
             .client
             .post(url)
             .json(&CreateTransactionRequest::Message { recipient, message });
-        let response = request.send().await.unwrap();
-        let tx = response.json().await.unwrap();
+        send(request).await
+    }
 
-        Ok(tx)
+    /// Sends several transactions at once. Either all of them are accepted or none is.
+    pub async fn send_batch(
+        &self,
+        requests: &[CreateTransactionRequest],
+    ) -> Result<Vec<Signed<Transaction>>, Err> {
+        let url = self.rpc_url.join("transactions/batch").unwrap();
+        send(self.client.post(url).json(requests)).await
+    }
+
+    /// Submits a transaction that was signed locally, e.g. with `Wallet::sign_tx`.
+    pub async fn submit_transaction(
+        &self,
+        tx: &Signed<Transaction>,
+    ) -> Result<Signed<Transaction>, Err> {
+        let url = self.rpc_url.join("submit").unwrap();
+        send(self.client.post(url).json(tx)).await
     }
 
     pub async fn stake(&self, amount: u64) -> Result<Signed<Transaction>, Err> {
         let url = self.rpc_url.join("stake").unwrap();
-        let request = self.client.post(url).json(&SetStakeRequest { amount });
-        let response = request.send().await.unwrap();
+        send(self.client.post(url).json(&SetStakeRequest { amount })).await
+    }
+
+    /// Makes the node reject new transactions, or accept them again.
+    pub async fn set_draining(&self, draining: bool) -> Result<DrainResponse, Err> {
+        let url = self.rpc_url.join("admin/drain").unwrap();
+        send(self.client.post(url).json(&DrainRequest { draining })).await
+    }
+}
+
+/// Sends the request and decodes the JSON body of a successful response.
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, Err> {
+    let response = request.send().await.map_err(ClientError::Connection)?;
+    check_status(response)
+        .await?
+        .json()
+        .await
+        .map_err(ClientError::Decode)
+}
+
+/// Turns an unsuccessful response into an error carrying the status and the body sent by the node.
+async fn check_status(response: Response) -> Result<Response, Err> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.map_err(ClientError::Connection)?;
+    Err(ClientError::Status { status, body })
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode as AxumStatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+
+    use super::*;
+
+    /// Serves the provided router on an ephemeral port and returns a client connected to it.
+    async fn mock_client(router: Router) -> BlockchatClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        BlockchatClient::new(rpc_url.parse().unwrap())
+    }
+
+    #[test]
+    fn connection_error() {
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let client =
+            BlockchatClient::new(rpc_url.parse().unwrap()).with_retry_policy(RetryPolicy::none());
+        let err = runtime.block_on(client.get_balance()).unwrap_err();
+        assert!(matches!(err, ClientError::Connection(_)), "{err}");
+    }
+
+    #[test]
+    fn retry_transient_failures() {
+        let wallet = Wallet::from_address(Address::invalid());
+        let balance_requests = Arc::new(AtomicUsize::new(0));
+        let stake_requests = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route(
+                "/balance",
+                get({
+                    let requests = Arc::clone(&balance_requests);
+                    move || async move {
+                        // The node is unavailable for the first two requests
+                        if requests.fetch_add(1, Ordering::SeqCst) < 2 {
+                            return Err(AxumStatusCode::SERVICE_UNAVAILABLE);
+                        }
+                        Ok(Json(wallet))
+                    }
+                }),
+            )
+            .route("/block", get(|| async { AxumStatusCode::BAD_GATEWAY }))
+            .route(
+                "/stake",
+                post({
+                    let requests = Arc::clone(&stake_requests);
+                    move || async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        AxumStatusCode::SERVICE_UNAVAILABLE
+                    }
+                }),
+            );
+        let policy = RetryPolicy {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+        };
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let client = mock_client(router).await.with_retry_policy(policy);
+
+            let wallet = client.get_balance().await.unwrap();
+            assert_eq!(wallet.address, Address::invalid());
+            assert_eq!(balance_requests.load(Ordering::SeqCst), 3);
+
+            let err = client.get_last_block().await.unwrap_err();
+            assert!(
+                matches!(err, ClientError::RetriesExhausted { attempts: 3, .. }),
+                "{err}"
+            );
+
+            // Creating transactions is not retried
+            let err = client.stake(10).await.unwrap_err();
+            assert!(matches!(err, ClientError::Status { .. }), "{err}");
+            assert_eq!(stake_requests.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn status_error() {
+        let router = Router::new().route(
+            "/stake",
+            post(|| async { (AxumStatusCode::BAD_REQUEST, "not enough funds") }),
+        );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let err = runtime.block_on(async {
+            let client = mock_client(router).await;
+            client.stake(1_000_000).await.unwrap_err()
+        });
+        assert_eq!(
+            err.to_string(),
+            "the node responded with 400 Bad Request: not enough funds"
+        );
+        let ClientError::Status { status, body } = err else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body, "not enough funds");
+    }
+
+    #[test]
+    fn decode_error() {
+        let router = Router::new().route("/block", get(|| async { "not a block" }));
 
-        let stake_tx = response.json().await.unwrap();
-        Ok(stake_tx)
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let err = runtime.block_on(async {
+            let client = mock_client(router).await;
+            client.get_last_block().await.unwrap_err()
+        });
+        assert!(matches!(err, ClientError::Decode(_)), "{err}");
     }
 }
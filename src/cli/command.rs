@@ -1,9 +1,16 @@
 //! The various commands supported by the CLI
 
+use std::fmt;
+use std::io::Write;
 use std::str::FromStr;
 
-use crate::crypto::Address;
+use serde::Serialize;
 
+use crate::crypto::{Address, Hash, Signed};
+use crate::node::TxStatus;
+use crate::wallet::Transaction;
+
+use super::address_book::AddressBook;
 use super::client::BlockchatClient;
 
 #[derive(Debug)]
@@ -11,7 +18,10 @@ pub enum Command {
     NewTransaction(NewTransactionCommand),
     NewMessage(NewMessageCommand),
     Stake(StakeCommand),
+    Alias(AliasCommand),
     ViewLastBlockCommand,
+    ShowChain(ChainCommand),
+    ShowTransaction(TxCommand),
     ShowBalanceCommand,
     HelpCommand,
 }
@@ -25,59 +35,201 @@ impl FromStr for Command {
             "view" => Command::ViewLastBlockCommand,
             "balance" => Command::ShowBalanceCommand,
             "help" => Command::HelpCommand,
+            cmd if cmd == "chain" || cmd.starts_with("chain ") => Command::ShowChain(cmd.parse()?),
+            cmd if cmd.starts_with("tx ") => Command::ShowTransaction(cmd.parse()?),
             cmd if cmd.starts_with("t ") => Command::NewTransaction(cmd.parse()?),
             cmd if cmd.starts_with("m ") => Command::NewMessage(cmd.parse()?),
             cmd if cmd.starts_with("stake ") => Command::Stake(cmd.parse()?),
+            cmd if cmd.starts_with("alias ") => Command::Alias(cmd.parse()?),
             cmd => return Err(format!("invalid command: {cmd}")),
         })
     }
 }
 
+/// The format used to print the results of the commands.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum OutputFormat {
+    /// Human readable output.
+    #[default]
+    Pretty,
+    /// One JSON document per line, suitable for scripting.
+    Json,
+}
+
+impl OutputFormat {
+    fn print<T: fmt::Debug + Serialize>(&self, out: &mut impl Write, value: &T) {
+        match self {
+            OutputFormat::Pretty => writeln!(out, "{:#?}", value),
+            OutputFormat::Json => writeln!(out, "{}", serde_json::to_string(value).unwrap()),
+        }
+        .unwrap();
+    }
+
+    /// Like `print` but uses the `Display` implementation for human readable output.
+    fn display<T: fmt::Display + Serialize>(&self, out: &mut impl Write, value: &T) {
+        match self {
+            OutputFormat::Pretty => writeln!(out, "{}", value),
+            OutputFormat::Json => writeln!(out, "{}", serde_json::to_string(value).unwrap()),
+        }
+        .unwrap();
+    }
+}
+
 impl Command {
-    pub async fn run(&self, client: BlockchatClient) {
+    pub async fn run(
+        &self,
+        client: BlockchatClient,
+        address_book: &mut AddressBook,
+    ) -> Result<(), String> {
+        let mut stdout = std::io::stdout();
+        self.run_with_output(client, address_book, OutputFormat::Pretty, &mut stdout)
+            .await
+    }
+
+    /// Runs the command printing its results to `out` using the provided format.
+    pub async fn run_with_output(
+        &self,
+        client: BlockchatClient,
+        address_book: &mut AddressBook,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
         match self {
-            Command::NewTransaction(tx) => tx.run(client).await,
-            Command::NewMessage(tx) => tx.run(client).await,
-            Command::Stake(tx) => tx.run(client).await,
-            Command::ViewLastBlockCommand => Command::get_last_block(client).await,
-            Command::ShowBalanceCommand => Command::get_balance(client).await,
-            Command::HelpCommand => Command::help(),
+            Command::NewTransaction(tx) => tx.run(client, address_book, format, out).await,
+            Command::NewMessage(tx) => tx.run(client, address_book, format, out).await,
+            Command::Stake(tx) => tx.run(client, format, out).await,
+            Command::Alias(alias) => alias.run(address_book, out),
+            Command::ViewLastBlockCommand => Command::get_last_block(client, format, out).await,
+            Command::ShowChain(chain) => chain.run(client, format, out).await,
+            Command::ShowTransaction(tx) => tx.run(client, format, out).await,
+            Command::ShowBalanceCommand => Command::get_balance(client, format, out).await,
+            Command::HelpCommand => Command::help(out),
         }
     }
 
-    async fn get_balance(client: BlockchatClient) {
-        let wallet = client.get_balance().await.unwrap();
-        println!("{:#?}", wallet);
+    /// Runs each of the provided commands in order, printing their results to `out` and any
+    /// errors to stderr. Returns the number of commands that failed to parse or run.
+    pub async fn run_all<'a>(
+        commands: impl IntoIterator<Item = &'a str>,
+        client: BlockchatClient,
+        address_book: &mut AddressBook,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> usize {
+        let mut failures = 0;
+        for cmd in commands {
+            let result = match cmd.parse::<Command>() {
+                Ok(cmd) => {
+                    cmd.run_with_output(client.clone(), address_book, format, out)
+                        .await
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                eprintln!("Error: {err}");
+                failures += 1;
+            }
+        }
+        failures
     }
 
-    async fn get_last_block(client: BlockchatClient) {
-        let last_block = client.get_last_block().await.unwrap();
-        println!("{:#?}", last_block);
+    /// Parses a script containing one command per line. Empty lines and lines starting with `#`
+    /// are ignored.
+    pub fn script_lines(script: &str) -> impl Iterator<Item = &str> {
+        script
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
     }
 
-    fn help() {
-        println!("  t <recipient_address> <amount> - Send an <amount> BTC to <recipient_address>");
-        println!("  t <recipient_address> <message> - Send a message to <recipient_address>");
-        println!("  stake <amount> - Stake an <amount> of BTC");
-        println!("  help - Display the help documentation");
-        println!("  view - View last block");
-        println!("  balance - Show balance");
+    async fn get_balance(
+        client: BlockchatClient,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let wallet = client.get_balance().await.map_err(|err| err.to_string())?;
+        format.display(out, &wallet);
+        Ok(())
     }
+
+    async fn get_last_block(
+        client: BlockchatClient,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let last_block = client
+            .get_last_block()
+            .await
+            .map_err(|err| err.to_string())?;
+        match format {
+            // The fees are charged according to the fee schedule of the node's wallet
+            OutputFormat::Pretty => {
+                let wallet = client.get_balance().await.map_err(|err| err.to_string())?;
+                let summary = last_block.data.summary(wallet.fee_schedule);
+                writeln!(out, "block {} {summary}", last_block.hash).unwrap();
+            }
+            OutputFormat::Json => format.print(out, &last_block),
+        }
+        Ok(())
+    }
+
+    fn help(out: &mut impl Write) -> Result<(), String> {
+        let help = [
+            "  t <recipient_address> <amount> - Send an <amount> BTC to <recipient_address>",
+            "  t <recipient_address> max - Send all the available BTC to <recipient_address>",
+            "  t <recipient_address> <message> - Send a message to <recipient_address>",
+            "  stake <amount> - Stake an <amount> of BTC",
+            "  alias <name> <address> - Use <name> in place of <address> as a recipient",
+            "  help - Display the help documentation",
+            "  view - Summarize the last block",
+            "  tx <tx_hash> - Show the inclusion status and the contents of a transaction",
+            "  chain [start] [end] - Summarize the blocks from height [start] up to but excluding [end]",
+            "  balance - Show balance",
+        ];
+        for line in help {
+            writeln!(out, "{line}").unwrap();
+        }
+        Ok(())
+    }
+}
+
+/// The amount of BCC sent by a coin transaction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Amount {
+    Exact(u64),
+    /// All the available funds, minus the fees.
+    Max,
 }
 
 #[derive(Debug)]
 pub struct NewTransactionCommand {
-    pub recipient: Address,
-    pub amount: u64,
+    /// The address or alias of the recipient.
+    pub recipient: String,
+    pub amount: Amount,
 }
 
 impl NewTransactionCommand {
-    pub async fn run(&self, client: BlockchatClient) {
+    pub async fn run(
+        &self,
+        client: BlockchatClient,
+        address_book: &AddressBook,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let recipient = address_book.resolve(&self.recipient)?;
+        let amount = match self.amount {
+            Amount::Exact(amount) => amount,
+            Amount::Max => {
+                let wallet = client.get_balance().await.map_err(|err| err.to_string())?;
+                wallet.max_coin_amount()
+            }
+        };
         let tx = client
-            .send_transaction(self.recipient.clone(), self.amount)
+            .send_transaction(recipient, amount)
             .await
-            .unwrap();
-        println!("{:#?}", tx);
+            .map_err(|err| err.to_string())?;
+        format.print(out, &tx);
+        Ok(())
     }
 }
 
@@ -90,14 +242,16 @@ impl FromStr for NewTransactionCommand {
         assert_eq!(parts.next(), Some("t"));
 
         let recipient = match parts.next() {
-            Some(r) => r.parse::<Address>()?,
+            Some(r) => r.to_owned(),
             _ => return Err("No recipient address provided.".into()),
         };
 
         let amount = match parts.next() {
-            Some(a) => a
-                .parse::<u64>()
-                .map_err(|_| "Could not parse amount.".to_owned())?,
+            Some("max") => Amount::Max,
+            Some(a) => Amount::Exact(
+                a.parse::<u64>()
+                    .map_err(|_| "Could not parse amount.".to_owned())?,
+            ),
             None => return Err("No amount provided.".into()),
         };
 
@@ -107,17 +261,30 @@ impl FromStr for NewTransactionCommand {
 
 #[derive(Debug)]
 pub struct NewMessageCommand {
-    pub recipient: Address,
+    /// The address or alias of the recipient.
+    pub recipient: String,
     pub message: String,
 }
 
 impl NewMessageCommand {
-    pub async fn run(&self, client: BlockchatClient) {
+    pub async fn run(
+        &self,
+        client: BlockchatClient,
+        address_book: &AddressBook,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let recipient = address_book.resolve(&self.recipient)?;
+        // The fees of a message grow with its length, so a long one is rejected before it's sent
+        let wallet = client.get_balance().await.map_err(|err| err.to_string())?;
+        let tx = wallet.create_message_tx(recipient.clone(), self.message.clone());
+        wallet.check_cost(&tx).map_err(|err| err.to_string())?;
         let tx = client
-            .send_message(self.recipient.clone(), self.message.clone())
+            .send_message(recipient, self.message.clone())
             .await
-            .unwrap();
-        println!("{:#?}", tx);
+            .map_err(|err| err.to_string())?;
+        format.print(out, &tx);
+        Ok(())
     }
 }
 
@@ -130,7 +297,7 @@ impl FromStr for NewMessageCommand {
         assert_eq!(parts.next(), Some("m"));
 
         let recipient = match parts.next() {
-            Some(r) => r.parse::<Address>()?,
+            Some(r) => r.to_owned(),
             _ => return Err("No recipient address provided.".into()),
         };
 
@@ -149,9 +316,18 @@ pub struct StakeCommand {
 }
 
 impl StakeCommand {
-    pub async fn run(&self, client: BlockchatClient) {
-        let stake_tx = client.stake(self.amount).await.unwrap();
-        println!("{:#?}", stake_tx);
+    pub async fn run(
+        &self,
+        client: BlockchatClient,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let stake_tx = client
+            .stake(self.amount)
+            .await
+            .map_err(|err| err.to_string())?;
+        format.print(out, &stake_tx);
+        Ok(())
     }
 }
 
@@ -173,3 +349,508 @@ impl FromStr for StakeCommand {
         Ok(StakeCommand { amount })
     }
 }
+
+#[derive(Debug)]
+pub struct AliasCommand {
+    pub alias: String,
+    pub address: Address,
+}
+
+impl AliasCommand {
+    pub fn run(&self, address_book: &mut AddressBook, out: &mut impl Write) -> Result<(), String> {
+        match address_book.insert(self.alias.clone(), self.address.clone())? {
+            Some(previous) => writeln!(
+                out,
+                "{} now refers to {} instead of {previous}",
+                self.alias, self.address
+            ),
+            None => writeln!(out, "{} now refers to {}", self.alias, self.address),
+        }
+        .unwrap();
+        Ok(())
+    }
+}
+
+impl FromStr for AliasCommand {
+    type Err = String;
+
+    fn from_str(cmd: &str) -> Result<Self, Self::Err> {
+        let mut parts = cmd.split_whitespace();
+
+        assert_eq!(parts.next(), Some("alias"));
+
+        let alias = match parts.next() {
+            Some(a) => a.to_owned(),
+            None => return Err("No alias provided.".into()),
+        };
+
+        let address = match parts.next() {
+            Some(a) => a.parse::<Address>()?,
+            None => return Err("No address provided.".into()),
+        };
+
+        Ok(AliasCommand { alias, address })
+    }
+}
+
+/// Summarizes a range of blocks of the blockchain. Both ends are optional and default to the whole
+/// blockchain.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ChainCommand {
+    /// The height of the first block shown.
+    pub start: usize,
+    /// The height after the last block shown.
+    pub end: Option<usize>,
+}
+
+/// The compact summary of a block printed by the `chain` command.
+#[derive(Debug, Serialize)]
+struct BlockSummary {
+    height: usize,
+    validator: Address,
+    transactions: usize,
+    hash: Hash,
+}
+
+impl fmt::Display for BlockSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} validator: {} transactions: {}",
+            self.height, self.hash, self.validator, self.transactions
+        )
+    }
+}
+
+impl ChainCommand {
+    pub async fn run(
+        &self,
+        client: BlockchatClient,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let blocks = client
+            .get_blocks(self.start, self.end)
+            .await
+            .map_err(|err| err.to_string())?;
+        for (height, block) in (self.start..).zip(blocks) {
+            let summary = BlockSummary {
+                height,
+                validator: block.data.validator.clone(),
+                transactions: block.data.transactions.len(),
+                hash: block.hash,
+            };
+            format.display(out, &summary);
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ChainCommand {
+    type Err = String;
+
+    fn from_str(cmd: &str) -> Result<Self, Self::Err> {
+        let mut parts = cmd.split_whitespace();
+
+        assert_eq!(parts.next(), Some("chain"));
+
+        let parse_height = |height: &str| {
+            height
+                .parse::<usize>()
+                .map_err(|_| format!("Could not parse block height {height}."))
+        };
+        let start = parts.next().map(parse_height).transpose()?.unwrap_or(0);
+        let end = parts.next().map(parse_height).transpose()?;
+        if end.is_some_and(|end| end < start) {
+            return Err("The end of the range is before its start.".into());
+        }
+        if parts.next().is_some() {
+            return Err("Too many arguments.".into());
+        }
+
+        Ok(ChainCommand { start, end })
+    }
+}
+
+/// Looks up a transaction by its hash.
+#[derive(Debug, Eq, PartialEq)]
+pub struct TxCommand {
+    pub hash: Hash,
+}
+
+/// The result of the `tx` command. The contents of the transaction are only known once it is
+/// confirmed.
+#[derive(Debug, Serialize)]
+struct TxReport {
+    hash: Hash,
+    status: TxStatus,
+    transaction: Option<Signed<Transaction>>,
+}
+
+impl TxCommand {
+    pub async fn run(
+        &self,
+        client: BlockchatClient,
+        format: OutputFormat,
+        out: &mut impl Write,
+    ) -> Result<(), String> {
+        let status = client
+            .transaction_status(&self.hash)
+            .await
+            .map_err(|err| err.to_string())?;
+        let transaction = match status {
+            TxStatus::Confirmed { block_index, .. } => client
+                .get_blocks(block_index, Some(block_index + 1))
+                .await
+                .map_err(|err| err.to_string())?
+                .into_iter()
+                .flat_map(|block| block.data.transactions)
+                .find(|tx| tx.hash == self.hash),
+            TxStatus::Pending | TxStatus::Unknown => None,
+        };
+        let report = TxReport {
+            hash: self.hash.clone(),
+            status,
+            transaction,
+        };
+        format.print(out, &report);
+        Ok(())
+    }
+}
+
+impl FromStr for TxCommand {
+    type Err = String;
+
+    fn from_str(cmd: &str) -> Result<Self, Self::Err> {
+        let mut parts = cmd.split_whitespace();
+
+        assert_eq!(parts.next(), Some("tx"));
+
+        let hash = match parts.next() {
+            Some(h) => h.parse::<Hash>()?,
+            None => return Err("No transaction hash provided.".into()),
+        };
+
+        Ok(TxCommand { hash })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+
+    use crate::cli::client::CreateTransactionRequest;
+    use crate::crypto::{self, SignatureScheme, Signed};
+    use crate::error::Error;
+    use crate::node::Block;
+    use crate::wallet::{Transaction, TransactionKind, Wallet};
+
+    use super::*;
+
+    /// Serves the provided router on an ephemeral port and returns a client connected to it.
+    async fn mock_client(router: Router) -> BlockchatClient {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rpc_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        BlockchatClient::new(rpc_url.parse().unwrap())
+    }
+
+    #[test]
+    fn balance_json_output() {
+        let mut wallet = Wallet::from_address(Address::invalid());
+        wallet.add_funds(1000);
+        wallet.set_stake(10);
+        let served_wallet = wallet.clone();
+        let router = Router::new().route(
+            "/balance",
+            get(move || async move { Json(served_wallet.clone()) }),
+        );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        runtime.block_on(async {
+            let client = mock_client(router).await;
+            let cmd = "balance".parse::<Command>().unwrap();
+            let mut address_book = AddressBook::new();
+            cmd.run_with_output(client, &mut address_book, OutputFormat::Json, &mut out)
+                .await
+                .unwrap();
+        });
+
+        let output = String::from_utf8(out).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let parsed: Wallet = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed.address, wallet.address);
+        assert_eq!(parsed.balance, 1000);
+        assert_eq!(parsed.stake, 10);
+    }
+
+    #[test]
+    fn run_script() {
+        let wallet = Wallet::from_address(Address::invalid());
+        let (private_key, _) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let block = private_key.sign(Block {
+            timestamp: chrono::Utc::now(),
+            transactions: vec![],
+            validator: Address::invalid(),
+            parent_hash: Default::default(),
+            evidence: vec![],
+        });
+        let served_block = block.clone();
+        let router = Router::new()
+            .route("/balance", get(move || async move { Json(wallet.clone()) }))
+            .route(
+                "/block",
+                get(move || async move { Json(served_block.clone()) }),
+            );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        let script = "# Show the state of the node\nbalance\n\nview\n";
+        let failures = runtime.block_on(async {
+            let client = mock_client(router).await;
+            Command::run_all(
+                Command::script_lines(script),
+                client,
+                &mut AddressBook::new(),
+                OutputFormat::Json,
+                &mut out,
+            )
+            .await
+        });
+        assert_eq!(failures, 0);
+
+        let output = String::from_utf8(out).unwrap();
+        let mut lines = output.lines();
+        let _: Wallet = serde_json::from_str(lines.next().unwrap()).unwrap();
+        let parsed: Signed<Block> = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert_eq!(parsed.hash, block.hash);
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn run_script_failures() {
+        let router = Router::new();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        let failures = runtime.block_on(async {
+            let client = mock_client(router).await;
+            Command::run_all(
+                ["help", "invalid", "balance"],
+                client,
+                &mut AddressBook::new(),
+                OutputFormat::Json,
+                &mut out,
+            )
+            .await
+        });
+        // The invalid command fails to parse and the balance is not served
+        assert_eq!(failures, 2);
+    }
+
+    #[test]
+    fn send_to_alias() {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let bob = Address::from_public_key(&public_key);
+        let router = Router::new().route(
+            "/transaction",
+            post(
+                move |Json(request): Json<CreateTransactionRequest>| async move {
+                    let CreateTransactionRequest::Coin { recipient, amount } = request else {
+                        panic!("expected a coin transaction");
+                    };
+                    Json(private_key.sign(Transaction {
+                        sender_address: Address::invalid(),
+                        kind: TransactionKind::Coin(amount, recipient),
+                        nonce: 0,
+                    }))
+                },
+            ),
+        );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        let mut address_book = AddressBook::new();
+        let alias = format!("alias bob {bob}");
+        let failures = runtime.block_on(async {
+            let client = mock_client(router).await;
+            Command::run_all(
+                [alias.as_str(), "t bob 10", "t carol! 10"],
+                client,
+                &mut address_book,
+                OutputFormat::Json,
+                &mut out,
+            )
+            .await
+        });
+        // carol! is neither a known alias nor a valid address
+        assert_eq!(failures, 1);
+        assert_eq!(address_book.resolve("bob"), Ok(bob.clone()));
+
+        let output = String::from_utf8(out).unwrap();
+        let tx_line = output.lines().last().unwrap();
+        let tx: Signed<Transaction> = serde_json::from_str(tx_line).unwrap();
+        assert_eq!(tx.data.kind, TransactionKind::Coin(10, bob));
+    }
+
+    #[test]
+    fn send_max() {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let bob = Address::from_public_key(&public_key);
+        let mut wallet = Wallet::from_address(Address::invalid());
+        wallet.add_funds(1000);
+        let router = Router::new()
+            .route("/balance", get(move || async move { Json(wallet.clone()) }))
+            .route(
+                "/transaction",
+                post(
+                    move |Json(request): Json<CreateTransactionRequest>| async move {
+                        let CreateTransactionRequest::Coin { recipient, amount } = request else {
+                            panic!("expected a coin transaction");
+                        };
+                        Json(private_key.sign(Transaction {
+                            sender_address: Address::invalid(),
+                            kind: TransactionKind::Coin(amount, recipient),
+                            nonce: 0,
+                        }))
+                    },
+                ),
+            );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        runtime.block_on(async {
+            let client = mock_client(router).await;
+            let cmd = format!("t {bob} max").parse::<Command>().unwrap();
+            cmd.run_with_output(
+                client,
+                &mut AddressBook::new(),
+                OutputFormat::Json,
+                &mut out,
+            )
+            .await
+            .unwrap();
+        });
+
+        let output = String::from_utf8(out).unwrap();
+        let tx: Signed<Transaction> = serde_json::from_str(output.trim()).unwrap();
+        // 971 BCC plus 29 BCC of fees spend all the funds
+        assert_eq!(tx.data.kind, TransactionKind::Coin(971, bob));
+    }
+
+    #[test]
+    fn message_over_budget() {
+        let (_, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let bob = Address::from_public_key(&public_key);
+        let mut wallet = Wallet::from_address(Address::invalid());
+        wallet.add_funds(10);
+        let router = Router::new()
+            .route("/balance", get(move || async move { Json(wallet.clone()) }))
+            .route(
+                "/transaction",
+                post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        let err = runtime.block_on(async {
+            let client = mock_client(router).await;
+            let cmd = format!("m {bob} 0123456789a").parse::<Command>().unwrap();
+            cmd.run_with_output(
+                client,
+                &mut AddressBook::new(),
+                OutputFormat::Json,
+                &mut out,
+            )
+            .await
+            .unwrap_err()
+        });
+        // The node would have failed the request had the message been sent
+        assert_eq!(err, Error::CostExceedsFunds(11, 10).to_string());
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn parse_chain() {
+        let parse = |cmd: &str| match cmd.parse::<Command>() {
+            Ok(Command::ShowChain(chain)) => Ok(chain),
+            Ok(cmd) => panic!("unexpected command {cmd:?}"),
+            Err(err) => Err(err),
+        };
+        let chain = |start, end| Ok(ChainCommand { start, end });
+
+        assert_eq!(parse("chain 0 5"), chain(0, Some(5)));
+        assert_eq!(parse("chain 3"), chain(3, None));
+        assert_eq!(parse("chain"), chain(0, None));
+        assert_eq!(parse("  chain  2 2 "), chain(2, Some(2)));
+        assert!(parse("chain x").is_err());
+        assert!(parse("chain 0 -1").is_err());
+        assert!(parse("chain 5 2").is_err());
+        assert!(parse("chain 0 5 7").is_err());
+        assert!("chains".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_tx() {
+        let hash = Hash::digest("tx");
+        let cmd = format!("tx {hash}").parse::<Command>().unwrap();
+        assert!(matches!(cmd, Command::ShowTransaction(TxCommand { hash: h }) if h == hash));
+
+        let err = "tx 1234".parse::<Command>().unwrap_err();
+        assert_eq!(err, "hash must be 32 bytes but is 2: 1234");
+        let err = format!("tx {}", "z".repeat(64))
+            .parse::<Command>()
+            .unwrap_err();
+        assert!(err.starts_with("invalid hash"), "{err}");
+        assert!("tx".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn view_summary() {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let validator = Address::from_public_key(&public_key);
+        let mut wallet = Wallet::from_address(validator.clone());
+        wallet.add_funds(1000);
+        let tx = private_key.sign(wallet.create_coin_tx(validator.clone(), 100));
+        let block = private_key.sign(Block {
+            timestamp: chrono::Utc::now(),
+            transactions: vec![tx],
+            validator: validator.clone(),
+            parent_hash: Default::default(),
+            evidence: vec![],
+        });
+        let served_block = block.clone();
+        let router = Router::new()
+            .route("/balance", get(move || async move { Json(wallet.clone()) }))
+            .route(
+                "/block",
+                get(move || async move { Json(served_block.clone()) }),
+            );
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut out = vec![];
+        runtime.block_on(async {
+            let client = mock_client(router).await;
+            let cmd = "view".parse::<Command>().unwrap();
+            cmd.run_with_output(
+                client,
+                &mut AddressBook::new(),
+                OutputFormat::Pretty,
+                &mut out,
+            )
+            .await
+            .unwrap();
+        });
+
+        let output = String::from_utf8(out).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        assert!(
+            output.starts_with(&format!("block {} ", block.hash)),
+            "{output}"
+        );
+        assert!(output.contains(&format!("by {validator}")), "{output}");
+        assert!(output.contains("1 transactions, 3 BCC in fees"), "{output}");
+    }
+}
@@ -0,0 +1,57 @@
+//! Persistence of the CLI command history across sessions.
+
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use rustyline::error::ReadlineError;
+use rustyline::history::History;
+
+/// The default location of the history file, `~/.blockchat_history`. Returns `None` if the home
+/// directory is unknown.
+pub fn default_history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".blockchat_history"))
+}
+
+/// Loads the history stored at `path`. A missing file is not an error since it is expected on
+/// the first run.
+pub fn load_history<H: History>(history: &mut H, path: &Path) -> rustyline::Result<()> {
+    match history.load(path) {
+        Err(ReadlineError::Io(err)) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Stores the history to `path`, creating the file if it doesn't exist.
+pub fn save_history<H: History>(history: &mut H, path: &Path) -> rustyline::Result<()> {
+    history.save(path)
+}
+
+#[cfg(test)]
+mod test {
+    use rustyline::history::{DefaultHistory, SearchDirection};
+
+    use super::*;
+
+    #[test]
+    fn history_persists() {
+        let path = std::env::temp_dir().join("blockchat_history_persists");
+        let _ = std::fs::remove_file(&path);
+
+        // The first run starts from an empty history
+        let mut history = DefaultHistory::new();
+        load_history(&mut history, &path).unwrap();
+        assert!(history.is_empty());
+        history.add("balance").unwrap();
+        history.add("view").unwrap();
+        save_history(&mut history, &path).unwrap();
+
+        // The next run sees the commands of the previous one
+        let mut history = DefaultHistory::new();
+        load_history(&mut history, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(history.len(), 2);
+        let entry = history.get(1, SearchDirection::Forward).unwrap().unwrap();
+        assert_eq!(entry.entry, "view");
+    }
+}
@@ -0,0 +1,188 @@
+//! An in-memory cluster of nodes for testing consensus without TCP connections or threads.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::crypto::{self, Address, SignatureScheme};
+use crate::genesis::GenesisConfig;
+use crate::network::TestNetwork;
+use crate::node::{Message, Node};
+
+/// A fully connected cluster of nodes driven one step at a time. The first node is the genesis
+/// validator and every node mints as soon as it is elected, so each round of steps extends the
+/// blockchain.
+pub struct TestCluster {
+    nodes: Vec<Node>,
+    networks: Vec<TestNetwork<Message>>,
+}
+
+impl TestCluster {
+    /// Constructs a cluster of `count` nodes that each start with `funds` BCC.
+    pub fn new(count: usize, funds: u64, capacity: usize) -> Self {
+        Self::with_genesis(count, funds, capacity, |_| {})
+    }
+
+    /// Like `new` but lets `configure` adjust the genesis configuration shared by the nodes.
+    pub fn with_genesis<F>(count: usize, funds: u64, capacity: usize, configure: F) -> Self
+    where
+        F: FnOnce(&mut GenesisConfig),
+    {
+        let keys: Vec<_> = (0..count)
+            .map(|_| crypto::generate_keypair_with(SignatureScheme::Ed25519))
+            .collect();
+        let balances: BTreeMap<_, _> = keys
+            .iter()
+            .map(|(_, public_key)| (Address::from_public_key(public_key), funds))
+            .collect();
+        let mut genesis = GenesisConfig {
+            balances,
+            ..GenesisConfig::new(Address::from_public_key(&keys[0].1), funds, capacity)
+        };
+        configure(&mut genesis);
+
+        let nodes = keys
+            .into_iter()
+            .enumerate()
+            .map(|(index, (private_key, public_key))| {
+                let name = format!("node-{index}");
+                let mut node = Node::from_genesis(name, public_key, private_key, genesis.clone())
+                    .expect("invalid genesis configuration");
+                node.set_mint_interval(Duration::ZERO);
+                node
+            })
+            .collect();
+        let links: Vec<_> = (0..count)
+            .flat_map(|a| (a + 1..count).map(move |b| (a, b)))
+            .collect();
+        let networks = TestNetwork::from_links(count, &links);
+
+        Self { nodes, networks }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut Node {
+        &mut self.nodes[index]
+    }
+
+    /// Tears down the cluster and returns its nodes, e.g. to serve them.
+    pub fn into_nodes(self) -> Vec<Node> {
+        self.nodes
+    }
+
+    /// Steps every node once, in index order.
+    pub fn step(&mut self) {
+        for (node, network) in self.nodes.iter_mut().zip(self.networks.iter_mut()) {
+            node.step(network);
+        }
+    }
+
+    /// Runs the provided number of rounds of steps.
+    pub fn run(&mut self, rounds: usize) {
+        for _ in 0..rounds {
+            self.step();
+        }
+    }
+
+    /// Delivers all the messages in flight without minting any new blocks.
+    pub fn settle(&mut self) {
+        let observers: Vec<_> = self.nodes.iter().map(Node::is_observer).collect();
+        for node in self.nodes.iter_mut() {
+            node.set_observer(true);
+        }
+        // A block relayed by the last node reaches the others on the second round
+        self.run(2);
+        for (node, observer) in self.nodes.iter_mut().zip(observers) {
+            node.set_observer(observer);
+        }
+    }
+
+    /// Reports whether all the nodes have the same blockchain.
+    pub fn is_converged(&self) -> bool {
+        let blockchain = self.nodes[0].blockchain();
+        self.nodes
+            .iter()
+            .all(|node| node.blockchain() == blockchain)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn four_nodes_converge() {
+        let mut cluster = TestCluster::new(4, 10_000, 5);
+
+        // Every node stakes so that all of them take turns minting
+        for index in 0..4 {
+            let node = cluster.node_mut(index);
+            let tx = node.sign_transaction(node.wallet().create_stake_tx(100));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.broadcast_transaction(tx).unwrap();
+        }
+        for round in 0..10 {
+            let index = round % 4;
+            let receiver = cluster.nodes()[(index + 1) % 4].address().clone();
+            let node = cluster.node_mut(index);
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver, 10));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.broadcast_transaction(tx).unwrap();
+            cluster.step();
+        }
+        // The validator elected after the last transaction may have minted before receiving it
+        cluster.run(3);
+        cluster.settle();
+
+        assert!(cluster.is_converged());
+        let node = &cluster.nodes()[0];
+        assert!(node.blockchain().len() > 10);
+        assert!(!node.has_pending_transactions());
+        assert!(node.verify_chain().is_ok());
+        for other in cluster.nodes() {
+            assert_eq!(other.wallet_for(other.address()).unwrap().stake, 100);
+        }
+
+        let validators: BTreeSet<_> = node.blockchain()[1..]
+            .iter()
+            .map(|block| block.data.validator.clone())
+            .collect();
+        assert!(validators.len() > 1, "only {validators:?} minted blocks");
+    }
+
+    #[test]
+    fn epochs_converge() {
+        const EPOCH_LENGTH: usize = 4;
+        let mut cluster = TestCluster::with_genesis(3, 10_000, 5, |genesis| {
+            genesis.epoch_length = EPOCH_LENGTH;
+        });
+        for index in 0..3 {
+            let node = cluster.node_mut(index);
+            let tx = node.sign_transaction(node.wallet().create_stake_tx(100));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.broadcast_transaction(tx).unwrap();
+        }
+        cluster.run(40);
+        cluster.settle();
+
+        assert!(cluster.is_converged());
+        let node = &cluster.nodes()[0];
+        assert!(node.verify_chain().is_ok());
+        for other in cluster.nodes() {
+            assert_eq!(other.wallet_for(other.address()).unwrap().stake, 100);
+        }
+
+        // Once the stakes are confirmed, every staker mints in every epoch. The epochs start right
+        // after the genesis block.
+        let blockchain = node.blockchain();
+        assert!(blockchain.len() > 30);
+        for epoch in blockchain[1..].chunks_exact(EPOCH_LENGTH).skip(3) {
+            let validators: BTreeSet<_> = epoch.iter().map(|block| &block.data.validator).collect();
+            assert_eq!(validators.len(), 3, "{validators:?} minted an epoch");
+        }
+    }
+}
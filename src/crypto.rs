@@ -3,27 +3,50 @@
 use std::fmt;
 use std::str::FromStr;
 
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::Rng;
 use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
 use rsa::sha2::{Digest, Sha256};
 use rsa::signature::SignatureEncoding;
 use rsa::signature::{Signer, Verifier};
 use rsa::traits::PublicKeyParts;
-use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use rsa::{BigUint, Oaep, RsaPrivateKey, RsaPublicKey};
+use serde::de::Error as _;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_with::base64::Base64;
 use serde_with::{serde_as, DeserializeFromStr, SerializeDisplay};
 
 use crate::error::{Error, Result};
 
+/// The size in bits of the RSA keys generated by default.
 pub const KEY_SIZE: usize = 2048;
+/// The size in bits of the RSA keys generated in tests. Small keys are insecure but generating
+/// them is an order of magnitude faster.
+#[cfg(test)]
+pub(crate) const TEST_KEY_SIZE: usize = 1024;
 
 #[derive(
     Default, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, DeserializeFromStr, SerializeDisplay,
 )]
 pub struct Hash(pub [u8; 32]);
 
+#[cfg(test)]
+thread_local! {
+    /// The number of times data has been serialized by `Hash::digest` in the current thread.
+    pub(crate) static DIGEST_COUNT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
 impl Hash {
+    /// Computes the SHA-256 digest of the bincode encoding of `data`.
+    ///
+    /// Every node must compute the same digest for the same data, so the encoding is canonical:
+    /// bincode encodes struct fields and enum variants in declaration order and sequences and maps
+    /// in iteration order. Hashed types must therefore only contain ordered collections like
+    /// `BTreeMap`, never a `HashMap` whose iteration order differs between processes.
     pub fn digest<T: Serialize>(data: T) -> Self {
+        #[cfg(test)]
+        DIGEST_COUNT.with(|count| count.set(count.get() + 1));
         let data_encoded = bincode::serialize(&data).unwrap();
         Self(Sha256::digest(data_encoded).into())
     }
@@ -45,31 +68,92 @@ impl FromStr for Hash {
     type Err = String;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        // TODO: error handling
-        let hash = hex::decode(s).unwrap().try_into().unwrap();
+        let bytes = hex::decode(s).map_err(|err| format!("invalid hash {s}: {err}"))?;
+        let hash = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            format!("hash must be 32 bytes but is {}: {s}", bytes.len())
+        })?;
         Ok(Hash(hash))
     }
 }
 
+/// The signature schemes supported by BlockChat.
+///
+/// RSA is the default. Ed25519 keys are generated orders of magnitude faster than 2048 bit RSA
+/// keys and produce 64 byte signatures instead of 256 byte ones.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum SignatureScheme {
+    #[default]
+    Rsa,
+    Ed25519,
+}
+
+impl fmt::Display for SignatureScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureScheme::Rsa => write!(f, "rsa"),
+            SignatureScheme::Ed25519 => write!(f, "ed25519"),
+        }
+    }
+}
+
+impl FromStr for SignatureScheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "rsa" => Ok(SignatureScheme::Rsa),
+            "ed25519" => Ok(SignatureScheme::Ed25519),
+            _ => Err(format!("unknown signature scheme: {s}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct PublicKey {
-    key: RsaPublicKey,
+    key: PublicKeyInner,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum PublicKeyInner {
+    Rsa(RsaPublicKey),
+    Ed25519(ed25519_dalek::VerifyingKey),
 }
 
 #[serde_as]
 #[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
-struct EncodedPublicKey {
+struct EncodedRsaPublicKey {
     #[serde_as(as = "Base64")]
     modulus: Vec<u8>,
     #[serde_as(as = "Base64")]
     public_exponent: Vec<u8>,
 }
 
+impl From<&RsaPublicKey> for EncodedRsaPublicKey {
+    fn from(key: &RsaPublicKey) -> Self {
+        Self {
+            modulus: key.n().to_bytes_be(),
+            public_exponent: key.e().to_bytes_be(),
+        }
+    }
+}
+
+#[serde_as]
+#[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+enum EncodedPublicKey {
+    Rsa(EncodedRsaPublicKey),
+    Ed25519 {
+        #[serde_as(as = "Base64")]
+        key: Vec<u8>,
+    },
+}
+
 impl Serialize for PublicKey {
     fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
-        let encoded = EncodedPublicKey {
-            modulus: self.key.n().to_bytes_be(),
-            public_exponent: self.key.e().to_bytes_be(),
+        let encoded = match &self.key {
+            PublicKeyInner::Rsa(key) => EncodedPublicKey::Rsa(key.into()),
+            PublicKeyInner::Ed25519(key) => EncodedPublicKey::Ed25519 {
+                key: key.to_bytes().to_vec(),
+            },
         };
         encoded.serialize(serializer)
     }
@@ -77,10 +161,30 @@ impl Serialize for PublicKey {
 
 impl<'de> Deserialize<'de> for PublicKey {
     fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
-        let encoded = EncodedPublicKey::deserialize(deserializer)?;
-        let modulus = BigUint::from_bytes_be(&encoded.modulus);
-        let public_exponent = BigUint::from_bytes_be(&encoded.public_exponent);
-        let key = RsaPublicKey::new(modulus, public_exponent).unwrap();
+        let key = match EncodedPublicKey::deserialize(deserializer)? {
+            EncodedPublicKey::Rsa(encoded) => {
+                let modulus = BigUint::from_bytes_be(&encoded.modulus);
+                let public_exponent = BigUint::from_bytes_be(&encoded.public_exponent);
+                // The all zero key is the placeholder of unsigned data like the genesis block
+                if modulus.bits() == 0 && public_exponent.bits() == 0 {
+                    return Ok(PublicKey::invalid());
+                }
+                let is_even = encoded.modulus.last().is_none_or(|byte| byte & 1 == 0);
+                if modulus.bits() == 0 || is_even {
+                    return Err(D::Error::custom("invalid rsa public key modulus"));
+                }
+                let key = RsaPublicKey::new(modulus, public_exponent).map_err(D::Error::custom)?;
+                PublicKeyInner::Rsa(key)
+            }
+            EncodedPublicKey::Ed25519 { key } => {
+                let bytes: [u8; ed25519_dalek::PUBLIC_KEY_LENGTH] = key
+                    .try_into()
+                    .map_err(|_| D::Error::custom("invalid ed25519 public key length"))?;
+                let key =
+                    ed25519_dalek::VerifyingKey::from_bytes(&bytes).map_err(D::Error::custom)?;
+                PublicKeyInner::Ed25519(key)
+            }
+        };
         Ok(PublicKey { key })
     }
 }
@@ -89,7 +193,35 @@ impl PublicKey {
     /// Constructs an invalid public key which does not have a corresponding private key.
     pub fn invalid() -> Self {
         Self {
-            key: RsaPublicKey::new_unchecked(0u64.into(), 0u64.into()),
+            key: PublicKeyInner::Rsa(RsaPublicKey::new_unchecked(0u64.into(), 0u64.into())),
+        }
+    }
+
+    /// Encrypts `message` so that only the holder of the corresponding private key can read it.
+    /// Only RSA keys support encryption.
+    pub fn encrypt(&self, message: &str) -> Result<EncryptedMessage> {
+        let PublicKeyInner::Rsa(key) = &self.key else {
+            return Err(Error::Encryption("only RSA keys support encryption"));
+        };
+        let mut rng = rand::thread_rng();
+        let message_key: [u8; 32] = rng.gen();
+        let encrypted_key = key
+            .encrypt(&mut rng, Oaep::new::<Sha256>(), &message_key)
+            .map_err(|_| Error::Encryption("failed to encrypt the message key"))?;
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&message_key))
+            .encrypt(Nonce::from_slice(&MESSAGE_NONCE), message.as_bytes())
+            .map_err(|_| Error::Encryption("failed to encrypt the message"))?;
+        Ok(EncryptedMessage {
+            encrypted_key,
+            ciphertext,
+        })
+    }
+
+    /// The signature scheme of this key.
+    pub fn scheme(&self) -> SignatureScheme {
+        match self.key {
+            PublicKeyInner::Rsa(_) => SignatureScheme::Rsa,
+            PublicKeyInner::Ed25519(_) => SignatureScheme::Ed25519,
         }
     }
 }
@@ -99,7 +231,14 @@ pub struct Address(Hash);
 
 impl Address {
     pub fn from_public_key(key: &PublicKey) -> Self {
-        Self(Hash::digest(key))
+        // RSA addresses are derived from the bare key encoding so that they stay the same
+        // regardless of the other supported schemes.
+        match &key.key {
+            PublicKeyInner::Rsa(key) => Self(Hash::digest(EncodedRsaPublicKey::from(key))),
+            PublicKeyInner::Ed25519(key) => {
+                Self(Hash::digest((SignatureScheme::Ed25519, key.to_bytes())))
+            }
+        }
     }
 
     pub fn invalid() -> Self {
@@ -107,9 +246,37 @@ impl Address {
     }
 }
 
+/// Separates the encoded hash of an address from its checksum.
+const ADDRESS_CHECKSUM_SEPARATOR: char = '_';
+
+impl Address {
+    /// A short checksum appended to the displayed address so that typos are detected instead of
+    /// resulting in a different valid address.
+    fn checksum(&self) -> String {
+        hex::encode(&Sha256::digest(self.0 .0)[..3])
+    }
+
+    /// Parses an address without a checksum, as displayed before checksums were introduced.
+    pub fn parse_unchecked(s: &str) -> std::result::Result<Self, String> {
+        let bytes = base_62::base62::decode(s).map_err(|_| format!("invalid address: {s}"))?;
+        // The encoding is numeric so any leading zero bytes are lost
+        if bytes.len() > 32 {
+            return Err(format!("invalid address length: {s}"));
+        }
+        let mut hash = [0; 32];
+        hash[32 - bytes.len()..].copy_from_slice(&bytes);
+        Ok(Address(Hash(hash)))
+    }
+}
+
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", base_62::base62::encode(&self.0 .0))
+        write!(
+            f,
+            "{}{ADDRESS_CHECKSUM_SEPARATOR}{}",
+            base_62::base62::encode(&self.0 .0),
+            self.checksum()
+        )
     }
 }
 
@@ -123,39 +290,139 @@ impl FromStr for Address {
     type Err = String;
 
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        // TODO: error handling
-        let hash = base_62::base62::decode(s).unwrap().try_into().unwrap();
-        Ok(Address(Hash(hash)))
+        let Some((encoded, checksum)) = s.rsplit_once(ADDRESS_CHECKSUM_SEPARATOR) else {
+            return Err(format!("address is missing its checksum: {s}"));
+        };
+        let address = Self::parse_unchecked(encoded)?;
+        if address.checksum() != checksum {
+            return Err(format!("invalid address checksum: {s}"));
+        }
+        Ok(address)
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
-pub struct PrivateKey(RsaPrivateKey);
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PrivateKey(PrivateKeyInner);
+
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize)]
+enum PrivateKeyInner {
+    Rsa(RsaPrivateKey),
+    Ed25519(ed25519_dalek::SigningKey),
+}
 
 impl PrivateKey {
     pub fn sign<T: Serialize>(&self, data: T) -> Signed<T> {
-        let signing_key = SigningKey::<Sha256>::new(self.0.clone());
         let hash = Hash::digest(&data);
+        let signature = match &self.0 {
+            PrivateKeyInner::Rsa(key) => {
+                let signing_key = SigningKey::<Sha256>::new(key.clone());
+                signing_key.sign(&hash.0).to_vec()
+            }
+            PrivateKeyInner::Ed25519(key) => key.sign(&hash.0).to_vec(),
+        };
 
         Signed {
-            signature: signing_key.sign(&hash.0).to_vec(),
-            public_key: PublicKey {
-                key: RsaPublicKey::from(&self.0),
-            },
+            signature,
+            public_key: self.public_key(),
             hash,
             data,
         }
     }
+
+    /// Decrypts a message encrypted to the public key corresponding to this private key.
+    pub fn decrypt(&self, message: &EncryptedMessage) -> Result<String> {
+        let PrivateKeyInner::Rsa(key) = &self.0 else {
+            return Err(Error::Encryption("only RSA keys support encryption"));
+        };
+        let message_key = key
+            .decrypt(Oaep::new::<Sha256>(), &message.encrypted_key)
+            .map_err(|_| Error::Encryption("the message is not encrypted to this key"))?;
+        if message_key.len() != 32 {
+            return Err(Error::Encryption("invalid message key"));
+        }
+        let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&message_key))
+            .decrypt(Nonce::from_slice(&MESSAGE_NONCE), &*message.ciphertext)
+            .map_err(|_| Error::Encryption("the message has been tampered with"))?;
+        String::from_utf8(plaintext).map_err(|_| Error::Encryption("the message is not UTF-8"))
+    }
+
+    /// The public key corresponding to this private key.
+    pub fn public_key(&self) -> PublicKey {
+        let key = match &self.0 {
+            PrivateKeyInner::Rsa(key) => PublicKeyInner::Rsa(RsaPublicKey::from(key)),
+            PrivateKeyInner::Ed25519(key) => PublicKeyInner::Ed25519(key.verifying_key()),
+        };
+        PublicKey { key }
+    }
 }
 
+/// Generates a keypair using the default signature scheme.
 pub fn generate_keypair() -> (PrivateKey, PublicKey) {
+    generate_keypair_with(SignatureScheme::default())
+}
+
+/// Generates a keypair using the provided signature scheme.
+pub fn generate_keypair_with(scheme: SignatureScheme) -> (PrivateKey, PublicKey) {
+    match scheme {
+        SignatureScheme::Rsa => generate_keypair_with_size(KEY_SIZE),
+        SignatureScheme::Ed25519 => {
+            let key = ed25519_dalek::SigningKey::generate(&mut rand::thread_rng());
+            let private_key = PrivateKey(PrivateKeyInner::Ed25519(key));
+            let public_key = private_key.public_key();
+
+            (private_key, public_key)
+        }
+    }
+}
+
+/// Derives an Ed25519 keypair from the provided seed, e.g. to give the nodes of a simulation the
+/// same keys on every run.
+pub fn keypair_from_seed(seed: [u8; 32]) -> (PrivateKey, PublicKey) {
+    let key = ed25519_dalek::SigningKey::from_bytes(&seed);
+    let private_key = PrivateKey(PrivateKeyInner::Ed25519(key));
+    let public_key = private_key.public_key();
+
+    (private_key, public_key)
+}
+
+/// Generates an RSA keypair with a modulus of `bits` bits.
+pub fn generate_keypair_with_size(bits: usize) -> (PrivateKey, PublicKey) {
     let mut rng = rand::thread_rng();
+    let key = RsaPrivateKey::new(&mut rng, bits).expect("failed to generate a key");
+    let private_key = PrivateKey(PrivateKeyInner::Rsa(key));
+    let public_key = private_key.public_key();
 
-    let private_key = RsaPrivateKey::new(&mut rng, KEY_SIZE).expect("failed to generate a key");
-    let public_key = RsaPublicKey::from(&private_key);
-    let public_key = PublicKey { key: public_key };
+    (private_key, public_key)
+}
+
+/// Generates an RSA keypair of `TEST_KEY_SIZE` bits.
+#[cfg(test)]
+pub(crate) fn generate_test_keypair() -> (PrivateKey, PublicKey) {
+    generate_keypair_with_size(TEST_KEY_SIZE)
+}
+
+/// The AES-GCM nonce of encrypted messages. Every message is encrypted with a fresh random key so
+/// the nonce never needs to change.
+const MESSAGE_NONCE: [u8; 12] = [0; 12];
+
+/// A message encrypted to the public key of its recipient. The message is encrypted with a random
+/// AES-256-GCM key, which is itself encrypted to the recipient's RSA key using OAEP.
+#[serde_as]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct EncryptedMessage {
+    /// The message key encrypted to the recipient's public key.
+    #[serde_as(as = "Base64")]
+    pub encrypted_key: Vec<u8>,
+    /// The encrypted message along with its authentication tag.
+    #[serde_as(as = "Base64")]
+    pub ciphertext: Vec<u8>,
+}
 
-    (PrivateKey(private_key), public_key)
+impl EncryptedMessage {
+    /// The number of bytes of the encrypted message, including the encrypted key.
+    pub fn size(&self) -> usize {
+        self.encrypted_key.len() + self.ciphertext.len()
+    }
 }
 
 /// A container of signed data
@@ -196,13 +463,21 @@ impl<T: Serialize + Clone> Signed<T> {
     }
 
     pub fn verify(&self) -> Result<()> {
-        let verifying_key = VerifyingKey::<Sha256>::new(self.public_key.key.clone());
         let hash = Hash::digest(&self.data);
         if hash != self.hash {
             return Err(Error::InvalidSignature(Default::default()));
         }
-        let signature_decoded = Signature::try_from(&*self.signature).unwrap();
-        verifying_key.verify(&self.hash.0, &signature_decoded)?;
+        match &self.public_key.key {
+            PublicKeyInner::Rsa(key) => {
+                let verifying_key = VerifyingKey::<Sha256>::new(key.clone());
+                let signature_decoded = Signature::try_from(&*self.signature)?;
+                verifying_key.verify(&self.hash.0, &signature_decoded)?;
+            }
+            PublicKeyInner::Ed25519(key) => {
+                let signature_decoded = ed25519_dalek::Signature::from_slice(&self.signature)?;
+                key.verify(&self.hash.0, &signature_decoded)?;
+            }
+        }
         Ok(())
     }
 }
@@ -215,8 +490,27 @@ mod test {
     fn generate_keypair_test() {
         let (private_key, public_key) = generate_keypair();
 
-        assert!(private_key.0.validate().is_ok());
-        assert!(private_key.0.to_public_key() == public_key.key);
+        let PrivateKeyInner::Rsa(rsa_private_key) = &private_key.0 else {
+            panic!("default scheme should be RSA");
+        };
+        assert!(rsa_private_key.validate().is_ok());
+        assert!(PublicKeyInner::Rsa(rsa_private_key.to_public_key()) == public_key.key);
+    }
+
+    #[test]
+    fn keypair_size_test() {
+        for bits in [1024, 1536] {
+            let (private_key, public_key) = generate_keypair_with_size(bits);
+            let PrivateKeyInner::Rsa(rsa_private_key) = &private_key.0 else {
+                panic!("sized keys should be RSA");
+            };
+            assert_eq!(rsa_private_key.size() * 8, bits);
+            assert_eq!(public_key.scheme(), SignatureScheme::Rsa);
+
+            let signature = private_key.sign(b"Hello World!");
+            assert_eq!(signature.public_key, public_key);
+            assert!(signature.verify().is_ok());
+        }
     }
 
     #[test]
@@ -227,4 +521,155 @@ mod test {
 
         assert!(signature.verify().is_ok());
     }
+
+    #[test]
+    fn ed25519_sign_verify_test() {
+        let (private_key, public_key) = generate_keypair_with(SignatureScheme::Ed25519);
+        assert_eq!(public_key.scheme(), SignatureScheme::Ed25519);
+
+        let mut signature = private_key.sign(b"Hello World!");
+        assert!(signature.verify().is_ok());
+
+        // A signature of a different key must not verify
+        let (_, other_public_key) = generate_keypair_with(SignatureScheme::Ed25519);
+        signature.public_key = other_public_key;
+        assert!(signature.verify().is_err());
+    }
+
+    #[test]
+    fn malformed_signature_test() {
+        for scheme in [SignatureScheme::Rsa, SignatureScheme::Ed25519] {
+            let (private_key, _) = generate_keypair_with(scheme);
+            let mut signed = private_key.sign(b"Hello World!");
+            for garbage in [vec![], vec![1, 2, 3, 4, 5], vec![0xff; 1024]] {
+                signed.signature = garbage;
+                assert!(matches!(signed.verify(), Err(Error::InvalidSignature(_))));
+            }
+        }
+
+        // Unsigned data carries the placeholder key
+        let unsigned = Signed::new_invalid(42);
+        assert!(matches!(unsigned.verify(), Err(Error::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn public_key_roundtrip_test() {
+        for scheme in [SignatureScheme::Rsa, SignatureScheme::Ed25519] {
+            let (_, public_key) = generate_keypair_with(scheme);
+            let address = Address::from_public_key(&public_key);
+
+            let encoded = bincode::serialize(&public_key).unwrap();
+            let decoded: PublicKey = bincode::deserialize(&encoded).unwrap();
+            assert_eq!(decoded, public_key);
+            assert_eq!(Address::from_public_key(&decoded), address);
+        }
+    }
+
+    #[test]
+    fn canonical_digest_test() {
+        #[derive(Serialize)]
+        struct Balances {
+            name: &'static str,
+            balances: std::collections::BTreeMap<u64, u64>,
+        }
+        let ascending = Balances {
+            name: "balances",
+            balances: (0..100).map(|i| (i, i * 2)).collect(),
+        };
+        let descending = Balances {
+            name: "balances",
+            balances: (0..100).rev().map(|i| (i, i * 2)).collect(),
+        };
+        assert_eq!(Hash::digest(&ascending), Hash::digest(&descending));
+
+        // The address of a fixed key must never change, or nodes of different versions would
+        // disagree on the owner of every wallet
+        let key = ed25519_dalek::SigningKey::from_bytes(&[7; 32]);
+        let public_key = PrivateKey(PrivateKeyInner::Ed25519(key)).public_key();
+        let address = Address::from_public_key(&public_key);
+        assert_eq!(
+            address.to_string(),
+            "DafhxpyKKYU0KStRfzYYwSkHco0Ci3U7W3EBkTP3d5n_811e97"
+        );
+    }
+
+    #[test]
+    fn invalid_public_key_test() {
+        let encode = |modulus: u64, public_exponent: u64| {
+            let encoded = EncodedPublicKey::Rsa(EncodedRsaPublicKey {
+                modulus: BigUint::from(modulus).to_bytes_be(),
+                public_exponent: BigUint::from(public_exponent).to_bytes_be(),
+            });
+            serde_json::to_string(&encoded).unwrap()
+        };
+
+        // Zero and even moduli are rejected before reaching the RSA implementation
+        for (modulus, public_exponent) in [(0, 65537), (1 << 40, 65537), (0xff_ffff, 1)] {
+            let json = encode(modulus, public_exponent);
+            let err = serde_json::from_str::<PublicKey>(&json).unwrap_err();
+            assert!(err.is_data(), "{err}");
+        }
+
+        // The placeholder key of unsigned data still roundtrips
+        let json = serde_json::to_string(&PublicKey::invalid()).unwrap();
+        assert_eq!(json, encode(0, 0));
+        let decoded: PublicKey = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, PublicKey::invalid());
+    }
+
+    #[test]
+    fn address_checksum_test() {
+        let (_, public_key) = generate_keypair_with(SignatureScheme::Ed25519);
+        let address = Address::from_public_key(&public_key);
+
+        let displayed = address.to_string();
+        assert_eq!(displayed.parse::<Address>(), Ok(address.clone()));
+
+        // Mistyping any single character of the address is detected
+        for i in 0..displayed.len() {
+            let mut corrupted = displayed.clone().into_bytes();
+            corrupted[i] = match corrupted[i] {
+                b'_' => continue,
+                b'a' => b'b',
+                _ => b'a',
+            };
+            let corrupted = String::from_utf8(corrupted).unwrap();
+            assert!(corrupted.parse::<Address>().is_err(), "{corrupted}");
+        }
+
+        // Addresses without a checksum are only accepted explicitly
+        let (unchecked, _) = displayed.split_once('_').unwrap();
+        assert!(unchecked.parse::<Address>().is_err());
+        assert_eq!(Address::parse_unchecked(unchecked), Ok(address));
+    }
+
+    /// Compares the cost of the two signature schemes. On a typical machine generating an Ed25519
+    /// keypair is more than a thousand times faster than generating a 2048 bit RSA keypair and
+    /// signing is more than a hundred times faster.
+    #[test]
+    fn ed25519_speedup_test() {
+        const ROUNDS: u32 = 3;
+        let measure = |scheme| {
+            let start = std::time::Instant::now();
+            let mut private_key = None;
+            for _ in 0..ROUNDS {
+                private_key = Some(generate_keypair_with(scheme).0);
+            }
+            let keygen = start.elapsed() / ROUNDS;
+
+            let private_key = private_key.unwrap();
+            let start = std::time::Instant::now();
+            for i in 0..ROUNDS {
+                private_key.sign(i);
+            }
+            let signing = start.elapsed() / ROUNDS;
+            (keygen, signing)
+        };
+
+        let (rsa_keygen, rsa_signing) = measure(SignatureScheme::Rsa);
+        let (ed25519_keygen, ed25519_signing) = measure(SignatureScheme::Ed25519);
+
+        assert!(ed25519_keygen < rsa_keygen);
+        assert!(ed25519_signing < rsa_signing);
+    }
 }
@@ -1,5 +1,9 @@
+use std::io;
+
 use thiserror::Error;
 
+use crate::crypto::{Address, Hash};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Error, Debug)]
@@ -8,8 +12,103 @@ pub enum Error {
     InvalidSignature(#[from] rsa::signature::Error),
     #[error("the wallet does not have sufficient funds")]
     InsufficientFunds,
+    #[error("the transaction costs {0} BCC but only {1} BCC are available")]
+    CostExceedsFunds(u64, u64),
     #[error("expected nonce to be at least ${1} but was ${0}")]
     NonceReused(u64, u64),
     #[error("block signer is not the expected validator")]
     InvalidBlockValidator,
+    #[error("no wallet has staked any funds so no validator can be elected")]
+    NoStakers,
+    #[error("applying the block changes the total supply from {0} to {1}")]
+    SupplyMismatch(u64, u64),
+    #[error("block contains more than one transaction from {0:?} with nonce {1}")]
+    DuplicateNonce(Address, u64),
+    #[error("the mempool has no room for the transaction")]
+    MempoolFull,
+    #[error("{0} sent more transactions than the mempool accepts per interval")]
+    RateLimited(Address),
+    #[error("invalid genesis block: {0}")]
+    InvalidGenesis(&'static str),
+    #[error("invalid genesis configuration: {0}")]
+    InvalidGenesisConfig(&'static str),
+    #[error("the parent {0:?} of the block is not the tip of the blockchain")]
+    UnknownParent(Hash),
+    #[error("the block extends {0:?} instead of the tip {1:?}")]
+    InvalidParent(Hash, Hash),
+    #[error("invalid slashing evidence: {0}")]
+    InvalidEvidence(&'static str),
+    #[error("validator {0} minted two different blocks at height {1}")]
+    ValidatorEquivocation(Address, usize),
+    #[error("encryption error: {0}")]
+    Encryption(&'static str),
+    #[error("block {0} of the blockchain is invalid: {1}")]
+    InvalidChain(usize, Box<Error>),
+    #[error("only the system address can send genesis transactions")]
+    SystemTransaction,
+    #[error("observer nodes do not stake")]
+    ObserverStake,
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(&'static str),
+    #[error("the transaction is not signed by its sender")]
+    ForeignSigner,
+    #[error("the invalid address cannot receive transactions")]
+    InvalidRecipient,
+    #[error("block contains {0} transactions but the capacity is {1}")]
+    BlockOverCapacity(usize, usize),
+    #[error("the transactions of the block are not in canonical order")]
+    NonCanonicalOrder,
+    #[error("the node is draining and does not accept new transactions")]
+    NodeDraining,
+    #[error("the acknowledgement is not signed by the receiver of the transaction")]
+    InvalidAcknowledgement,
+}
+
+/// The ways bootstrapping a node can fail.
+#[derive(Error, Debug)]
+pub enum BootstrapError {
+    #[error("failed to listen for peer connections on {0}: {1}")]
+    Listen(String, io::Error),
+    #[error("the bootstrap server at {0} is unreachable: {1}")]
+    BootstrapUnreachable(String, io::Error),
+    #[error("the bootstrap server sent invalid discovery data: {0}")]
+    Discovery(io::Error),
+    #[error("expected {0} peers but the bootstrap server reported {1}")]
+    PeerCount(usize, usize),
+    #[error("the genesis validator {0} is not one of the peers")]
+    UnknownGenesisValidator(Address),
+    #[error("failed to load the TLS certificates: {0}")]
+    Tls(io::Error),
+    #[error("failed to connect to the peers: {0}")]
+    PeerConnection(io::Error),
+    #[error(transparent)]
+    Genesis(Error),
+}
+
+impl BootstrapError {
+    /// A suggestion for the operator on how to resolve the failure.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            BootstrapError::Listen(..) => {
+                "check that the listen host is an address of this machine"
+            }
+            BootstrapError::BootstrapUnreachable(..) => {
+                "check that the bootstrap leader is running and that every node uses its address"
+            }
+            BootstrapError::Discovery(_) => {
+                "check that the bootstrap address belongs to a blockchat node of the same version"
+            }
+            BootstrapError::PeerCount(..) => "every node must expect the same number of peers",
+            BootstrapError::UnknownGenesisValidator(_) => {
+                "the genesis validator must be the address of one of the peers"
+            }
+            BootstrapError::Tls(_) => "check the paths and the contents of the TLS files",
+            BootstrapError::PeerConnection(_) => {
+                "check that every peer started and that the connections between them are allowed"
+            }
+            BootstrapError::Genesis(_) => {
+                "the genesis validator must have funds and the block capacity must be positive"
+            }
+        }
+    }
 }
@@ -0,0 +1,160 @@
+//! The configuration of the initial state of a BlockChat network.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{Address, Hash, Signed};
+use crate::error::{Error, Result};
+use crate::node::Block;
+use crate::wallet::{FeeSchedule, Transaction, TransactionKind};
+
+/// The parent hash of every genesis block, since it has no actual parent.
+pub const GENESIS_PARENT_HASH: Hash = {
+    let mut hash = [0; 32];
+    hash[31] = 1;
+    Hash(hash)
+};
+
+/// Describes the genesis block of a network. Nodes constructed from the same configuration agree
+/// on the genesis block and therefore on the rest of the blockchain.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct GenesisConfig {
+    /// The address of the wallet that validates the first block.
+    pub validator: Address,
+    /// The initial balance of each address.
+    pub balances: BTreeMap<Address, u64>,
+    /// The maximum number of transactions contained in each block after the genesis block. Nodes
+    /// reject larger blocks, so all the nodes of a network must use the same capacity.
+    pub capacity: usize,
+    /// The fee model of the network.
+    #[serde(default)]
+    pub fee_schedule: FeeSchedule,
+    /// The stake burned from a validator that signs a block with invalid transactions, once a
+    /// later block carries the invalid block as evidence. Slashing is disabled when zero.
+    #[serde(default)]
+    pub slashing_penalty: u64,
+    /// The number of blocks the validators are elected for at once. The stakes and the seed of the
+    /// election are fixed for the whole epoch, which spreads the blocks of an epoch across the
+    /// stakers in proportion to their stake. With the default of one, every block reseeds the
+    /// election.
+    #[serde(default = "default_epoch_length")]
+    pub epoch_length: usize,
+}
+
+fn default_epoch_length() -> usize {
+    1
+}
+
+impl GenesisConfig {
+    /// Creates a configuration where the validator receives all the initial funds.
+    pub fn new(validator: Address, genesis_funds: u64, capacity: usize) -> Self {
+        Self {
+            balances: BTreeMap::from([(validator.clone(), genesis_funds)]),
+            validator,
+            capacity,
+            fee_schedule: FeeSchedule::default(),
+            slashing_penalty: 0,
+            epoch_length: default_epoch_length(),
+        }
+    }
+
+    /// Loads a JSON encoded configuration from the provided path.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+
+    /// Checks that a network can start from this configuration. The genesis validator stakes one
+    /// coin of its initial balance to validate the first blocks, so it must receive some funds.
+    pub fn validate(&self) -> Result<()> {
+        if self.capacity == 0 {
+            return Err(Error::InvalidGenesisConfig("the block capacity is zero"));
+        }
+        if self.balances.get(&self.validator).copied().unwrap_or(0) == 0 {
+            return Err(Error::InvalidGenesisConfig(
+                "the genesis validator has no funds",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Constructs the genesis block described by this configuration. The initial balances are
+    /// minted by one genesis transaction per address.
+    pub fn block(&self) -> Signed<Block> {
+        let transactions = self
+            .balances
+            .iter()
+            .enumerate()
+            .map(|(nonce, (address, amount))| {
+                Signed::new_invalid(Transaction {
+                    sender_address: Address::invalid(),
+                    kind: TransactionKind::Genesis(*amount, address.clone()),
+                    nonce: nonce as u64,
+                })
+            })
+            .collect();
+
+        let block = Block {
+            timestamp: DateTime::<Utc>::MIN_UTC,
+            transactions,
+            validator: Address::invalid(),
+            parent_hash: GENESIS_PARENT_HASH,
+            evidence: vec![],
+        };
+        Signed::new_invalid(block)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto;
+    use crate::node::Node;
+
+    use super::*;
+
+    #[test]
+    fn same_genesis_file_same_genesis_block() {
+        let (_, validator_public_key) = crypto::generate_test_keypair();
+        let (_, user_public_key) = crypto::generate_test_keypair();
+        let validator = Address::from_public_key(&validator_public_key);
+        let mut genesis = GenesisConfig::new(validator, 1000, 5);
+        genesis
+            .balances
+            .insert(Address::from_public_key(&user_public_key), 500);
+
+        let path = std::env::temp_dir().join("blockchat_same_genesis_file.json");
+        std::fs::write(&path, serde_json::to_string(&genesis).unwrap()).unwrap();
+
+        let nodes: Vec<_> = (0..2)
+            .map(|i| {
+                let (private_key, public_key) = crypto::generate_test_keypair();
+                let genesis = GenesisConfig::from_file(&path).unwrap();
+                Node::from_genesis(format!("node-{i}"), public_key, private_key, genesis).unwrap()
+            })
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let genesis_block = &nodes[0].blockchain()[0];
+        assert_eq!(genesis_block.data.transactions.len(), 2);
+        assert_eq!(genesis_block.hash, nodes[1].blockchain()[0].hash);
+        assert_eq!(genesis_block.hash, genesis.block().hash);
+    }
+
+    #[test]
+    fn unfunded_genesis_validator() {
+        let (private_key, public_key) = crypto::generate_test_keypair();
+        let (_, user_public_key) = crypto::generate_test_keypair();
+        let validator = Address::from_public_key(&public_key);
+        let user = Address::from_public_key(&user_public_key);
+        let genesis = GenesisConfig {
+            balances: BTreeMap::from([(user, 1000)]),
+            ..GenesisConfig::new(validator, 0, 5)
+        };
+
+        let result = Node::from_genesis("node".into(), public_key, private_key, genesis);
+        assert!(matches!(result, Err(Error::InvalidGenesisConfig(_))));
+    }
+}
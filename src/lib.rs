@@ -1,9 +1,16 @@
 #![allow(clippy::single_match)]
 
+pub mod api;
+pub mod bench;
 pub mod bootstrap;
 pub mod cli;
+pub mod cluster;
 pub mod crypto;
 pub mod error;
+pub mod genesis;
+pub mod logging;
 pub mod network;
 pub mod node;
+pub mod sim;
+pub mod snapshot;
 pub mod wallet;
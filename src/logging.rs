@@ -0,0 +1,129 @@
+//! Configuration of the log output of the binaries.
+
+use std::fmt;
+use std::str::FromStr;
+
+use tracing::Subscriber;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// The formats logs can be written in.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, suitable for aggregating the logs of several nodes.
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format: {s}")),
+        }
+    }
+}
+
+/// Constructs a subscriber writing the events of at least the provided level to `writer`.
+pub fn subscriber<W>(
+    level: LevelFilter,
+    format: LogFormat,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(builder.finish()),
+        LogFormat::Json => Box::new(builder.json().finish()),
+    }
+}
+
+/// Installs a subscriber writing the events of at least the provided level to stdout as the
+/// global default. Records of the `log` crate are forwarded to it too.
+pub fn init(level: LevelFilter, format: LogFormat) {
+    subscriber(level, format, std::io::stdout).init();
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// Collects the log output of a test.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl LogBuffer {
+        fn lines(&self) -> Vec<String> {
+            let output = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+            output.lines().map(str::to_owned).collect()
+        }
+    }
+
+    #[test]
+    fn level_and_format() {
+        let level = "warn".parse::<LevelFilter>().unwrap();
+
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let logs = subscriber(level, LogFormat::Text, move || writer.clone());
+        tracing::subscriber::with_default(logs, || {
+            tracing::info!("filtered out");
+            tracing::warn!("kept");
+        });
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        assert!(
+            lines[0].contains("WARN") && lines[0].contains("kept"),
+            "{lines:?}"
+        );
+
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let format = "json".parse::<LogFormat>().unwrap();
+        let logs = subscriber(level, format, move || writer.clone());
+        tracing::subscriber::with_default(logs, || {
+            tracing::debug!("filtered out");
+            tracing::error!(height = 3, "kept");
+        });
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 1);
+        let event: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(event["level"], "ERROR");
+        assert_eq!(event["fields"]["message"], "kept");
+        assert_eq!(event["fields"]["height"], 3);
+
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+}
@@ -1,12 +1,17 @@
-use std::io::{BufReader, Write};
+use std::collections::BTreeMap;
+use std::io::{self, BufReader};
 use std::net::TcpStream;
-use std::sync::mpsc::{self, Receiver, Sender};
-use std::time::Duration;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use serde::{de::DeserializeOwned, Serialize};
 
 pub mod broadcast;
 pub mod discovery;
+mod frame;
+pub mod tls;
 
 /// A wrapper over a TCP connection that is able to send and receive typed data
 struct TypedStream {
@@ -21,13 +26,14 @@ impl TypedStream {
         }
     }
 
-    fn send<T: Serialize>(&mut self, msg: &T) {
-        bincode::serialize_into(self.stream.get_mut(), &msg).unwrap();
-        self.stream.get_mut().flush().unwrap();
+    fn send<T: Serialize>(&mut self, msg: &T) -> io::Result<()> {
+        frame::write_frame(self.stream.get_mut(), msg)
     }
 
-    fn recv<T: DeserializeOwned>(&mut self) -> T {
-        bincode::deserialize_from(&mut self.stream).unwrap()
+    /// Receives the next message. Returns an error if the connection fails or the peer sends a
+    /// malformed frame.
+    fn recv<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        frame::read_frame(&mut self.stream)
     }
 }
 
@@ -41,47 +47,245 @@ pub trait Network<T> {
 
 /// An in-memory testing network to help with unit testing
 pub struct TestNetwork<T> {
-    rx: Receiver<T>,
-    tx: Sender<T>,
-    buffer: Option<T>,
+    rx: Receiver<(Instant, T)>,
+    txs: Vec<Sender<(Instant, T)>>,
+    /// The received messages keyed by the time they are delivered at and the order they were
+    /// received in, so that messages due at the same time keep their order.
+    pending: BTreeMap<(Instant, u64), T>,
+    received: u64,
+    conditions: TestNetworkBuilder,
+    rng: StdRng,
+    dropped: usize,
+}
+
+/// Configures the latency and the loss of the links of test networks. By default messages are
+/// delivered instantly and reliably.
+#[derive(Debug, Clone, Default)]
+pub struct TestNetworkBuilder {
+    min_latency: Duration,
+    max_latency: Duration,
+    drop_rate: f64,
+    seed: u64,
+}
+
+impl TestNetworkBuilder {
+    /// Delays each message by a random duration between `min` and `max`. Messages with different
+    /// delays can be delivered out of order.
+    pub fn latency(mut self, min: Duration, max: Duration) -> Self {
+        assert!(min <= max, "the minimum latency exceeds the maximum");
+        self.min_latency = min;
+        self.max_latency = max;
+        self
+    }
+
+    /// Drops each message sent over a link with probability `rate`.
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        assert!((0.0..=1.0).contains(&rate), "invalid drop rate {rate}");
+        self.drop_rate = rate;
+        self
+    }
+
+    /// Seeds the randomness of the latencies and the drops so that runs are reproducible.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Constructs the networks of two linked peers.
+    pub fn pair<T>(self) -> (TestNetwork<T>, TestNetwork<T>) {
+        let mut networks = self.from_links(2, &[(0, 1)]);
+        let network2 = networks.pop().unwrap();
+        let network1 = networks.pop().unwrap();
+        (network1, network2)
+    }
+
+    /// Constructs the networks of `count` peers where each peer only reaches the peers it shares a
+    /// link with.
+    pub fn from_links<T>(self, count: usize, links: &[(usize, usize)]) -> Vec<TestNetwork<T>> {
+        let (txs, rxs): (Vec<_>, Vec<_>) = (0..count).map(|_| mpsc::channel()).unzip();
+        let mut networks: Vec<_> = rxs
+            .into_iter()
+            .enumerate()
+            .map(|(index, rx)| TestNetwork {
+                rx,
+                txs: vec![],
+                pending: BTreeMap::new(),
+                received: 0,
+                conditions: self.clone(),
+                rng: StdRng::seed_from_u64(self.seed.wrapping_add(index as u64)),
+                dropped: 0,
+            })
+            .collect();
+        for &(a, b) in links {
+            networks[a].txs.push(txs[b].clone());
+            networks[b].txs.push(txs[a].clone());
+        }
+        networks
+    }
 }
 
 impl<T> TestNetwork<T> {
     pub fn new() -> (Self, Self) {
-        let (tx1, rx1) = mpsc::channel();
-        let (tx2, rx2) = mpsc::channel();
-        let network1 = Self {
-            rx: rx1,
-            tx: tx2,
-            buffer: None,
-        };
-        let network2 = Self {
-            rx: rx2,
-            tx: tx1,
-            buffer: None,
-        };
-        (network1, network2)
+        TestNetworkBuilder::default().pair()
+    }
+
+    /// Constructs the networks of `count` peers where each peer only reaches the peers it shares a
+    /// link with.
+    pub fn from_links(count: usize, links: &[(usize, usize)]) -> Vec<Self> {
+        TestNetworkBuilder::default().from_links(count, links)
+    }
+
+    /// The number of messages this peer sent that were lost.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    fn enqueue(&mut self, (at, msg): (Instant, T)) {
+        self.pending.insert((at, self.received), msg);
+        self.received += 1;
+    }
+
+    /// Moves the messages waiting in the channel to the pending queue.
+    fn drain_channel(&mut self) {
+        while let Ok(delivery) = self.rx.try_recv() {
+            self.enqueue(delivery);
+        }
+    }
+
+    /// The time the next pending message is delivered at.
+    fn next_delivery(&self) -> Option<Instant> {
+        self.pending.keys().next().map(|(at, _)| *at)
     }
 }
 
 impl<T: Send + Clone> Network<T> for TestNetwork<T> {
     fn await_events(&mut self, timeout: Option<Duration>) {
-        if self.buffer.is_none() {
-            self.buffer = match timeout {
-                Some(timeout) => self.rx.recv_timeout(timeout).ok(),
-                None => self.rx.recv().ok(),
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            self.drain_channel();
+            let now = Instant::now();
+            let next_delivery = self.next_delivery();
+            if next_delivery.is_some_and(|at| at <= now) {
+                return;
+            }
+            let wake_up = match (next_delivery, deadline) {
+                (Some(at), Some(deadline)) => Some(at.min(deadline)),
+                (at, deadline) => at.or(deadline),
+            };
+            let result = match wake_up {
+                Some(wake_up) if wake_up <= now => return,
+                Some(wake_up) => self.rx.recv_timeout(wake_up - now),
+                None => self.rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
             };
+            match result {
+                Ok(delivery) => self.enqueue(delivery),
+                Err(RecvTimeoutError::Timeout) => {}
+                // Without peers only the already pending messages can still be delivered
+                Err(RecvTimeoutError::Disconnected) => match wake_up {
+                    Some(wake_up) if next_delivery.is_some() => {
+                        std::thread::sleep(wake_up.saturating_duration_since(now))
+                    }
+                    _ => return,
+                },
+            }
         }
     }
 
     fn recv(&mut self) -> Option<T> {
-        match self.buffer.take() {
-            Some(msg) => Some(msg),
-            None => self.rx.try_recv().ok(),
+        self.drain_channel();
+        match self.pending.first_entry() {
+            Some(entry) if entry.key().0 <= Instant::now() => Some(entry.remove()),
+            _ => None,
         }
     }
 
     fn send(&mut self, msg: &T) {
-        self.tx.send(msg.clone()).unwrap();
+        let TestNetworkBuilder {
+            min_latency,
+            max_latency,
+            drop_rate,
+            ..
+        } = self.conditions;
+        for tx in self.txs.iter() {
+            if drop_rate > 0.0 && self.rng.gen_bool(drop_rate) {
+                self.dropped += 1;
+                continue;
+            }
+            let latency = if min_latency < max_latency {
+                self.rng.gen_range(min_latency..=max_latency)
+            } else {
+                min_latency
+            };
+            tx.send((Instant::now() + latency, msg.clone())).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Returns a typed stream reading whatever is written to the returned TCP stream.
+    fn stream_pair() -> (TypedStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let writer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let reader = TypedStream::new(listener.accept().unwrap().0);
+        (reader, writer)
+    }
+
+    #[test]
+    fn latency_and_loss() {
+        let (mut network1, mut network2) = TestNetworkBuilder::default()
+            .latency(Duration::from_millis(10), Duration::from_millis(30))
+            .seed(7)
+            .pair();
+        for i in 0..20 {
+            network1.send(&i);
+        }
+        // Nothing arrives before the minimum latency
+        assert_eq!(network2.recv(), None);
+        let mut received = vec![];
+        while received.len() < 20 {
+            network2.await_events(Some(Duration::from_secs(1)));
+            while let Some(msg) = network2.recv() {
+                received.push(msg);
+            }
+        }
+        // The random latencies reorder the messages
+        assert_ne!(received, (0..20).collect::<Vec<_>>());
+        received.sort();
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+
+        let (mut network1, mut network2) = TestNetworkBuilder::default().drop_rate(1.0).pair();
+        network1.send(&1);
+        network2.await_events(Some(Duration::from_millis(10)));
+        assert_eq!(network2.recv(), None);
+        assert_eq!(network1.dropped(), 1);
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let (mut reader, mut writer) = stream_pair();
+        // A raw frame announcing a 100 byte payload that is cut short
+        writer.write_all(&[0, 0, 0, 0, 100, 1, 2, 3]).unwrap();
+        drop(writer);
+
+        let err = reader.recv::<Vec<u64>>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn malformed_payload() {
+        let (mut reader, mut writer) = stream_pair();
+        // A complete frame whose payload is not a valid string
+        writer
+            .write_all(&[0, 0, 0, 0, 9, 1, 0, 0, 0, 0, 0, 0, 0, 0xff])
+            .unwrap();
+
+        let err = reader.recv::<String>().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
     }
 }
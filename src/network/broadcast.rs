@@ -1,61 +1,265 @@
 //! Implementation of a broadcasting network
 
-use std::io::{BufReader, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
 use std::time::Duration;
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::network::Network;
+use crate::network::tls::{TlsConfig, TlsContext};
+use crate::network::{frame, Network};
+
+/// The timeouts of the connections to the peers. A peer that doesn't complete a read or write
+/// within its timeout is considered disconnected, which keeps a half-open connection from blocking
+/// a thread forever.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionTimeouts {
+    /// The maximum time to wait for the next message of a peer.
+    pub read: Option<Duration>,
+    /// The maximum time to wait for a message to be sent to a peer.
+    pub write: Option<Duration>,
+}
+
+impl Default for ConnectionTimeouts {
+    fn default() -> Self {
+        // Validators mint a block every second by default so a minute without messages means the
+        // peer is gone
+        Self {
+            read: Some(Duration::from_secs(60)),
+            write: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+impl ConnectionTimeouts {
+    /// Applies the timeouts to the reads and writes of `socket`.
+    pub(crate) fn apply(&self, socket: &TcpStream) -> io::Result<()> {
+        socket.set_read_timeout(self.read)?;
+        socket.set_write_timeout(self.write)
+    }
+}
+
+/// How persistently a node connects to the peers that are expected to accept its connections.
+/// Peers may start at different times, so failed attempts are retried with an exponentially
+/// growing delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionRetries {
+    /// The number of attempts at connecting to a peer before giving up.
+    pub attempts: u32,
+    /// The delay after the first failed attempt. It doubles after every subsequent failure.
+    pub initial_backoff: Duration,
+    /// The longest delay between two attempts.
+    pub max_backoff: Duration,
+}
+
+impl Default for ConnectionRetries {
+    fn default() -> Self {
+        // Gives the peers about half a minute to come up
+        Self {
+            attempts: 10,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ConnectionRetries {
+    /// The delay after the provided failed attempt, counting from 1.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    /// Connects to the first reachable address of a server, retrying failed attempts with an
+    /// exponential backoff. Returns the error of the last attempt if all of them fail.
+    pub(crate) fn connect(&self, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        let server = match addrs {
+            [addr] => addr.to_string(),
+            addrs => format!("{addrs:?}"),
+        };
+        let max_attempts = self.attempts.max(1);
+        let mut attempt = 1;
+        loop {
+            log::debug!("connecting to {server} attempt {attempt}/{max_attempts}");
+            match TcpStream::connect(addrs) {
+                Ok(stream) => return Ok(stream),
+                Err(error) if attempt < max_attempts => {
+                    let backoff = self.backoff(attempt);
+                    log::warn!("Failed connecting to {server}, retrying in {backoff:?}: {error}");
+                    std::thread::sleep(backoff);
+                    attempt += 1;
+                }
+                Err(error) => {
+                    let msg = format!("failed connecting to {server} after {attempt} attempts");
+                    log::error!("{msg}: {error}");
+                    return Err(io::Error::new(error.kind(), format!("{msg}: {error}")));
+                }
+            }
+        }
+    }
+}
+
+/// The number of open connections to the peers of a `Broadcaster`. A connection is closed once
+/// reading from it fails, e.g. because the peer exited or timed out.
+#[derive(Debug, Clone, Default)]
+pub struct PeerConnections(Arc<AtomicUsize>);
+
+impl PeerConnections {
+    /// The number of connections that are still open.
+    pub fn open(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
 
 pub struct Broadcaster<T> {
     write_txs: Vec<Sender<T>>,
     read_rx: Receiver<T>,
     buffer: Option<T>,
+    connections: PeerConnections,
 }
 
 impl<T: Serialize + DeserializeOwned + Clone + Send + 'static> Broadcaster<T> {
-    pub fn new(listener: TcpListener, peers: &[SocketAddr], my_index: usize) -> Self {
-        let sockets = std::thread::scope(|s| {
-            let start_task = s.spawn(|| start_connections(&peers[..my_index]));
-            let await_task = s.spawn(|| await_connections(&listener, peers.len() - my_index - 1));
-
-            let mut sockets = start_task.join().unwrap();
-            sockets.extend(await_task.join().unwrap());
-            sockets
-        });
+    /// Connects to all the peers. Returns an error if one of the peers can't be reached.
+    pub fn new(listener: TcpListener, peers: &[SocketAddr], my_index: usize) -> io::Result<Self> {
+        Self::with_timeouts(
+            listener,
+            peers,
+            my_index,
+            ConnectionTimeouts::default(),
+            ConnectionRetries::default(),
+        )
+    }
+
+    pub fn with_timeouts(
+        listener: TcpListener,
+        peers: &[SocketAddr],
+        my_index: usize,
+        timeouts: ConnectionTimeouts,
+        retries: ConnectionRetries,
+    ) -> io::Result<Self> {
+        Self::connect(listener, peers, my_index, timeouts, retries, None)
+    }
+
+    /// Like `with_timeouts` but encrypts the connections to the peers with TLS. Returns an error
+    /// if the certificates can't be loaded.
+    pub fn with_tls(
+        listener: TcpListener,
+        peers: &[SocketAddr],
+        my_index: usize,
+        timeouts: ConnectionTimeouts,
+        retries: ConnectionRetries,
+        tls: &TlsConfig,
+    ) -> io::Result<Self> {
+        let tls = tls.load()?;
+        Self::connect(listener, peers, my_index, timeouts, retries, Some(tls))
+    }
+
+    pub(crate) fn connect(
+        listener: TcpListener,
+        peers: &[SocketAddr],
+        my_index: usize,
+        timeouts: ConnectionTimeouts,
+        retries: ConnectionRetries,
+        tls: Option<TlsContext>,
+    ) -> io::Result<Self> {
+        // Each socket is paired with whether this node initiated the connection
+        let expected = peers[my_index + 1..].to_vec();
+        let await_task = std::thread::spawn(move || await_connections(&listener, &expected));
+        // If a peer can't be reached the node gives up without waiting for the others. The
+        // accepting thread is left behind and exits once the remaining peers connect.
+        let mut sockets: Vec<_> = start_connections(&peers[..my_index], retries)?
+            .into_iter()
+            .map(|socket| (true, socket))
+            .collect();
+        sockets.extend(
+            await_task
+                .join()
+                .unwrap()
+                .into_iter()
+                .map(|socket| (false, socket)),
+        );
 
         let (read_tx, read_rx) = mpsc::channel();
         let mut write_txs = vec![];
-        for mut socket in sockets {
-            let mut read_socket = BufReader::new(socket.try_clone().unwrap());
+        let connections = PeerConnections::default();
+        for (initiator, socket) in sockets {
+            timeouts
+                .apply(&socket)
+                .expect("setting the socket timeouts failed");
+            let (reader, mut writer): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match &tls {
+                Some(tls) => match tls.wrap(&socket, initiator) {
+                    Ok((reader, writer)) => (Box::new(reader), Box::new(writer)),
+                    Err(err) => {
+                        log::error!("Failed to start TLS session, disconnecting peer: {err}");
+                        let _ = socket.shutdown(Shutdown::Both);
+                        continue;
+                    }
+                },
+                None => (
+                    Box::new(socket.try_clone().unwrap()),
+                    Box::new(socket.try_clone().unwrap()),
+                ),
+            };
+
+            let mut reader = BufReader::new(reader);
+            let read_socket = socket.try_clone().unwrap();
             let read_tx = read_tx.clone();
+            let open = Arc::clone(&connections.0);
+            open.fetch_add(1, Ordering::SeqCst);
             std::thread::spawn(move || loop {
-                match bincode::deserialize_from(&mut read_socket) {
-                    Ok(msg) => read_tx.send(msg).unwrap(),
+                match frame::read_payload(&mut reader) {
+                    // A peer running a newer version may send messages this version doesn't
+                    // know about. The frame was fully read so the stream is still usable.
+                    Ok(payload) => match frame::decode(&payload) {
+                        Ok(msg) => {
+                            if read_tx.send(msg).is_err() {
+                                open.fetch_sub(1, Ordering::SeqCst);
+                                return;
+                            }
+                        }
+                        Err(err) => log::warn!("Skipping undecodable message: {err}"),
+                    },
                     Err(err) => {
-                        log::error!("Connection error: {err}");
+                        // A timeout may have interrupted a partially read message so the stream
+                        // can't be resumed. Shutting it down also stops the writer.
+                        log::error!("Connection error, disconnecting peer: {err}");
+                        let _ = read_socket.shutdown(Shutdown::Both);
+                        open.fetch_sub(1, Ordering::SeqCst);
                         return;
                     }
                 }
             });
 
-            let (write_tx, write_rx) = mpsc::channel();
+            let (write_tx, write_rx) = mpsc::channel::<T>();
             std::thread::spawn(move || {
                 while let Ok(msg) = write_rx.recv() {
-                    bincode::serialize_into(&mut socket, &msg).unwrap();
-                    socket.flush().unwrap();
+                    if let Err(err) = frame::write_frame(&mut writer, &msg) {
+                        log::error!("Connection error, disconnecting peer: {err}");
+                        let _ = socket.shutdown(Shutdown::Both);
+                        return;
+                    }
                 }
             });
             write_txs.push(write_tx);
         }
-        Self {
+        Ok(Self {
             write_txs,
             read_rx,
             buffer: None,
-        }
+            connections,
+        })
+    }
+
+    /// The number of open connections to the peers, which keeps being updated after the
+    /// broadcaster is moved to the thread running the node.
+    pub fn connections(&self) -> PeerConnections {
+        self.connections.clone()
     }
 }
 
@@ -77,59 +281,212 @@ impl<T: Serialize + DeserializeOwned + Clone + Send + 'static> Network<T> for Br
     }
 
     fn send(&mut self, msg: &T) {
-        for write_tx in self.write_txs.iter_mut() {
-            write_tx.send(msg.clone()).unwrap();
-        }
+        // The writers of disconnected peers have exited so their channels are dropped
+        self.write_txs
+            .retain(|write_tx| write_tx.send(msg.clone()).is_ok());
     }
 }
 
-/// Connects to the provided list of peers. Returns the established TCP streams.
-fn start_connections(peers: &[SocketAddr]) -> Vec<TcpStream> {
-    let max_attempts = 5;
+/// Connects to the provided list of peers, retrying failed attempts with an exponential backoff.
+/// Returns the established TCP streams, or an error if one of the peers can't be reached.
+fn start_connections(
+    peers: &[SocketAddr],
+    retries: ConnectionRetries,
+) -> io::Result<Vec<TcpStream>> {
     let mut streams = vec![];
-    'peers: for peer in peers {
-        // Make 5 attempts at connecting
-        // TODO(petrosagg): Replace with the retry crate
-        for attempt in 1..=max_attempts {
-            log::debug!("connecting to {peer} attempt {attempt}/{max_attempts}");
-
-            match TcpStream::connect(peer) {
-                Ok(stream) => {
-                    log::info!("connected to {peer}");
-                    stream.set_nodelay(true).expect("set_nodelay call failed");
-                    streams.push(stream);
-                    continue 'peers;
-                }
-                Err(error) => {
-                    log::warn!("Failed connecting to {peer}: {error}");
-                    std::thread::sleep(Duration::from_millis(200));
-                }
-            }
-        }
-        log::error!(
-            "Failed connecting to {peer} after {} attempts",
-            max_attempts
-        );
+    for peer in peers {
+        let stream = retries.connect(std::slice::from_ref(peer))?;
+        log::info!("connected to {peer}");
+        stream.set_nodelay(true).expect("set_nodelay call failed");
+        streams.push(stream);
     }
-    streams
+    Ok(streams)
 }
 
-/// Waits for the expected number of peers to connect. Returns the established TCP streams.
-fn await_connections(listener: &TcpListener, expected_peers: usize) -> Vec<TcpStream> {
+/// Waits for the provided peers to connect. Connections from addresses that don't belong to any
+/// of the peers that are still expected are closed, so that stray connections can't take the place
+/// of a peer. Returns the established TCP streams.
+fn await_connections(listener: &TcpListener, peers: &[SocketAddr]) -> Vec<TcpStream> {
+    let mut expected = peers.to_vec();
     let mut streams = vec![];
 
-    for _ in 0..expected_peers {
-        let stream = listener.accept().unwrap().0;
+    while !expected.is_empty() {
+        let (stream, remote) = listener.accept().unwrap();
+        let Some(position) = expected
+            .iter()
+            .position(|peer| is_expected_ip(peer.ip(), remote.ip()))
+        else {
+            log::warn!("Rejecting unexpected connection from {remote}");
+            let _ = stream.shutdown(Shutdown::Both);
+            continue;
+        };
+        log::info!("accepted connection from {remote}");
+        expected.swap_remove(position);
         stream.set_nodelay(true).expect("set_nodelay call failed");
         streams.push(stream);
     }
     streams
 }
 
+/// Reports whether a connection from `remote` may come from a peer listening on `expected`. A peer
+/// listening on all interfaces may connect from any of its addresses.
+fn is_expected_ip(expected: IpAddr, remote: IpAddr) -> bool {
+    expected.is_unspecified() || expected.to_canonical() == remote.to_canonical()
+}
+
 #[cfg(test)]
 mod test {
+    use serde::Deserialize;
+
     use super::*;
 
+    #[test]
+    fn silent_peer_times_out() {
+        // The silent peer accepts the connection but never sends anything
+        let silent_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [
+            silent_listener.local_addr().unwrap(),
+            listener.local_addr().unwrap(),
+        ];
+        let timeouts = ConnectionTimeouts {
+            read: Some(Duration::from_millis(100)),
+            write: Some(Duration::from_millis(100)),
+        };
+        let retries = ConnectionRetries::default();
+        let mut peer =
+            Broadcaster::<usize>::with_timeouts(listener, &addrs, 1, timeouts, retries).unwrap();
+        let connections = peer.connections();
+
+        // Once the only reader disconnects, waiting for events returns instead of hanging
+        let (done_tx, done_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            peer.await_events(None);
+            done_tx.send(peer.recv()).unwrap();
+        });
+        let received = done_rx.recv_timeout(Duration::from_secs(10));
+        assert_eq!(received, Ok(None));
+        assert_eq!(connections.open(), 0);
+        drop(silent_listener);
+    }
+
+    #[test]
+    fn unknown_message_is_skipped() {
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        enum OldMessage {
+            Number(u64),
+        }
+        #[derive(Serialize)]
+        enum NewMessage {
+            Number(u64),
+            Text(String),
+        }
+
+        let newer_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [
+            newer_listener.local_addr().unwrap(),
+            listener.local_addr().unwrap(),
+        ];
+        let mut peer = Broadcaster::<OldMessage>::new(listener, &addrs, 1).unwrap();
+
+        // A peer running a newer version sends a message the older peer can't decode
+        let mut newer = newer_listener.accept().unwrap().0;
+        frame::write_frame(&mut newer, &NewMessage::Text("hello".into())).unwrap();
+        frame::write_frame(&mut newer, &NewMessage::Number(42)).unwrap();
+
+        peer.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(peer.recv(), Some(OldMessage::Number(42)));
+    }
+
+    #[test]
+    fn unexpected_connection_is_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let peer_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [
+            listener.local_addr().unwrap(),
+            peer_listener.local_addr().unwrap(),
+        ];
+
+        // A stranger connects from another loopback address before the expected peer does
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut stranger = runtime.block_on(async {
+            let socket = tokio::net::TcpSocket::new_v4().unwrap();
+            socket.bind("127.0.0.2:0".parse().unwrap()).unwrap();
+            let stream = socket.connect(addrs[0]).await.unwrap();
+            let stream = stream.into_std().unwrap();
+            stream.set_nonblocking(false).unwrap();
+            stream
+        });
+
+        let node = std::thread::spawn(move || Broadcaster::<usize>::new(listener, &addrs, 0));
+        let mut peer = Broadcaster::<usize>::new(peer_listener, &addrs, 1).unwrap();
+        let mut node = node.join().unwrap().unwrap();
+
+        // The stranger is disconnected and the peer took its place in the mesh
+        stranger
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        assert_eq!(stranger.read(&mut [0; 1]).unwrap(), 0);
+        peer.send(&42);
+        node.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(node.recv(), Some(42));
+        node.send(&7);
+        peer.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(peer.recv(), Some(7));
+    }
+
+    #[test]
+    fn late_peer_is_retried() {
+        // Reserve an address for a peer that only starts listening after a while
+        let late_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [late_addr, listener.local_addr().unwrap()];
+        let retries = ConnectionRetries {
+            attempts: 10,
+            initial_backoff: Duration::from_millis(20),
+            max_backoff: Duration::from_millis(200),
+        };
+        assert_eq!(retries.backoff(1), Duration::from_millis(20));
+        assert_eq!(retries.backoff(3), Duration::from_millis(80));
+        assert_eq!(retries.backoff(9), Duration::from_millis(200));
+
+        let late = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            let listener = TcpListener::bind(late_addr).unwrap();
+            Broadcaster::<usize>::new(listener, &addrs, 0).unwrap()
+        });
+        let timeouts = ConnectionTimeouts::default();
+        let mut peer =
+            Broadcaster::<usize>::with_timeouts(listener, &addrs, 1, timeouts, retries).unwrap();
+        let mut late = late.join().unwrap();
+
+        peer.send(&42);
+        late.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(late.recv(), Some(42));
+        assert_eq!(peer.connections().open(), 1);
+    }
+
+    #[test]
+    fn unreachable_peer_fails() {
+        // Ephemeral ports are never privileged, so no other test can start listening on it
+        let unreachable_addr = "127.0.0.1:1".parse().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [unreachable_addr, listener.local_addr().unwrap()];
+        let retries = ConnectionRetries {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+        let timeouts = ConnectionTimeouts::default();
+        let result = Broadcaster::<usize>::with_timeouts(listener, &addrs, 1, timeouts, retries);
+        let err = result.err().unwrap();
+        assert!(err.to_string().contains("after 3 attempts"), "{err}");
+    }
+
     #[test]
     fn basic_networking() {
         let addrs = [
@@ -140,18 +497,18 @@ mod test {
         std::thread::scope(|s| {
             s.spawn(|| {
                 let listener = TcpListener::bind(addrs[0]).unwrap();
-                let mut peer = Broadcaster::<usize>::new(listener, &addrs, 0);
+                let mut peer = Broadcaster::<usize>::new(listener, &addrs, 0).unwrap();
                 peer.await_events(None);
                 assert_eq!(peer.recv(), Some(42));
             });
             s.spawn(|| {
                 let listener = TcpListener::bind(addrs[1]).unwrap();
-                let mut peer = Broadcaster::<usize>::new(listener, &addrs, 1);
+                let mut peer = Broadcaster::<usize>::new(listener, &addrs, 1).unwrap();
                 peer.send(&42);
             });
             s.spawn(|| {
                 let listener = TcpListener::bind(addrs[2]).unwrap();
-                let mut peer = Broadcaster::<usize>::new(listener, &addrs, 2);
+                let mut peer = Broadcaster::<usize>::new(listener, &addrs, 2).unwrap();
                 peer.await_events(None);
                 assert_eq!(peer.recv(), Some(42));
             });
@@ -1,91 +1,323 @@
-use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::time::Duration;
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
 
 use serde::{de::DeserializeOwned, Serialize};
 
+use crate::network::broadcast::{ConnectionRetries, ConnectionTimeouts};
 use crate::network::TypedStream;
 
 /// Connects to the specified bootstrap server and returns a list of addreses for all the nodes in
-/// the network.
-pub fn discover_peers<D1, D2>(bootstrap_addr: SocketAddr, data: D1) -> (usize, Vec<D1>, D2)
+/// the network. The address of the bootstrap server can be a hostname, in which case every
+/// address it resolves to is tried. Returns an error if the address can't be resolved or reached,
+/// or if the bootstrap server sends malformed data or doesn't respond within the read timeout.
+pub fn discover_peers<A, D1, D2>(
+    bootstrap_addr: A,
+    data: D1,
+    retries: ConnectionRetries,
+    timeouts: ConnectionTimeouts,
+) -> io::Result<(usize, Vec<D1>, D2)>
 where
+    A: ToSocketAddrs,
     D1: Serialize + DeserializeOwned,
     D2: Serialize + DeserializeOwned,
 {
-    let socket = loop {
-        match TcpStream::connect(bootstrap_addr) {
-            Ok(stream) => break stream,
-            // TODO(petrosagg): replace with retry crate
-            Err(_) => std::thread::sleep(Duration::from_millis(200)),
-        }
-    };
+    let socket = connect_to_bootstrap(bootstrap_addr, retries)?;
+    exchange_peer_data(socket, data, timeouts)
+}
+
+/// Connects to the bootstrap server, which may not have started yet. Only a failure to resolve
+/// its address is not retried.
+pub fn connect_to_bootstrap<A: ToSocketAddrs>(
+    bootstrap_addr: A,
+    retries: ConnectionRetries,
+) -> io::Result<TcpStream> {
+    let bootstrap_addrs: Vec<SocketAddr> = bootstrap_addr.to_socket_addrs()?.collect();
+    retries.connect(&bootstrap_addrs)
+}
+
+/// Sends the data of this node over a connection to the bootstrap server and receives the index of
+/// this node, the data of all the peers and the data of the bootstrap server. The server only
+/// responds once all the peers have sent their data, so the read timeout bounds how long this node
+/// waits for the others to start.
+pub fn exchange_peer_data<D1, D2>(
+    socket: TcpStream,
+    data: D1,
+    timeouts: ConnectionTimeouts,
+) -> io::Result<(usize, Vec<D1>, D2)>
+where
+    D1: Serialize + DeserializeOwned,
+    D2: Serialize + DeserializeOwned,
+{
+    timeouts.apply(&socket)?;
     let mut stream = TypedStream::new(socket);
 
-    stream.send(&data);
-    (stream.recv(), stream.recv(), stream.recv())
+    stream.send(&data)?;
+    Ok((stream.recv()?, stream.recv()?, stream.recv()?))
 }
 
-pub fn bootstrap_helper<D1, D2>(
-    bootstrap_addr: SocketAddr,
+/// Waits for `expected_peers` peers to send their data and responds to each one with its index,
+/// the data of all the peers and `bootstrap_data`. Connections whose data can't be read within the
+/// read timeout are dropped without counting towards the expected peers.
+pub fn bootstrap_helper<A, D1, D2>(
+    bootstrap_addr: A,
     expected_peers: usize,
     bootstrap_data: D2,
-) where
+    timeouts: ConnectionTimeouts,
+) -> io::Result<()>
+where
+    A: ToSocketAddrs,
     D1: Serialize + DeserializeOwned,
     D2: Serialize + DeserializeOwned,
 {
-    let listener = TcpListener::bind(bootstrap_addr).unwrap();
+    let listener = TcpListener::bind(bootstrap_addr)?;
 
     let mut streams = vec![];
     let mut peer_data = vec![];
-    for _ in 0..expected_peers {
-        let socket = listener.accept().unwrap().0;
+    while streams.len() < expected_peers {
+        let (socket, addr) = listener.accept()?;
+        if let Err(err) = timeouts.apply(&socket) {
+            log::warn!("Dropping peer {addr} whose connection can't be configured: {err}");
+            continue;
+        }
         let mut stream = TypedStream::new(socket);
-        let data = stream.recv::<D1>();
+        let data = match stream.recv::<D1>() {
+            Ok(data) => data,
+            Err(err) => {
+                log::warn!("Dropping peer {addr} that sent invalid discovery data: {err}");
+                continue;
+            }
+        };
         let index = streams.len();
         streams.push((index, stream));
         peer_data.push(data);
     }
 
     for (peer_index, mut peer_stream) in streams {
-        peer_stream.send(&peer_index);
-        peer_stream.send(&peer_data);
-        peer_stream.send(&bootstrap_data);
+        let result = peer_stream
+            .send(&peer_index)
+            .and_then(|_| peer_stream.send(&peer_data))
+            .and_then(|_| peer_stream.send(&bootstrap_data));
+        if let Err(err) = result {
+            log::error!("Failed to send the discovery results to peer {peer_index}: {err}");
+        }
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
+    use std::io::Write;
+    use std::time::Duration;
+
     use super::*;
 
+    #[test]
+    fn discover_by_hostname() {
+        let timeouts = ConnectionTimeouts::default();
+        let port = TcpListener::bind("localhost:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let bootstrap_addr = format!("localhost:{port}");
+        std::thread::scope(|s| {
+            let helper =
+                s.spawn(|| bootstrap_helper::<_, u64, u64>(&*bootstrap_addr, 1, 42, timeouts));
+            let (_, peer_data, bootstrap_data) = discover_peers::<_, u64, u64>(
+                &*bootstrap_addr,
+                7,
+                ConnectionRetries::default(),
+                timeouts,
+            )
+            .unwrap();
+            assert_eq!(peer_data, [7]);
+            assert_eq!(bootstrap_data, 42);
+            helper.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn discover_over_ipv6() {
+        let timeouts = ConnectionTimeouts::default();
+        let bootstrap_addr = TcpListener::bind("[::1]:0").unwrap().local_addr().unwrap();
+        assert!(bootstrap_addr.is_ipv6());
+        std::thread::scope(|s| {
+            let helper =
+                s.spawn(|| bootstrap_helper::<_, u64, u64>(bootstrap_addr, 1, 42, timeouts));
+            let (_, peer_data, bootstrap_data) = discover_peers::<_, u64, u64>(
+                bootstrap_addr.to_string(),
+                7,
+                ConnectionRetries::default(),
+                timeouts,
+            )
+            .unwrap();
+            assert_eq!(peer_data, [7]);
+            assert_eq!(bootstrap_data, 42);
+            helper.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn unresolvable_bootstrap_host() {
+        let timeouts = ConnectionTimeouts::default();
+        let err = discover_peers::<_, u64, u64>(
+            "bootstrap.invalid:7000",
+            7,
+            ConnectionRetries::default(),
+            timeouts,
+        )
+        .unwrap_err();
+        assert_ne!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn malformed_peer_data() {
+        let timeouts = ConnectionTimeouts::default();
+        // Reserve a free port for the bootstrap helper
+        let bootstrap_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        std::thread::scope(|s| {
+            let helper =
+                s.spawn(|| bootstrap_helper::<_, u64, u64>(bootstrap_addr, 1, 42, timeouts));
+
+            // A peer sending a truncated frame is dropped
+            let mut malformed = loop {
+                match TcpStream::connect(bootstrap_addr) {
+                    Ok(stream) => break stream,
+                    Err(_) => std::thread::sleep(Duration::from_millis(10)),
+                }
+            };
+            malformed.write_all(&[0, 0, 0, 0, 8, 1]).unwrap();
+            drop(malformed);
+
+            let (my_index, peer_data, bootstrap_data) = discover_peers::<_, u64, u64>(
+                bootstrap_addr,
+                7,
+                ConnectionRetries::default(),
+                timeouts,
+            )
+            .unwrap();
+            assert_eq!(my_index, 0);
+            assert_eq!(peer_data, [7]);
+            assert_eq!(bootstrap_data, 42);
+            helper.join().unwrap().unwrap();
+        });
+    }
+
+    #[test]
+    fn silent_peer_data() {
+        let helper_timeouts = ConnectionTimeouts {
+            read: Some(Duration::from_millis(100)),
+            write: Some(Duration::from_millis(100)),
+        };
+        // Reserve a free port for the bootstrap helper
+        let bootstrap_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        std::thread::scope(|s| {
+            let helper =
+                s.spawn(|| bootstrap_helper::<_, u64, u64>(bootstrap_addr, 1, 42, helper_timeouts));
+
+            // A peer that connects but never sends its data is dropped once it times out
+            let silent = loop {
+                match TcpStream::connect(bootstrap_addr) {
+                    Ok(stream) => break stream,
+                    Err(_) => std::thread::sleep(Duration::from_millis(10)),
+                }
+            };
+
+            let (my_index, peer_data, bootstrap_data) = discover_peers::<_, u64, u64>(
+                bootstrap_addr,
+                7,
+                ConnectionRetries::default(),
+                ConnectionTimeouts::default(),
+            )
+            .unwrap();
+            assert_eq!(my_index, 0);
+            assert_eq!(peer_data, [7]);
+            assert_eq!(bootstrap_data, 42);
+            helper.join().unwrap().unwrap();
+            drop(silent);
+        });
+    }
+
+    #[test]
+    fn silent_bootstrap_server() {
+        let timeouts = ConnectionTimeouts {
+            read: Some(Duration::from_millis(100)),
+            write: Some(Duration::from_millis(100)),
+        };
+        // The bootstrap server accepts the connection but never responds
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let bootstrap_addr = listener.local_addr().unwrap();
+        let err = discover_peers::<_, u64, u64>(
+            bootstrap_addr,
+            7,
+            ConnectionRetries::default(),
+            timeouts,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            "{err}"
+        );
+        drop(listener);
+    }
+
     #[test]
     #[ignore]
     fn basic_discovery() {
-        let bootstrap_addr = "127.0.0.1:7001".parse().unwrap();
+        let timeouts = ConnectionTimeouts::default();
+        let bootstrap_addr: SocketAddr = "127.0.0.1:7001".parse().unwrap();
         std::thread::scope(|s| {
             // First spawn the bootstrap helper
-            s.spawn(|| bootstrap_helper::<(SocketAddr, u64), u64>(bootstrap_addr, 3, 42));
+            s.spawn(|| {
+                bootstrap_helper::<_, (SocketAddr, u64), u64>(bootstrap_addr, 3, 42, timeouts)
+                    .unwrap()
+            });
 
             // Then each peer performs discovery
             s.spawn(|| {
                 let addr: SocketAddr = "127.0.0.1:6000".parse().unwrap();
-                let (my_index, peer_data, bootstrap_data) =
-                    discover_peers::<_, u64>(bootstrap_addr, (addr, 1));
+                let (my_index, peer_data, bootstrap_data) = discover_peers::<_, _, u64>(
+                    bootstrap_addr,
+                    (addr, 1),
+                    ConnectionRetries::default(),
+                    timeouts,
+                )
+                .unwrap();
                 assert_eq!(peer_data[my_index], (addr, 1));
                 assert_eq!(peer_data.len(), 3);
                 assert_eq!(bootstrap_data, 42);
             });
             s.spawn(|| {
                 let addr: SocketAddr = "127.0.0.1:6001".parse().unwrap();
-                let (my_index, peer_data, bootstrap_data) =
-                    discover_peers::<_, u64>(bootstrap_addr, (addr, 2));
+                let (my_index, peer_data, bootstrap_data) = discover_peers::<_, _, u64>(
+                    bootstrap_addr,
+                    (addr, 2),
+                    ConnectionRetries::default(),
+                    timeouts,
+                )
+                .unwrap();
                 assert_eq!(peer_data[my_index], (addr, 2));
                 assert_eq!(peer_data.len(), 3);
                 assert_eq!(bootstrap_data, 42);
             });
             s.spawn(|| {
                 let addr: SocketAddr = "127.0.0.1:6002".parse().unwrap();
-                let (my_index, peer_data, bootstrap_data) =
-                    discover_peers::<_, u64>(bootstrap_addr, (addr, 3));
+                let (my_index, peer_data, bootstrap_data) = discover_peers::<_, _, u64>(
+                    bootstrap_addr,
+                    (addr, 3),
+                    ConnectionRetries::default(),
+                    timeouts,
+                )
+                .unwrap();
                 assert_eq!(peer_data[my_index], (addr, 3));
                 assert_eq!(peer_data.len(), 3);
                 assert_eq!(bootstrap_data, 42);
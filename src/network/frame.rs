@@ -0,0 +1,142 @@
+//! The framing of the messages sent over TCP connections.
+//!
+//! Every message is encoded with bincode and sent as a frame made of a flag byte, the length of
+//! the payload as a big endian `u32` and the payload itself. Payloads larger than
+//! `COMPRESSION_THRESHOLD` are deflate compressed if that makes them smaller, which is signalled
+//! by the flag byte so that the reader can transparently decompress them.
+
+use std::io::{self, Read, Write};
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Payloads smaller than this are always sent uncompressed since compressing them saves little.
+const COMPRESSION_THRESHOLD: usize = 1024;
+/// The deflate compression level, from 0 to 10.
+const COMPRESSION_LEVEL: u8 = 6;
+/// The maximum size of a payload, before or after decompression.
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024 * 1024;
+
+/// The flag of frames whose payload is the plain bincode encoding.
+const FLAG_RAW: u8 = 0;
+/// The flag of frames whose payload is the deflate compressed bincode encoding.
+const FLAG_DEFLATE: u8 = 1;
+
+/// Writes `msg` as a single frame.
+pub(crate) fn write_frame<T: Serialize>(writer: &mut impl Write, msg: &T) -> io::Result<()> {
+    let encoded = bincode::serialize(msg).map_err(invalid_data)?;
+    let (flag, payload) = if encoded.len() >= COMPRESSION_THRESHOLD {
+        let compressed = compress_to_vec(&encoded, COMPRESSION_LEVEL);
+        if compressed.len() < encoded.len() {
+            (FLAG_DEFLATE, compressed)
+        } else {
+            (FLAG_RAW, encoded)
+        }
+    } else {
+        (FLAG_RAW, encoded)
+    };
+    let len = u32::try_from(payload.len()).map_err(invalid_data)?;
+
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(flag);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Reads a single frame and decodes the message it contains.
+pub(crate) fn read_frame<T: DeserializeOwned>(reader: &mut impl Read) -> io::Result<T> {
+    decode(&read_payload(reader)?)
+}
+
+/// Reads a single frame and returns the encoding of the message it contains, decompressed if
+/// needed. The reader is left at the start of the next frame even if the message can't be decoded.
+pub(crate) fn read_payload(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    let flag = header[0];
+    let len = u32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+    if len > MAX_PAYLOAD_SIZE {
+        return Err(invalid_data(format!("frame of {len} bytes is too large")));
+    }
+
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    let encoded = match flag {
+        FLAG_RAW => payload,
+        FLAG_DEFLATE => decompress_to_vec_with_limit(&payload, MAX_PAYLOAD_SIZE)
+            .map_err(|err| invalid_data(format!("failed to decompress frame: {err:?}")))?,
+        flag => return Err(invalid_data(format!("unknown frame flag {flag}"))),
+    };
+    Ok(encoded)
+}
+
+/// Decodes a message from the payload of a frame.
+pub(crate) fn decode<T: DeserializeOwned>(payload: &[u8]) -> io::Result<T> {
+    bincode::deserialize(payload).map_err(invalid_data)
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::crypto::{self, Address, SignatureScheme};
+    use crate::node::Block;
+    use crate::wallet::{Transaction, TransactionKind};
+
+    use super::*;
+
+    #[test]
+    fn small_frame_roundtrip() {
+        let mut wire = vec![];
+        write_frame(&mut wire, &42usize).unwrap();
+        assert_eq!(wire[0], FLAG_RAW);
+        assert_eq!(read_frame::<usize>(&mut &wire[..]).unwrap(), 42);
+    }
+
+    #[test]
+    fn large_block_is_compressed() {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let address = Address::from_public_key(&public_key);
+        let transactions = (0..50)
+            .map(|nonce| {
+                private_key.sign(Transaction {
+                    sender_address: address.clone(),
+                    kind: TransactionKind::Message("hello ".repeat(100), address.clone()),
+                    nonce,
+                })
+            })
+            .collect();
+        let block = private_key.sign(Block {
+            timestamp: Utc::now(),
+            transactions,
+            validator: address,
+            parent_hash: Default::default(),
+            evidence: vec![],
+        });
+
+        let mut wire = vec![];
+        write_frame(&mut wire, &block).unwrap();
+        assert_eq!(wire[0], FLAG_DEFLATE);
+        assert!(wire.len() < bincode::serialize(&block).unwrap().len() / 2);
+
+        let decoded: crypto::Signed<Block> = read_frame(&mut &wire[..]).unwrap();
+        assert_eq!(decoded, block);
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let wire = [7, 0, 0, 0, 0];
+        let err = read_frame::<usize>(&mut &wire[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
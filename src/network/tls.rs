@@ -0,0 +1,386 @@
+//! Optional TLS encryption of the connections between peers.
+//!
+//! Every peer presents a certificate issued for the same server name by a shared certificate
+//! authority and verifies the certificate of the other side, so both ends of a connection are
+//! authenticated. The handshake is driven by the threads reading and writing the connection, the
+//! messages written before it completes are buffered. The session is only locked while encrypting
+//! and decrypting, never while waiting on the socket, so a peer that is slow to read doesn't stop
+//! the messages it sends from being received.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig};
+use rustls::{ServerConnection, DEFAULT_VERSIONS};
+
+/// The size of the buffer encrypted data is read into.
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// The certificates used to encrypt the connections to the peers.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain this node presents to its peers.
+    pub cert_path: PathBuf,
+    /// A PEM file with the private key of the certificate.
+    pub key_path: PathBuf,
+    /// A PEM file with the certificate authorities that issue the certificates of the peers.
+    pub ca_path: PathBuf,
+    /// The name the certificates of all the peers are issued for.
+    pub server_name: String,
+}
+
+impl TlsConfig {
+    /// Loads the certificates and the key and constructs the configuration of the connections.
+    pub(crate) fn load(&self) -> io::Result<TlsContext> {
+        let certs = load_certs(&self.cert_path)?;
+        let key = load_key(&self.key_path)?;
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&self.ca_path)? {
+            roots.add(cert).map_err(invalid_data)?;
+        }
+        let roots = Arc::new(roots);
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_protocol_versions(DEFAULT_VERSIONS)
+            .map_err(invalid_data)?
+            .with_root_certificates(Arc::clone(&roots))
+            .with_client_auth_cert(certs.clone(), key.clone_key())
+            .map_err(invalid_data)?;
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots, Arc::clone(&provider))
+            .build()
+            .map_err(invalid_data)?;
+        let server = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(DEFAULT_VERSIONS)
+            .map_err(invalid_data)?
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)
+            .map_err(invalid_data)?;
+        let server_name = ServerName::try_from(self.server_name.clone()).map_err(invalid_data)?;
+
+        Ok(TlsContext {
+            client: Arc::new(client),
+            server: Arc::new(server),
+            server_name,
+        })
+    }
+}
+
+fn load_certs(path: &PathBuf) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!(
+            "no certificates in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &PathBuf) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| invalid_data(format!("no private key in {}", path.display())))
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// The loaded configuration of the TLS connections.
+#[derive(Clone)]
+pub(crate) struct TlsContext {
+    client: Arc<ClientConfig>,
+    server: Arc<ServerConfig>,
+    server_name: ServerName<'static>,
+}
+
+impl TlsContext {
+    /// Starts a TLS session over the provided connection. The side that initiated the connection
+    /// acts as the client of the handshake. Returns the halves reading and writing plaintext.
+    pub(crate) fn wrap(
+        &self,
+        socket: &TcpStream,
+        initiator: bool,
+    ) -> io::Result<(TlsReader, TlsWriter)> {
+        let mut conn: Connection = if initiator {
+            ClientConnection::new(Arc::clone(&self.client), self.server_name.clone())
+                .map_err(invalid_data)?
+                .into()
+        } else {
+            ServerConnection::new(Arc::clone(&self.server))
+                .map_err(invalid_data)?
+                .into()
+        };
+        // Messages written before the handshake completes must not be rejected
+        conn.set_buffer_limit(None);
+        let conn = Arc::new(Mutex::new(conn));
+        let output = Arc::new(Mutex::new(socket.try_clone()?));
+        let reader = TlsReader {
+            socket: socket.try_clone()?,
+            output: Arc::clone(&output),
+            conn: Arc::clone(&conn),
+            buffer: vec![0; READ_BUFFER_SIZE],
+        };
+        let mut writer = TlsWriter { output, conn };
+        // Send the client hello
+        writer.flush()?;
+        Ok((reader, writer))
+    }
+}
+
+/// Locks the TLS session or the socket shared by the reader and the writer of a connection.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks the socket shared by the reader and the writer of a connection, unless the other half is
+/// already sending.
+fn try_lock(socket: &Mutex<TcpStream>) -> Option<MutexGuard<'_, TcpStream>> {
+    match socket.try_lock() {
+        Ok(socket) => Some(socket),
+        Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Sends all the pending encrypted data of the session. The data is taken out of the session with
+/// it locked and written to the socket with it unlocked. Holding the socket keeps the data of the
+/// reader and the writer in order.
+fn send_pending(mut socket: MutexGuard<'_, TcpStream>, conn: &Mutex<Connection>) -> io::Result<()> {
+    let mut encrypted = vec![];
+    loop {
+        let mut session = lock(conn);
+        while session.wants_write() {
+            session.write_tls(&mut encrypted)?;
+        }
+        if encrypted.is_empty() {
+            // The socket is released before the session so that the data queued after this check
+            // is sent by the half queuing it
+            drop(socket);
+            return Ok(());
+        }
+        drop(session);
+        socket.write_all(&encrypted)?;
+        encrypted.clear();
+    }
+}
+
+/// The half of a TLS connection reading plaintext.
+pub(crate) struct TlsReader {
+    socket: TcpStream,
+    output: Arc<Mutex<TcpStream>>,
+    conn: Arc<Mutex<Connection>>,
+    buffer: Vec<u8>,
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match lock(&self.conn).reader().read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            // The lock is not held while waiting for the peer so that the writer can make progress
+            let len = self.socket.read(&mut self.buffer)?;
+            if len == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let mut conn = lock(&self.conn);
+            let mut encrypted = &self.buffer[..len];
+            while !encrypted.is_empty() {
+                conn.read_tls(&mut encrypted)?;
+                conn.process_new_packets().map_err(invalid_data)?;
+            }
+            // The handshake responses and any messages buffered until the handshake completed. If
+            // the writer is sending it also sends these before releasing the socket.
+            if let Some(output) = try_lock(&self.output) {
+                drop(conn);
+                send_pending(output, &self.conn)?;
+            }
+        }
+    }
+}
+
+/// The half of a TLS connection writing plaintext.
+pub(crate) struct TlsWriter {
+    output: Arc<Mutex<TcpStream>>,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let output = lock(&self.output);
+        let len = lock(&self.conn).writer().write(buf)?;
+        send_pending(output, &self.conn)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let output = lock(&self.output);
+        lock(&self.conn).writer().flush()?;
+        send_pending(output, &self.conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use rand::distributions::Alphanumeric;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    use crate::network::broadcast::{Broadcaster, ConnectionRetries, ConnectionTimeouts};
+    use crate::network::Network;
+
+    use super::*;
+
+    /// Writes a certificate authority and a certificate it issued for `server_name` to `dir`.
+    fn write_test_certs(dir: &Path, server_name: &str) -> TlsConfig {
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+        let params = CertificateParams::new(vec![server_name.to_owned()]).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca_cert, &ca_key).unwrap();
+
+        std::fs::create_dir_all(dir).unwrap();
+        let config = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            ca_path: dir.join("ca.pem"),
+            server_name: server_name.to_owned(),
+        };
+        std::fs::write(&config.cert_path, cert.pem()).unwrap();
+        std::fs::write(&config.key_path, key.serialize_pem()).unwrap();
+        std::fs::write(&config.ca_path, ca_cert.pem()).unwrap();
+        config
+    }
+
+    #[test]
+    fn tls_networking() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", std::process::id()));
+        let config = write_test_certs(&dir, "blockchat");
+        let listeners = [
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+        ];
+        let addrs = listeners.each_ref().map(|l| l.local_addr().unwrap());
+
+        let timeouts = ConnectionTimeouts::default();
+        let retries = ConnectionRetries::default();
+        let (first, second) = std::thread::scope(|s| {
+            let [first_listener, second_listener] = listeners;
+            let first = s.spawn(|| {
+                Broadcaster::<String>::with_tls(
+                    first_listener,
+                    &addrs,
+                    0,
+                    timeouts,
+                    retries,
+                    &config,
+                )
+            });
+            let second = s.spawn(|| {
+                Broadcaster::<String>::with_tls(
+                    second_listener,
+                    &addrs,
+                    1,
+                    timeouts,
+                    retries,
+                    &config,
+                )
+            });
+            (first.join().unwrap(), second.join().unwrap())
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (mut first, mut second) = (first.unwrap(), second.unwrap());
+
+        // Messages flow in both directions, including a large one spanning many TLS records
+        let large = "x".repeat(100_000);
+        first.send(&"hello".to_owned());
+        second.send(&large);
+        second.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(second.recv().as_deref(), Some("hello"));
+        first.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(first.recv(), Some(large));
+
+        // Both sides send more than the socket buffers hold at the same time, so each writer waits
+        // for the other side to read while its own side keeps receiving
+        let incompressible = |seed| {
+            let rng = StdRng::seed_from_u64(seed);
+            rng.sample_iter(Alphanumeric)
+                .take(16 * 1024 * 1024)
+                .map(char::from)
+                .collect::<String>()
+        };
+        let (first_large, second_large) = (incompressible(0), incompressible(1));
+        first.send(&first_large);
+        second.send(&second_large);
+        second.await_events(Some(Duration::from_secs(30)));
+        assert!(second.recv() == Some(first_large));
+        first.await_events(Some(Duration::from_secs(30)));
+        assert!(first.recv() == Some(second_large));
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let dir = std::env::temp_dir().join(format!("tls-untrusted-{}", std::process::id()));
+        let config = write_test_certs(&dir.join("trusted"), "blockchat");
+        // A certificate for the right name, issued by another authority
+        let other_config = write_test_certs(&dir.join("other"), "blockchat");
+        let listeners = [
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+        ];
+        let addrs = listeners.each_ref().map(|l| l.local_addr().unwrap());
+
+        let timeouts = ConnectionTimeouts::default();
+        let retries = ConnectionRetries::default();
+        let (first, second) = std::thread::scope(|s| {
+            let [first_listener, second_listener] = listeners;
+            let first = s.spawn(|| {
+                Broadcaster::<String>::with_tls(
+                    first_listener,
+                    &addrs,
+                    0,
+                    timeouts,
+                    retries,
+                    &config,
+                )
+            });
+            let second = s.spawn(|| {
+                Broadcaster::<String>::with_tls(
+                    second_listener,
+                    &addrs,
+                    1,
+                    timeouts,
+                    retries,
+                    &other_config,
+                )
+            });
+            (first.join().unwrap(), second.join().unwrap())
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (mut first, mut second) = (first.unwrap(), second.unwrap());
+
+        // The handshake fails so the message never arrives and the connection is dropped
+        second.send(&"forged".to_owned());
+        first.await_events(Some(Duration::from_secs(10)));
+        assert_eq!(first.recv(), None);
+    }
+}
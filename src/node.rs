@@ -1,26 +1,211 @@
-use std::collections::BTreeMap;
+pub mod handle;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque};
 use std::fmt;
+use std::ops::Bound;
 use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
 use rand::{Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
+use tracing::Span;
 
 use crate::crypto::{Address, Hash, PrivateKey, PublicKey, Signed};
 use crate::error::{Error, Result};
+use crate::genesis::{GenesisConfig, GENESIS_PARENT_HASH};
 use crate::network::Network;
-use crate::wallet::{Transaction, TransactionKind, Wallet};
+use crate::snapshot::Snapshot;
+use crate::wallet::{FeeSchedule, Transaction, TransactionKind, Wallet};
+
+/// The default time between two blocks minted by the same validator.
+pub const DEFAULT_MINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The maximum number of blocks sent in response to a single `Message::GetBlocks`.
+const MAX_SYNC_BLOCKS: usize = 100;
+
+/// The number of recently seen network messages remembered to drop duplicates.
+const SEEN_MESSAGES_CAPACITY: usize = 10_000;
+
+/// A bounded set of the hashes of recently seen messages. Once full the oldest hash is forgotten.
+#[derive(Debug, Default)]
+struct SeenMessages {
+    /// The remembered hashes in insertion order.
+    order: VecDeque<Hash>,
+    /// The remembered hashes for fast lookups.
+    hashes: HashSet<Hash>,
+}
+
+impl SeenMessages {
+    fn contains(&self, hash: &Hash) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    fn insert(&mut self, hash: Hash) {
+        if !self.hashes.insert(hash.clone()) {
+            return;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > SEEN_MESSAGES_CAPACITY {
+            let oldest = self.order.pop_front().unwrap();
+            self.hashes.remove(&oldest);
+        }
+    }
+}
+
+/// The number of idempotency keys of transaction requests remembered by a node.
+const IDEMPOTENCY_KEYS_CAPACITY: usize = 1_000;
+
+/// The transactions created for recent requests, keyed by the idempotency keys of the requests.
+/// Once full the oldest key is forgotten.
+#[derive(Debug, Default)]
+struct IdempotencyKeys {
+    /// The remembered keys in insertion order.
+    order: VecDeque<String>,
+    /// The transaction created for each remembered key.
+    transactions: HashMap<String, Signed<Transaction>>,
+}
+
+impl IdempotencyKeys {
+    fn get(&self, key: &str) -> Option<&Signed<Transaction>> {
+        self.transactions.get(key)
+    }
+
+    fn insert(&mut self, key: String, tx: Signed<Transaction>) {
+        if self.transactions.insert(key.clone(), tx).is_some() {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > IDEMPOTENCY_KEYS_CAPACITY {
+            let oldest = self.order.pop_front().unwrap();
+            self.transactions.remove(&oldest);
+        }
+    }
+}
+
+/// Bounds on the number of transactions kept in the mempool. The per sender bounds don't apply to
+/// the transactions of the node itself.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MempoolLimits {
+    /// The maximum number of pending transactions.
+    pub max_transactions: usize,
+    /// The maximum number of pending transactions from a single sender.
+    pub max_per_sender: usize,
+    /// The number of transactions a single sender can add to the mempool within `rate_interval`.
+    /// The allowance refills gradually, so a sender can send a burst of this many transactions
+    /// and is then held to the average rate.
+    pub max_per_interval: u32,
+    /// The interval the rate limit of each sender applies to. Rate limiting is disabled when zero.
+    pub rate_interval: Duration,
+    /// The time after which a pending transaction that was not minted is evicted.
+    pub ttl: Duration,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_transactions: 10_000,
+            max_per_sender: 1_000,
+            max_per_interval: 100,
+            rate_interval: Duration::from_secs(1),
+            ttl: Duration::from_secs(600),
+        }
+    }
+}
+
+/// The proof that a validator signed two different blocks at the same height.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Equivocation {
+    /// The height of the conflicting blocks.
+    pub height: usize,
+    /// The block that was accepted at this height.
+    pub accepted: Signed<Block>,
+    /// The conflicting block that was rejected.
+    pub rejected: Signed<Block>,
+}
 
-const MINT_INTERVAL: Duration = Duration::from_secs(1);
+/// The counters maintained by a node as it processes messages.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NodeMetrics {
+    /// The blocks minted by this node.
+    pub blocks_minted: u64,
+    /// The blocks appended to the blockchain, including the ones minted by this node.
+    pub blocks_accepted: u64,
+    /// The transactions confirmed in accepted blocks.
+    pub transactions_processed: u64,
+    /// The time between the timestamps of consecutive accepted blocks.
+    pub block_times: BlockTimeHistogram,
+}
+
+/// The upper bounds in milliseconds of the buckets of `BlockTimeHistogram`.
+pub const BLOCK_TIME_BUCKETS_MS: [u64; 9] =
+    [100, 250, 500, 1_000, 2_000, 5_000, 10_000, 30_000, 60_000];
+
+/// A histogram of the time between consecutive blocks. A network keeping up with the mint
+/// interval records most blocks around it, while a stalling one records longer times.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTimeHistogram {
+    /// The number of times recorded in each bucket of `BLOCK_TIME_BUCKETS_MS`, followed by the
+    /// number of times longer than the last bucket.
+    pub counts: [u64; BLOCK_TIME_BUCKETS_MS.len() + 1],
+    /// The sum of the recorded times in milliseconds.
+    pub sum_ms: u64,
+}
+
+impl BlockTimeHistogram {
+    pub fn record(&mut self, time: Duration) {
+        let ms = u64::try_from(time.as_millis()).unwrap_or(u64::MAX);
+        let bucket = BLOCK_TIME_BUCKETS_MS.partition_point(|&bound| bound < ms);
+        self.counts[bucket] += 1;
+        self.sum_ms = self.sum_ms.saturating_add(ms);
+    }
+
+    /// The number of recorded times.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The number of times recorded up to each bucket bound, ending with the total count under no
+    /// bound, as exposed by Prometheus histograms.
+    pub fn cumulative_counts(&self) -> impl Iterator<Item = (Option<u64>, u64)> + '_ {
+        let bounds = BLOCK_TIME_BUCKETS_MS.iter().copied().map(Some);
+        bounds
+            .chain([None])
+            .zip(self.counts.iter().scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            }))
+    }
+}
 
 pub struct Node {
     // The name of this node. Used for logging
     name: String,
-    /// The maximum number of transactions contained in each block.
+    /// The maximum number of transactions contained in each block. It is a consensus parameter
+    /// taken from the genesis configuration so that all the nodes agree on which blocks are valid.
     capacity: usize,
+    /// The time after which a validator mints a block even if it isn't full.
+    mint_interval: Duration,
+    /// The fee model of the network.
+    fee_schedule: FeeSchedule,
+    /// The stake burned from a validator that signs a block with invalid transactions.
+    slashing_penalty: u64,
+    /// The number of blocks the validators are elected for at once.
+    epoch_length: usize,
+    /// The validators elected for each block of the current epoch, fixed by its first block.
+    epoch_validators: Vec<Address>,
+    /// The bounds on the size of the mempool.
+    mempool_limits: MempoolLimits,
     /// The set of signed but not necessarily valid transactions waiting to be included in a block.
     pending_transactions: BTreeMap<(Address, u64), Signed<Transaction>>,
+    /// The arrival time of the transactions that entered the mempool, oldest first. The entries of
+    /// transactions that already left the mempool are skipped once they expire.
+    pending_arrivals: VecDeque<(DateTime<Utc>, (Address, u64), Hash)>,
+    /// The remaining rate limit allowance of each sender, along with the time it was last updated.
+    /// Senders whose allowance has fully refilled are forgotten.
+    sender_allowances: HashMap<Address, (f64, DateTime<Utc>)>,
     /// The current blockchain.
     blockchain: Vec<Signed<Block>>,
     /// The public key of the wallet of this node.
@@ -35,14 +220,39 @@ pub struct Node {
     /// The state of each known wallet indexed by public key. We use a BTreeMap to always maintain
     /// the wallets in sorted public key order which helps perform the validator election.
     wallets: BTreeMap<Address, Wallet>,
+    /// The state of the wallets before the first block after genesis, used to replay the chain.
+    genesis_wallets: BTreeMap<Address, Wallet>,
+    /// The invalid blocks received by this node, waiting to be included as evidence in a block.
+    pending_evidence: Vec<Signed<Block>>,
     /// Messages that should be broadcast on the next tick
     outbox: Vec<Message>,
+    /// The messages that have already been handled or sent by this node.
+    seen_messages: SeenMessages,
+    /// The transactions created for recent requests that carried an idempotency key.
+    idempotency_keys: IdempotencyKeys,
+    /// The equivocations of validators detected by this node.
+    equivocations: Vec<Equivocation>,
+    /// The counters exposed for monitoring.
+    metrics: NodeMetrics,
+    /// Whether this node only follows the blockchain without ever minting or staking.
+    observer: bool,
+    /// The amount this node stakes as soon as its wallet can afford it.
+    initial_stake: Option<u64>,
+    /// Whether this node rejects new transactions until its mempool is drained.
+    draining: bool,
+    /// The time this node uses instead of the wall clock, e.g. in simulations.
+    simulated_time: Option<DateTime<Utc>>,
+    /// Whether this node acknowledges the messages sent to it.
+    send_acks: bool,
+    /// The acknowledgements of the transactions sent by this node, keyed by transaction hash.
+    acknowledgements: HashMap<Hash, Signed<Acknowledgement>>,
 }
 
 impl fmt::Debug for Node {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Node")
             .field("capacity", &self.capacity)
+            .field("fee_schedule", &self.fee_schedule)
             .field("pending_transactions", &self.pending_transactions)
             .field("blockchain", &self.blockchain)
             .field("public_key", &self.public_key)
@@ -62,66 +272,200 @@ impl Node {
         genesis_funds: u64,
         capacity: usize,
     ) -> Self {
+        let genesis = GenesisConfig::new(
+            Address::from_public_key(&genesis_validator),
+            genesis_funds,
+            capacity,
+        );
+        Self::from_genesis(name, public_key, private_key, genesis)
+            .expect("the genesis funds and capacity must be positive")
+    }
+
+    /// Constructs a node whose blockchain starts from the genesis block described by the provided
+    /// configuration. Returns an error if the configuration is invalid.
+    pub fn from_genesis(
+        name: String,
+        public_key: PublicKey,
+        private_key: PrivateKey,
+        genesis: GenesisConfig,
+    ) -> Result<Self> {
+        genesis.validate()?;
+
+        let fee_schedule = genesis.fee_schedule;
         let mut wallets = BTreeMap::new();
         let node_address = Address::from_public_key(&public_key);
-        let node_wallet = Wallet::from_address(node_address.clone());
+        let node_wallet = Wallet::with_fee_schedule(node_address.clone(), fee_schedule);
         wallets.insert(node_address.clone(), node_wallet.clone());
 
-        let genesis_address = Address::from_public_key(&genesis_validator);
-        let genesis_tx = Transaction {
-            sender_address: Address::invalid(),
-            kind: TransactionKind::Coin(genesis_funds, genesis_address.clone()),
-            nonce: 0,
-        };
-
-        let genesis_block = Block {
-            timestamp: DateTime::<Utc>::MIN_UTC,
-            transactions: vec![Signed::new_invalid(genesis_tx)],
-            validator: Address::invalid(),
-            parent_hash: "0000000000000000000000000000000000000000000000000000000000000001"
-                .parse()
-                .unwrap(),
-        };
+        for (address, amount) in genesis.balances.iter() {
+            wallets
+                .entry(address.clone())
+                .or_insert_with(|| Wallet::with_fee_schedule(address.clone(), fee_schedule))
+                .add_funds(*amount);
+        }
 
         let genesis_wallet = wallets
-            .entry(genesis_address.clone())
-            .or_insert_with(|| Wallet::from_address(genesis_address));
-        genesis_wallet.add_funds(genesis_funds);
+            .entry(genesis.validator.clone())
+            .or_insert_with(|| Wallet::with_fee_schedule(genesis.validator.clone(), fee_schedule));
         genesis_wallet.set_stake(1);
 
-        Self {
+        Ok(Self {
             name,
-            capacity,
+            capacity: genesis.capacity,
+            mint_interval: DEFAULT_MINT_INTERVAL,
+            fee_schedule,
+            slashing_penalty: genesis.slashing_penalty,
+            epoch_length: genesis.epoch_length.max(1),
+            epoch_validators: vec![],
+            mempool_limits: MempoolLimits::default(),
             pending_transactions: BTreeMap::new(),
+            pending_arrivals: VecDeque::new(),
+            sender_allowances: HashMap::new(),
             node_wallet: wallets[&node_address].clone(),
             address: node_address,
             public_key,
             private_key,
-            blockchain: vec![Signed::new_invalid(genesis_block)],
+            blockchain: vec![genesis.block()],
+            genesis_wallets: wallets.clone(),
             wallets,
+            pending_evidence: vec![],
             outbox: vec![],
+            seen_messages: SeenMessages::default(),
+            idempotency_keys: IdempotencyKeys::default(),
+            equivocations: vec![],
+            metrics: NodeMetrics::default(),
+            observer: false,
+            initial_stake: None,
+            draining: false,
+            simulated_time: None,
+            send_acks: false,
+            acknowledgements: HashMap::new(),
+        })
+    }
+
+    /// Constructs a node from a snapshot of the state of the wallets and the persisted
+    /// `blockchain`, which starts from the genesis block. The blocks covered by the snapshot are
+    /// trusted and only the blocks after it are validated and applied.
+    pub fn from_snapshot(
+        name: String,
+        public_key: PublicKey,
+        private_key: PrivateKey,
+        genesis: GenesisConfig,
+        snapshot: Snapshot,
+        mut blockchain: Vec<Signed<Block>>,
+    ) -> Result<Self> {
+        let mut node = Self::from_genesis(name, public_key, private_key, genesis)?;
+        if blockchain.first() != node.blockchain.first() {
+            return Err(Error::InvalidGenesis(
+                "different from the local genesis block",
+            ));
         }
+        let tip = snapshot
+            .height
+            .checked_sub(1)
+            .and_then(|i| blockchain.get(i));
+        if tip.map(|tip| &tip.hash) != Some(&snapshot.tip) {
+            return Err(Error::InvalidSnapshot("tip is not part of the blockchain"));
+        }
+
+        let replayed = blockchain.split_off(snapshot.height);
+        for block in blockchain.iter() {
+            node.seen_messages.insert(block.hash.clone());
+        }
+        node.blockchain = blockchain;
+        // The fee schedule is not part of the snapshot
+        node.wallets = snapshot.wallets;
+        for wallet in node.wallets.values_mut() {
+            wallet.fee_schedule = node.fee_schedule;
+        }
+        let node_wallet = node
+            .wallets
+            .entry(node.address.clone())
+            .or_insert_with(|| Wallet::with_fee_schedule(node.address.clone(), node.fee_schedule));
+        node.node_wallet = node_wallet.clone();
+        node.epoch_validators = snapshot.epoch_validators;
+        let slot = node.epoch_slot(node.blockchain.len());
+        if slot != 0 && node.epoch_validators.len() != node.epoch_length {
+            return Err(Error::InvalidSnapshot(
+                "missing the validators of the epoch",
+            ));
+        }
+
+        for block in replayed {
+            node.handle_block(block)?;
+        }
+        Ok(node)
     }
 
-    fn next_validator(&self) -> Address {
-        let seed = self.blockchain.last().unwrap().hash.0;
-        let mut rng = StdRng::from_seed(seed);
-        // Construct the ballot from the current set of
-        let total_stake: u64 = self.wallets.values().map(|w| w.staked_amount()).sum();
-        assert!(total_stake > 0, "no stakers, BlockChat is doomed");
+    /// Takes a snapshot of the state of the wallets as of the tip of the blockchain.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            height: self.blockchain.len(),
+            tip: self.blockchain.last().unwrap().hash.clone(),
+            wallets: self.wallets.clone(),
+            epoch_validators: match self.epoch_slot(self.blockchain.len()) {
+                0 => vec![],
+                _ => self.epoch_validators.clone(),
+            },
+        }
+    }
 
-        let mut winner = rng.gen_range(0..total_stake);
-        self.wallets
-            .values()
-            .find_map(|wallet| {
-                if wallet.staked_amount() > winner {
-                    Some(wallet.address.clone())
-                } else {
-                    winner -= wallet.staked_amount();
-                    None
-                }
-            })
-            .unwrap()
+    /// Checks that `block` is a well formed genesis block. A genesis block is not signed by any
+    /// validator, points to the genesis parent sentinel and only contains unsigned genesis
+    /// transactions minting the initial balances with consecutive nonces.
+    pub fn validate_genesis(block: &Signed<Block>) -> Result<()> {
+        if block.hash != Hash::digest(&block.data) {
+            return Err(Error::InvalidGenesis("hash does not match the block"));
+        }
+        if block.public_key != PublicKey::invalid() || !block.signature.is_empty() {
+            return Err(Error::InvalidGenesis("block is signed"));
+        }
+        if block.data.validator != Address::invalid() {
+            return Err(Error::InvalidGenesis("block has a validator"));
+        }
+        if block.data.parent_hash != GENESIS_PARENT_HASH {
+            return Err(Error::InvalidGenesis("block has a parent"));
+        }
+        for (nonce, tx) in block.data.transactions.iter().enumerate() {
+            if tx.hash != Hash::digest(&tx.data) {
+                return Err(Error::InvalidGenesis("hash does not match a transaction"));
+            }
+            if tx.public_key != PublicKey::invalid() || !tx.signature.is_empty() {
+                return Err(Error::InvalidGenesis("transaction is signed"));
+            }
+            if tx.data.sender_address != Address::invalid() || tx.data.nonce != nonce as u64 {
+                return Err(Error::InvalidGenesis("transaction is not a mint"));
+            }
+            if !matches!(tx.data.kind, TransactionKind::Genesis(_, _)) {
+                return Err(Error::InvalidGenesis("transaction does not mint coins"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Elects the validator of the next block. Returns an error if no wallet has staked any funds.
+    fn next_validator(&self) -> Result<Address> {
+        let slot = self.epoch_slot(self.blockchain.len());
+        Ok(self.next_epoch()?.swap_remove(slot))
+    }
+
+    /// The validators of the epoch the next block belongs to. They are elected when the epoch
+    /// starts, from the stakes as of its first block's parent, which also seeds the election.
+    fn next_epoch(&self) -> Result<Vec<Address>> {
+        match self.epoch_slot(self.blockchain.len()) {
+            0 => elect_validators(
+                &self.wallets,
+                &self.blockchain.last().unwrap().hash,
+                self.epoch_length,
+            ),
+            _ => Ok(self.epoch_validators.clone()),
+        }
+    }
+
+    /// The position of the block at the provided height in its epoch. The epochs start right
+    /// after the genesis block.
+    fn epoch_slot(&self, height: usize) -> usize {
+        (height - 1) % self.epoch_length
     }
 
     /// The address of this node's wallet.
@@ -129,6 +473,11 @@ impl Node {
         &self.address
     }
 
+    /// The fee model of the network this node belongs to.
+    pub fn fee_schedule(&self) -> FeeSchedule {
+        self.fee_schedule
+    }
+
     /// This node's wallet.
     pub fn wallet(&self) -> &Wallet {
         &self.node_wallet
@@ -139,10 +488,43 @@ impl Node {
         &mut self.node_wallet
     }
 
+    /// The state of the wallet with the given address as of the last accepted block.
+    pub fn wallet_for(&self, address: &Address) -> Option<&Wallet> {
+        self.wallets.get(address)
+    }
+
+    /// The wallet of this node as it will be once the pending transactions are minted. See
+    /// `projected_wallet_for`.
+    pub fn projected_wallet(&self) -> Wallet {
+        self.projected_wallet_for(&self.address)
+            .unwrap_or_else(|| Wallet::with_fee_schedule(self.address.clone(), self.fee_schedule))
+    }
+
+    /// The state of the wallet with the given address as of the last accepted block, with the
+    /// pending transactions sending funds from or to it applied. Pending transactions that can't
+    /// be applied, e.g. since they spend funds that are also pending, are skipped.
+    pub fn projected_wallet_for(&self, address: &Address) -> Option<Wallet> {
+        let mut wallet = self.wallets.get(address)?.clone();
+        // The mempool is ordered by sender and nonce so the outgoing transactions apply in order
+        for tx in self.pending_transactions.values() {
+            if tx.data.sender_address == *address || tx.data.receiver().as_ref() == Some(address) {
+                if let Err(err) = wallet.apply_verified_tx(&tx.data) {
+                    tracing::debug!("skipping pending tx {:?}: {err}", tx.hash);
+                }
+            }
+        }
+        Some(wallet)
+    }
+
     pub fn blockchain(&self) -> &[Signed<Block>] {
         &self.blockchain
     }
 
+    /// The number of blocks in the blockchain, including the genesis block.
+    pub fn height(&self) -> usize {
+        self.blockchain.len()
+    }
+
     pub fn total_transactions(&self) -> usize {
         self.blockchain
             .iter()
@@ -150,213 +532,1062 @@ impl Node {
             .sum()
     }
 
+    /// The sum of the balances of all known wallets.
+    pub fn total_supply(&self) -> u64 {
+        total_supply(&self.wallets)
+    }
+
+    /// The sum of the stakes of all known wallets.
+    pub fn total_staked(&self) -> u64 {
+        self.wallets
+            .values()
+            .map(|wallet| wallet.staked_amount())
+            .sum()
+    }
+
+    /// The heights of the blocks minted by the provided validator, in ascending order.
+    pub fn blocks_by_validator(&self, validator: &Address) -> Vec<usize> {
+        // The genesis block is not minted by anyone
+        (1..self.blockchain.len())
+            .filter(|&height| &self.blockchain[height].data.validator == validator)
+            .collect()
+    }
+
+    /// Every address that appears in the wallets of the blockchain, sorted by address.
+    pub fn known_addresses(&self) -> Vec<Address> {
+        self.wallets.keys().cloned().collect()
+    }
+
+    /// The addresses with a non-zero stake along with their stake, sorted by address.
+    pub fn stakers(&self) -> Vec<(Address, u64)> {
+        self.wallets
+            .iter()
+            .map(|(address, wallet)| (address.clone(), wallet.staked_amount()))
+            .filter(|(_, stake)| *stake > 0)
+            .collect()
+    }
+
+    /// The counters maintained by this node since it started.
+    pub fn metrics(&self) -> NodeMetrics {
+        self.metrics
+    }
+
     /// Reports whether this node is aware of non-confirmed transactions
     pub fn has_pending_transactions(&self) -> bool {
         !self.pending_transactions.is_empty()
     }
 
-    /// Adds a transaction in the set of pending transactions
+    /// The number of non-confirmed transactions this node is aware of.
+    pub fn pending_transaction_count(&self) -> usize {
+        self.pending_transactions.len()
+    }
+
+    /// The transaction created for a recent request with the provided idempotency key, if any.
+    pub fn idempotent_transaction(&self, key: &str) -> Option<&Signed<Transaction>> {
+        self.idempotency_keys.get(key)
+    }
+
+    /// Remembers the transaction created for a request with the provided idempotency key, so that
+    /// a repeated request returns it instead of creating another one.
+    pub fn remember_idempotency_key(&mut self, key: String, tx: Signed<Transaction>) {
+        self.idempotency_keys.insert(key, tx);
+    }
+
+    /// A span tagging log lines with the name of this node and the current chain height. Returns
+    /// a disabled span when already inside a node span so that public methods calling each other
+    /// don't nest duplicate spans.
+    fn span(&self) -> Span {
+        let current = Span::current();
+        if current.metadata().is_some_and(|meta| meta.name() == "node") {
+            return Span::none();
+        }
+        tracing::info_span!("node", name = %self.name, height = self.blockchain.len())
+    }
+
+    /// Reports whether the transaction with the given hash is waiting in the mempool or has been
+    /// included in the blockchain.
+    pub fn transaction_status(&self, hash: &Hash) -> TxStatus {
+        let confirmed = self
+            .blockchain
+            .iter()
+            .enumerate()
+            .find(|(_, block)| block.data.transactions.iter().any(|tx| &tx.hash == hash));
+        if let Some((block_index, _)) = confirmed {
+            return TxStatus::Confirmed {
+                block_index,
+                confirmations: self.blockchain.len() - block_index,
+            };
+        }
+        if self
+            .pending_transactions
+            .values()
+            .any(|tx| &tx.hash == hash)
+        {
+            return TxStatus::Pending;
+        }
+        TxStatus::Unknown
+    }
+
+    /// The transaction with the provided hash, whether pending or confirmed.
+    fn find_transaction(&self, hash: &Hash) -> Option<&Signed<Transaction>> {
+        self.pending_transactions
+            .values()
+            .chain(
+                self.blockchain
+                    .iter()
+                    .flat_map(|block| &block.data.transactions),
+            )
+            .find(|tx| &tx.hash == hash)
+    }
+
+    /// Signs an acknowledgement of `tx` if acknowledgements are enabled and it's a message sent to
+    /// this node.
+    fn acknowledge(&self, tx: &Signed<Transaction>) -> Option<Signed<Acknowledgement>> {
+        let is_message = matches!(
+            tx.data.kind,
+            TransactionKind::Message(..) | TransactionKind::EncryptedMessage(..)
+        );
+        if !self.send_acks || !is_message || tx.data.receiver().as_ref() != Some(&self.address) {
+            return None;
+        }
+        tracing::debug!("acknowledging tx {:?}", tx.hash);
+        Some(self.private_key.sign(Acknowledgement {
+            tx_hash: tx.hash.clone(),
+        }))
+    }
+
+    /// Handles an acknowledgement received from the network. Acknowledgements of transactions sent
+    /// by this node are kept once verified, while the rest only need a valid signature to be
+    /// relayed.
+    pub fn handle_acknowledgement(&mut self, ack: Signed<Acknowledgement>) -> Result<()> {
+        let _span = self.span().entered();
+        ack.verify()?;
+        self.seen_messages.insert(ack.hash.clone());
+        let Some(tx) = self.find_transaction(&ack.data.tx_hash) else {
+            return Ok(());
+        };
+        if tx.data.sender_address == self.address {
+            verify_acknowledgement(&ack, tx)?;
+            tracing::info!("tx {:?} was acknowledged", tx.hash);
+            self.acknowledgements.insert(tx.hash.clone(), ack);
+        }
+        Ok(())
+    }
+
+    /// The equivocations of validators detected by this node, in the order they were detected.
+    pub fn equivocations(&self) -> &[Equivocation] {
+        &self.equivocations
+    }
+
+    /// Returns up to `limit` of the confirmed transactions sent or received by `address`, oldest
+    /// first, after skipping the first `offset` of them. The total number of confirmed
+    /// transactions of the address is returned along with the page.
+    pub fn transactions_for(
+        &self,
+        address: &Address,
+        offset: usize,
+        limit: usize,
+    ) -> (Vec<&Signed<Transaction>>, usize) {
+        let matching = self
+            .blockchain
+            .iter()
+            .flat_map(|block| block.data.transactions.iter())
+            .filter(|tx| {
+                &tx.data.sender_address == address || tx.data.receiver().as_ref() == Some(address)
+            });
+        let mut page = vec![];
+        let mut total = 0;
+        for tx in matching {
+            if total >= offset && page.len() < limit {
+                page.push(tx);
+            }
+            total += 1;
+        }
+        (page, total)
+    }
+
+    /// Sets the time after which this node mints a block even if it isn't full.
+    pub fn set_mint_interval(&mut self, interval: Duration) {
+        self.mint_interval = interval;
+    }
+
+    /// Makes this node read the time from the provided instant instead of the wall clock until it
+    /// is set again. Simulations use it to run several nodes reproducibly in simulated time.
+    pub fn set_simulated_time(&mut self, now: DateTime<Utc>) {
+        self.simulated_time = Some(now);
+    }
+
+    /// The current time as seen by this node.
+    fn now(&self) -> DateTime<Utc> {
+        self.simulated_time.unwrap_or_else(Utc::now)
+    }
+
+    /// Turns this node into an observer, or back into a regular node. Observers still validate and
+    /// relay blocks and transactions but never mint blocks or stake. An observer elected as a
+    /// validator stalls the blockchain, so observers must not hold any stake.
+    pub fn set_observer(&mut self, observer: bool) {
+        self.observer = observer;
+    }
+
+    /// Makes this node acknowledge the message transactions sent to it, as a delivery receipt for
+    /// their senders.
+    pub fn set_send_acks(&mut self, send_acks: bool) {
+        self.send_acks = send_acks;
+    }
+
+    /// The acknowledgement of a transaction sent by this node, if its receiver acknowledged it.
+    pub fn acknowledgement(&self, tx_hash: &Hash) -> Option<&Signed<Acknowledgement>> {
+        self.acknowledgements.get(tx_hash)
+    }
+
+    /// Makes this node stake `amount` on the first step its wallet has the funds to, e.g. once the
+    /// funds distributed during bootstrap arrive. Observers never stake.
+    pub fn set_initial_stake(&mut self, amount: u64) {
+        self.initial_stake = Some(amount);
+    }
+
+    /// Reports whether this node is an observer.
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+
+    /// Makes this node reject new transactions, from clients and peers alike, or accept them
+    /// again. A draining node keeps minting and accepting blocks, so its pending transactions are
+    /// eventually confirmed, e.g. before shutting it down for maintenance.
+    pub fn set_draining(&mut self, draining: bool) {
+        self.draining = draining;
+    }
+
+    /// Reports whether this node rejects new transactions.
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// Replaces the bounds on the size of the mempool. Already pending transactions are kept even
+    /// if they exceed the new bounds.
+    pub fn set_mempool_limits(&mut self, limits: MempoolLimits) {
+        self.mempool_limits = limits;
+    }
+
+    /// Adds a transaction in the set of pending transactions. When the mempool is full the
+    /// transaction replaces the pending transaction with the lowest fees, or is rejected if it
+    /// doesn't pay more than it. Transactions of other senders are rejected if they cost more than
+    /// the confirmed funds of their sender, so they can't claim fees they can't pay, and are
+    /// subject to the rate limit and the per sender bound of the mempool.
     pub fn handle_transaction(&mut self, tx: Signed<Transaction>) -> Result<()> {
-        let signer = tx.data.sender_address.clone();
+        let _span = self.span().entered();
+        let key = (tx.data.sender_address.clone(), tx.data.nonce);
+        // Transactions already in the mempool have been verified when they were first received.
+        if self.pending_transactions.get(&key) == Some(&tx) {
+            return Ok(());
+        }
+        if self.draining {
+            return Err(Error::NodeDraining);
+        }
         tx.verify()?;
-        self.pending_transactions
-            .insert((signer, tx.data.nonce), tx);
+        check_sender(&tx)?;
+        // The transactions of this node were already checked against its own wallet, which also
+        // accounts for its pending transactions
+        let own = tx.data.sender_address == self.address;
+        if !own {
+            self.check_confirmed_cost(&tx.data)?;
+        }
+        if !self.pending_transactions.contains_key(&key) {
+            if !own {
+                self.consume_allowance(&tx.data.sender_address)?;
+                self.check_sender_bound(&tx.data.sender_address)?;
+            }
+            self.make_room_for(&tx.data)?;
+        }
+        self.seen_messages.insert(tx.hash.clone());
+        self.pending_arrivals
+            .push_back((self.now(), key.clone(), tx.hash.clone()));
+        self.pending_transactions.insert(key, tx);
         // 2. Validate that there is enough balance
         Ok(())
     }
 
+    /// Takes one transaction out of the rate limit allowance of `sender`, after refilling it in
+    /// proportion to the time since it was last used.
+    fn consume_allowance(&mut self, sender: &Address) -> Result<()> {
+        let interval = self.mempool_limits.rate_interval;
+        if interval.is_zero() {
+            return Ok(());
+        }
+        let now = self.now();
+        let limit = f64::from(self.mempool_limits.max_per_interval);
+        let (allowance, updated) = self
+            .sender_allowances
+            .entry(sender.clone())
+            .or_insert((limit, now));
+        let elapsed = (now - *updated).to_std().unwrap_or_default();
+        *allowance =
+            (*allowance + limit * elapsed.as_secs_f64() / interval.as_secs_f64()).min(limit);
+        *updated = now;
+        if *allowance < 1.0 {
+            return Err(Error::RateLimited(sender.clone()));
+        }
+        *allowance -= 1.0;
+        Ok(())
+    }
+
+    /// Checks that the confirmed funds of the sender of `tx` cover its cost.
+    fn check_confirmed_cost(&self, tx: &Transaction) -> Result<()> {
+        match self.wallets.get(&tx.sender_address) {
+            Some(wallet) => wallet.check_cost(tx),
+            None => Wallet::with_fee_schedule(tx.sender_address.clone(), self.fee_schedule)
+                .check_cost(tx),
+        }
+    }
+
+    /// Checks that `sender` has fewer pending transactions than the mempool keeps per sender.
+    fn check_sender_bound(&self, sender: &Address) -> Result<()> {
+        let sender_pending = self
+            .pending_transactions
+            .range((sender.clone(), 0)..=(sender.clone(), u64::MAX))
+            .count();
+        if sender_pending >= self.mempool_limits.max_per_sender {
+            return Err(Error::MempoolFull);
+        }
+        Ok(())
+    }
+
+    /// Ensures the mempool can accept one more transaction like `tx`, evicting the pending
+    /// transaction with the lowest fees if necessary. Among equal fees the highest nonce is evicted
+    /// first since it is the furthest from being minted.
+    fn make_room_for(&mut self, tx: &Transaction) -> Result<()> {
+        if self.pending_transactions.len() < self.mempool_limits.max_transactions {
+            return Ok(());
+        }
+        let evicted = self
+            .pending_transactions
+            .iter()
+            .map(|(key, pending)| (pending.data.fees(&self.fee_schedule), key))
+            .min_by_key(|(fees, key)| (*fees, std::cmp::Reverse(key.1)));
+        match evicted {
+            Some((fees, key)) if fees < tx.fees(&self.fee_schedule) => {
+                let key = key.clone();
+                tracing::trace!("evicting tx {:?} from the full mempool", key);
+                self.pending_transactions.remove(&key);
+                Ok(())
+            }
+            _ => Err(Error::MempoolFull),
+        }
+    }
+
+    /// Evicts the pending transactions that were not minted within the TTL of the mempool.
+    fn expire_transactions(&mut self) {
+        let now = self.now();
+        while let Some((arrival, key, hash)) = self.pending_arrivals.front() {
+            if *arrival + self.mempool_limits.ttl > now {
+                break;
+            }
+            if self
+                .pending_transactions
+                .get(key)
+                .is_some_and(|tx| &tx.hash == hash)
+            {
+                tracing::info!("evicting tx {hash:?} that was pending since {arrival}");
+                self.pending_transactions.remove(key);
+            }
+            self.pending_arrivals.pop_front();
+        }
+
+        let interval = self.mempool_limits.rate_interval;
+        self.sender_allowances
+            .retain(|_, (_, updated)| *updated + interval > now);
+    }
+
+    /// The pending transaction following `key` from the same sender, keyed for minting.
+    fn next_candidate(&self, key: &(Address, u64)) -> Option<(u64, Reverse<(Address, u64)>)> {
+        let (sender, nonce) = key;
+        let range = (
+            Bound::Excluded((sender.clone(), *nonce)),
+            Bound::Included((sender.clone(), u64::MAX)),
+        );
+        let (next_key, tx) = self.pending_transactions.range(range).next()?;
+        Some((tx.data.fees(&self.fee_schedule), Reverse(next_key.clone())))
+    }
+
     /// Attempts to append the given block to the tip of the maintained blockchain. Returns an
     /// error if the block is invalid.
     pub fn handle_block(&mut self, block: Signed<Block>) -> Result<()> {
-        log::trace!(
-            "{}: handling block containing {} transactions",
-            self.name,
+        let _span = self.span().entered();
+        tracing::trace!(
+            "handling block containing {} transactions",
             block.data.transactions.len()
         );
-        // The block must be correctly signed
+        // The block must be correctly signed by the validator it names
         block.verify()?;
-
-        // TODO: Keep out-of-order blocks as pending.
-
-        // The signer must be the expected next validator
         let validator = block.data.validator.clone();
-        if validator != self.next_validator() {
+        if Address::from_public_key(&block.public_key) != validator {
             return Err(Error::InvalidBlockValidator);
         }
 
-        let mut total_fees = 0;
-        let mut new_wallets = self.wallets.clone();
-        for tx in block.data.transactions.iter() {
-            let sender = tx.data.sender_address.clone();
-            let sender_wallet = new_wallets
-                .entry(sender.clone())
-                .or_insert_with(|| Wallet::from_address(sender.clone()));
-
-            sender_wallet.apply_tx(tx.clone())?;
+        // A validator that already minted a block at this height is equivocating
+        self.check_equivocation(&block)?;
 
-            match &tx.data.kind {
-                TransactionKind::Coin(_, receiver) | TransactionKind::Message(_, receiver) => {
-                    let receiver_wallet = new_wallets
-                        .entry(receiver.clone())
-                        .or_insert_with(|| Wallet::from_address(receiver.clone()));
-
-                    receiver_wallet.apply_tx(tx.clone())?;
-                    if receiver == &self.address {
-                        self.node_wallet.apply_tx(tx.clone())?;
-                    }
-                }
-                TransactionKind::Stake(_) => {}
+        // The block must extend the tip of the blockchain. Out-of-order blocks are dropped and
+        // the missing ones are fetched with a `Message::GetBlocks` request instead, while a block
+        // extending an earlier block of the blockchain is invalid.
+        let tip = &self.blockchain.last().unwrap().hash;
+        if &block.data.parent_hash != tip {
+            let parent_hash = block.data.parent_hash.clone();
+            if self.has_block(&parent_hash) {
+                return Err(Error::InvalidParent(parent_hash, tip.clone()));
             }
+            return Err(Error::UnknownParent(parent_hash));
+        }
 
-            total_fees += tx.data.fees();
+        // The signer must be the expected next validator
+        let epoch = self.next_epoch()?;
+        if validator != epoch[self.epoch_slot(self.blockchain.len())] {
+            return Err(Error::InvalidBlockValidator);
         }
+        self.check_capacity(&block.data)?;
+        check_transaction_order(&block.data)?;
 
-        let validator_wallet = new_wallets
-            .entry(validator.clone())
-            .or_insert_with(|| Wallet::from_address(validator.clone()));
-        validator_wallet.add_funds(total_fees);
+        let applied = self.apply_block_transactions(&self.blockchain, &self.wallets, &block.data);
+        let new_wallets = match applied {
+            Ok(new_wallets) => new_wallets,
+            Err(err) => {
+                self.record_evidence(block);
+                return Err(err);
+            }
+        };
+
+        // The wallet of this node is only updated once the block is known to be valid
+        for tx in block.data.transactions.iter() {
+            if tx.data.receiver().as_ref() == Some(&self.address)
+                && tx.data.sender_address != self.address
+            {
+                self.node_wallet.apply_verified_tx(&tx.data)?;
+            }
+        }
         if validator == self.address {
+            let total_fees = block.data.total_fees(&self.fee_schedule);
             self.node_wallet.add_funds(total_fees);
         }
+        for evidence in block.data.evidence.iter() {
+            let offender = &evidence.data.validator;
+            tracing::warn!(
+                "slashed the stake of validator {offender} for block {:?}",
+                evidence.hash
+            );
+            if offender == &self.address {
+                self.node_wallet.slash(self.slashing_penalty);
+            }
+            self.pending_evidence.retain(|pending| {
+                pending.data.parent_hash != evidence.data.parent_hash
+                    || &pending.data.validator != offender
+            });
+        }
 
         for tx in block.data.transactions.iter() {
-            log::trace!("{}: accepted valid tx {:?}", self.name, tx.hash);
+            tracing::trace!("accepted valid tx {:?}", tx.hash);
             self.pending_transactions
                 .remove(&(tx.data.sender_address.clone(), tx.data.nonce));
         }
 
         self.wallets = new_wallets;
-        log::info!("{}: accepted valid block {:?}", self.name, block.hash);
+        self.epoch_validators = epoch;
+        // Blocks with a timestamp before their parent's, e.g. due to clock skew, count as instant
+        let parent_timestamp = self.blockchain.last().unwrap().data.timestamp;
+        let block_time = (block.data.timestamp - parent_timestamp)
+            .to_std()
+            .unwrap_or_default();
+        self.metrics.block_times.record(block_time);
+        self.metrics.blocks_accepted += 1;
+        self.metrics.transactions_processed += block.data.transactions.len() as u64;
+        tracing::info!("accepted valid block {:?}", block.hash);
+        self.seen_messages.insert(block.hash.clone());
         self.blockchain.push(block);
+        tracing::Span::current().record("height", self.blockchain.len());
 
         Ok(())
     }
 
-    /// Mints a block with at most `capacity` transactions.
-    pub fn mint_block(&mut self) -> Signed<Block> {
-        let mut tmp_wallets = self.wallets.clone();
+    /// Applies the evidence and the transactions of `block` on top of the provided state of the
+    /// wallets and returns the resulting state. `chain` is the blockchain the block extends.
+    /// Returns an error if any of the evidence or the transactions is invalid.
+    fn apply_block_transactions(
+        &self,
+        chain: &[Signed<Block>],
+        wallets: &BTreeMap<Address, Wallet>,
+        block: &Block,
+    ) -> Result<BTreeMap<Address, Wallet>> {
+        // A sender can't have more than one transaction with the same nonce in a block
+        let mut seen_nonces = BTreeSet::new();
+        for tx in block.transactions.iter() {
+            let key = (tx.data.sender_address.clone(), tx.data.nonce);
+            if !seen_nonces.insert(key) {
+                return Err(Error::DuplicateNonce(
+                    tx.data.sender_address.clone(),
+                    tx.data.nonce,
+                ));
+            }
+        }
 
-        let pending_transactions = std::mem::take(&mut self.pending_transactions);
-        let mut transactions = Vec::new();
+        // Slashing burns stake, so the supply is only conserved from that point on
+        let mut new_wallets = wallets.clone();
+        let mut slashed_heights = BTreeSet::new();
+        for evidence in block.evidence.iter() {
+            let height = check_evidence(chain, evidence)?;
+            if !slashed_heights.insert(height) {
+                return Err(Error::InvalidEvidence("the validator is already slashed"));
+            }
+            if let Some(wallet) = new_wallets.get_mut(&evidence.data.validator) {
+                wallet.slash(self.slashing_penalty);
+            }
+        }
+        let supply_before = total_supply(&new_wallets);
 
-        for (key, tx) in pending_transactions {
-            if transactions.len() < self.capacity {
-                let sender = tx.data.sender_address.clone();
-                let sender_wallet = tmp_wallets
-                    .entry(sender.clone())
-                    .or_insert_with(|| Wallet::from_address(sender.clone()));
-
-                match sender_wallet.apply_tx(tx.clone()) {
-                    Err(err @ Error::NonceReused(_, _)) => {
-                        log::trace!("{}: dropping invalid tx {:?}: {err}", self.name, tx.hash);
-                        continue;
-                    }
-                    Err(_) => {
-                        self.pending_transactions.insert(key, tx);
-                        continue;
-                    }
-                    Ok(_) => match tx.data.receiver() {
-                        Some(receiver) => {
-                            let receiver_wallet = tmp_wallets
-                                .entry(receiver.clone())
-                                .or_insert_with(|| Wallet::from_address(receiver.clone()));
-
-                            if sender != receiver {
-                                match receiver_wallet.apply_tx(tx.clone()) {
-                                    Ok(_) => {}
-                                    Err(_) => {
-                                        self.pending_transactions.insert(key, tx);
-                                        continue;
-                                    }
-                                }
-                            }
-                        }
-                        None => {}
-                    },
+        let mut total_fees = 0;
+        for tx in block.transactions.iter() {
+            // Transactions found in the mempool have already been verified
+            let key = (tx.data.sender_address.clone(), tx.data.nonce);
+            if self.pending_transactions.get(&key) != Some(tx) {
+                tx.verify()?;
+                check_sender(tx)?;
+            }
+
+            let sender = tx.data.sender_address.clone();
+            let sender_wallet = new_wallets
+                .entry(sender.clone())
+                .or_insert_with(|| Wallet::with_fee_schedule(sender.clone(), self.fee_schedule));
+
+            sender_wallet.apply_verified_tx(&tx.data)?;
+
+            match &tx.data.kind {
+                // A self-transfer was fully applied to the sender, which only loses the fees
+                TransactionKind::Coin(_, receiver)
+                | TransactionKind::Message(_, receiver)
+                | TransactionKind::EncryptedMessage(_, receiver)
+                | TransactionKind::Genesis(_, receiver)
+                    if receiver == &sender => {}
+                TransactionKind::Coin(_, receiver)
+                | TransactionKind::Message(_, receiver)
+                | TransactionKind::EncryptedMessage(_, receiver)
+                | TransactionKind::Genesis(_, receiver) => {
+                    let receiver_wallet =
+                        new_wallets.entry(receiver.clone()).or_insert_with(|| {
+                            Wallet::with_fee_schedule(receiver.clone(), self.fee_schedule)
+                        });
+
+                    receiver_wallet.apply_verified_tx(&tx.data)?;
                 }
+                TransactionKind::Stake(_) => {}
+            }
 
-                transactions.push(tx);
-            } else {
-                self.pending_transactions.insert(key, tx);
+            total_fees += tx.data.fees(&self.fee_schedule);
+        }
+
+        let validator = &block.validator;
+        let validator_wallet = new_wallets
+            .entry(validator.clone())
+            .or_insert_with(|| Wallet::with_fee_schedule(validator.clone(), self.fee_schedule));
+        validator_wallet.add_funds(total_fees);
+
+        // Fees move from the senders to the validator so the transactions never change the supply
+        let supply_after = total_supply(&new_wallets);
+        if supply_before != supply_after {
+            return Err(Error::SupplyMismatch(supply_before, supply_after));
+        }
+        Ok(new_wallets)
+    }
+
+    /// Keeps `block`, which is correctly signed by the elected validator but invalid, as evidence
+    /// for a later block. The penalty is only applied once a block carrying the evidence is
+    /// accepted, so every node burns the same stake at the same height.
+    fn record_evidence(&mut self, block: Signed<Block>) {
+        if self.slashing_penalty == 0 || self.seen_messages.contains(&block.hash) {
+            return;
+        }
+        self.seen_messages.insert(block.hash.clone());
+        tracing::warn!(
+            "validator {} signed the invalid block {:?}",
+            block.data.validator,
+            block.hash
+        );
+        self.pending_evidence.push(block);
+    }
+
+    /// Walks the whole blockchain checking that every block links to its parent, is signed by the
+    /// validator elected for its height and only contains validly signed transactions that can
+    /// be applied in order. Returns the first violation along with the index of its block.
+    pub fn verify_chain(&self) -> Result<()> {
+        let invalid = |index, err| Error::InvalidChain(index, Box::new(err));
+        let genesis = &self.blockchain[0];
+        Self::validate_genesis(genesis).map_err(|err| invalid(0, err))?;
+
+        let mut wallets = self.genesis_wallets.clone();
+        let mut epoch = vec![];
+        for (index, pair) in self.blockchain.windows(2).enumerate() {
+            let (parent, block) = (&pair[0], &pair[1]);
+            let index = index + 1;
+
+            if block.data.parent_hash != parent.hash {
+                let err = Error::InvalidParent(block.data.parent_hash.clone(), parent.hash.clone());
+                return Err(invalid(index, err));
+            }
+            block.verify().map_err(|err| invalid(index, err))?;
+            let slot = self.epoch_slot(index);
+            if slot == 0 {
+                epoch = elect_validators(&wallets, &parent.hash, self.epoch_length)
+                    .map_err(|err| invalid(index, err))?;
+            }
+            let validator = &epoch[slot];
+            if &block.data.validator != validator
+                || &Address::from_public_key(&block.public_key) != validator
+            {
+                return Err(invalid(index, Error::InvalidBlockValidator));
+            }
+            self.check_capacity(&block.data)
+                .map_err(|err| invalid(index, err))?;
+            check_transaction_order(&block.data).map_err(|err| invalid(index, err))?;
+            for tx in block.data.transactions.iter() {
+                tx.verify().map_err(|err| invalid(index, err))?;
+            }
+            wallets = self
+                .apply_block_transactions(&self.blockchain[..index], &wallets, &block.data)
+                .map_err(|err| invalid(index, err))?;
+        }
+        Ok(())
+    }
+
+    /// Checks that a block after genesis holds at most `capacity` transactions. The genesis block
+    /// is exempt since it contains one transaction per initial balance.
+    fn check_capacity(&self, block: &Block) -> Result<()> {
+        let len = block.transactions.len();
+        if len > self.capacity {
+            return Err(Error::BlockOverCapacity(len, self.capacity));
+        }
+        Ok(())
+    }
+
+    /// Checks whether a block with the same parent as `block` was already accepted from the same
+    /// validator. If so the two blocks are recorded as proof of the equivocation and an error is
+    /// returned.
+    fn check_equivocation(&mut self, block: &Signed<Block>) -> Result<()> {
+        let parent_height = self
+            .blockchain
+            .iter()
+            .position(|accepted| accepted.hash == block.data.parent_hash);
+        let Some(height) = parent_height.map(|height| height + 1) else {
+            return Ok(());
+        };
+        match self.blockchain.get(height) {
+            Some(accepted)
+                if accepted.data.validator == block.data.validator
+                    && accepted.hash != block.hash =>
+            {
+                tracing::warn!(
+                    "validator {} equivocated at height {height} with blocks {:?} and {:?}",
+                    block.data.validator,
+                    accepted.hash,
+                    block.hash
+                );
+                let equivocation = Equivocation {
+                    height,
+                    accepted: accepted.clone(),
+                    rejected: block.clone(),
+                };
+                if !self.equivocations.contains(&equivocation) {
+                    self.equivocations.push(equivocation);
+                }
+                Err(Error::ValidatorEquivocation(
+                    block.data.validator.clone(),
+                    height,
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Mints a block with at most `capacity` transactions. All pending transactions have been
+    /// verified when they entered the mempool so their signatures are not checked again.
+    ///
+    /// Transactions are greedily selected by fee. Each sender's transactions are considered in
+    /// nonce order, so a transaction only competes for inclusion once the ones before it from the
+    /// same sender have been included. Transactions after a gap in the nonces of their sender are
+    /// held back in the mempool until the missing nonce arrives.
+    ///
+    /// The selected transactions are stored in the canonical order of `canonical_order_key`.
+    pub fn mint_block(&mut self) -> Signed<Block> {
+        let _span = self.span().entered();
+        let mut tmp_wallets = self.wallets.clone();
+
+        let mut transactions = Vec::new();
+        // Transactions that can never become valid and should be dropped from the mempool
+        let mut invalid_transactions = Vec::new();
+
+        // The next transaction of each sender ordered by fee and then by key
+        let mut candidates = BinaryHeap::new();
+        let mut last_sender = None;
+        for (key, tx) in self.pending_transactions.iter() {
+            if last_sender != Some(&key.0) {
+                candidates.push((tx.data.fees(&self.fee_schedule), Reverse(key.clone())));
+                last_sender = Some(&key.0);
+            }
+        }
+
+        // The selected transactions stay in the mempool until the block is accepted
+        while let Some((_, Reverse(key))) = candidates.pop() {
+            if transactions.len() >= self.capacity {
+                break;
+            }
+            let tx = &self.pending_transactions[&key];
+            let sender = tx.data.sender_address.clone();
+            let sender_wallet = tmp_wallets
+                .entry(sender.clone())
+                .or_insert_with(|| Wallet::with_fee_schedule(sender.clone(), self.fee_schedule));
+
+            if tx.data.nonce > sender_wallet.nonce {
+                tracing::trace!(
+                    "holding back tx {:?} until nonce {} arrives",
+                    tx.hash,
+                    sender_wallet.nonce
+                );
+                continue;
+            }
+
+            match sender_wallet.apply_verified_tx(&tx.data) {
+                Err(err @ Error::NonceReused(_, _)) => {
+                    tracing::trace!("dropping invalid tx {:?}: {err}", tx.hash);
+                    invalid_transactions.push(key.clone());
+                    candidates.extend(self.next_candidate(&key));
+                    continue;
+                }
+                // A transaction its sender can't afford even with its confirmed funds was
+                // admitted before they were spent or slashed, so it is dropped instead of taking
+                // up the mempool until it expires
+                Err(err @ Error::InsufficientFunds)
+                    if self.check_confirmed_cost(&tx.data).is_err() =>
+                {
+                    tracing::trace!("dropping unaffordable tx {:?}: {err}", tx.hash);
+                    invalid_transactions.push(key.clone());
+                    continue;
+                }
+                // The later transactions of this sender are held back
+                Err(_) => continue,
+                Ok(_) => match tx.data.receiver() {
+                    // A self-transfer was fully applied to the sender, which only loses the fees
+                    Some(receiver) if receiver == sender => {}
+                    Some(receiver) => {
+                        let receiver_wallet =
+                            tmp_wallets.entry(receiver.clone()).or_insert_with(|| {
+                                Wallet::with_fee_schedule(receiver.clone(), self.fee_schedule)
+                            });
+
+                        if receiver_wallet.apply_verified_tx(&tx.data).is_err() {
+                            continue;
+                        }
+                    }
+                    None => {}
+                },
             }
+
+            transactions.push(tx.clone());
+            candidates.extend(self.next_candidate(&key));
+        }
+
+        for key in invalid_transactions {
+            self.pending_transactions.remove(&key);
         }
 
+        transactions.sort_by(|a, b| canonical_order_key(a).cmp(&canonical_order_key(b)));
+        self.retain_applicable(&mut transactions);
+
         let new_block = Block {
-            timestamp: Utc::now(),
+            timestamp: self.now(),
             transactions,
-            validator: Address::from_public_key(&self.public_key),
+            validator: self.address.clone(),
             parent_hash: self.blockchain.last().unwrap().hash.clone(),
+            evidence: self.includable_evidence(),
         };
 
+        self.metrics.blocks_minted += 1;
         self.private_key.sign(new_block)
     }
 
+    /// The pending evidence that can be included in the next block, at most one per offending
+    /// block height. Evidence for a height without an accepted block yet is kept for later.
+    fn includable_evidence(&self) -> Vec<Signed<Block>> {
+        let mut heights = BTreeSet::new();
+        self.pending_evidence
+            .iter()
+            .filter(|evidence| {
+                check_evidence(&self.blockchain, evidence)
+                    .is_ok_and(|height| heights.insert(height))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Drops the selected transactions that are no longer valid once sorted in canonical order. A
+    /// transaction selected after the one funding it may sort before it, in which case it stays in
+    /// the mempool for a later block along with the later transactions of its sender.
+    fn retain_applicable(&self, transactions: &mut Vec<Signed<Transaction>>) {
+        let mut wallets = self.wallets.clone();
+        transactions.retain(|tx| {
+            // Pending transactions were checked by `check_sender` when they entered the mempool
+            if matches!(tx.data.kind, TransactionKind::Genesis(_, _)) {
+                return false;
+            }
+            let sender = tx.data.sender_address.clone();
+            let sender_wallet = wallets
+                .entry(sender.clone())
+                .or_insert_with(|| Wallet::with_fee_schedule(sender.clone(), self.fee_schedule));
+            if tx.data.nonce > sender_wallet.nonce
+                || sender_wallet.apply_verified_tx(&tx.data).is_err()
+            {
+                return false;
+            }
+            match tx.data.receiver() {
+                Some(receiver) if receiver != sender => wallets
+                    .entry(receiver.clone())
+                    .or_insert_with(|| Wallet::with_fee_schedule(receiver, self.fee_schedule))
+                    .apply_verified_tx(&tx.data)
+                    .is_ok(),
+                _ => true,
+            }
+        });
+    }
+
     pub fn sign_transaction(&self, tx: Transaction) -> Signed<Transaction> {
         self.private_key.sign(tx)
     }
 
-    /// Broadcasts a transaction to the network
-    pub fn broadcast_transaction(&mut self, tx: Signed<Transaction>) {
-        log::trace!(
-            "{}: broadcasting tx {:?}: {:?}",
-            self.name,
-            tx.hash,
-            tx.data
-        );
-        if let Err(err) = self.handle_transaction(tx.clone()) {
-            log::warn!("{}: broadcasting invalid transaction {err}", self.name);
+    /// Reports whether the block with the given hash is part of the blockchain.
+    fn has_block(&self, hash: &Hash) -> bool {
+        self.blockchain.iter().any(|block| &block.hash == hash)
+    }
+
+    /// The request for the blocks following the tip of the blockchain.
+    fn sync_request(&self) -> Message {
+        Message::GetBlocks {
+            from: self.blockchain.len(),
+        }
+    }
+
+    /// Asks the peers for the blocks this node is missing. The blocks they respond with are
+    /// applied on a following step.
+    pub fn request_sync(&mut self) {
+        let request = self.sync_request();
+        self.outbox.push(request);
+    }
+
+    /// Appends consecutive blocks received from a peer to the blockchain. Blocks that are already
+    /// part of the blockchain are skipped and every other block is validated as usual.
+    pub fn handle_blocks(&mut self, blocks: Vec<Signed<Block>>) -> Result<()> {
+        let _span = self.span().entered();
+        for block in blocks {
+            if self.has_block(&block.hash) {
+                continue;
+            }
+            if block.data.parent_hash == GENESIS_PARENT_HASH {
+                Self::validate_genesis(&block)?;
+                return Err(Error::InvalidGenesis(
+                    "different from the local genesis block",
+                ));
+            }
+            self.handle_block(block)?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts a transaction to the network, unless the mempool of this node rejects it.
+    pub fn broadcast_transaction(&mut self, tx: Signed<Transaction>) -> Result<()> {
+        self.broadcast_transactions(std::slice::from_ref(&tx))
+    }
+
+    /// Broadcasts several transactions to the network. If the mempool of this node rejects any of
+    /// them none is broadcast and the ones already added to the mempool are removed again.
+    pub fn broadcast_transactions(&mut self, txs: &[Signed<Transaction>]) -> Result<()> {
+        let _span = self.span().entered();
+        for (index, tx) in txs.iter().enumerate() {
+            tracing::trace!("broadcasting tx {:?}: {:?}", tx.hash, tx.data);
+            if let Err(err) = self.handle_transaction(tx.clone()) {
+                for tx in &txs[..index] {
+                    let key = (tx.data.sender_address.clone(), tx.data.nonce);
+                    self.pending_transactions.remove(&key);
+                }
+                return Err(err);
+            }
+        }
+        self.outbox
+            .extend(txs.iter().cloned().map(Message::Transaction));
+        Ok(())
+    }
+
+    /// Applies a transaction to the wallet of this node and broadcasts it. The wallet is only
+    /// updated if the mempool accepts the transaction, so the nonce of a rejected transaction is
+    /// reused by the next one.
+    pub fn send_transaction(&mut self, tx: Signed<Transaction>) -> Result<()> {
+        let mut wallet = self.node_wallet.clone();
+        wallet.apply_tx(tx.clone())?;
+        self.broadcast_transaction(tx)?;
+        self.node_wallet = wallet;
+        Ok(())
+    }
+
+    /// Accepts a transaction signed outside of this node and broadcasts it. Unlike
+    /// `broadcast_transaction` the transaction is rejected unless it is signed by its sender and is
+    /// valid given the state of the sender's wallet as of the last accepted block.
+    pub fn submit_transaction(&mut self, tx: Signed<Transaction>) -> Result<()> {
+        let _span = self.span().entered();
+        let sender = &tx.data.sender_address;
+        if Address::from_public_key(&tx.public_key) != *sender {
+            return Err(Error::ForeignSigner);
         }
+        let mut wallet = self
+            .wallets
+            .get(sender)
+            .cloned()
+            .unwrap_or_else(|| Wallet::with_fee_schedule(sender.clone(), self.fee_schedule));
+        wallet.check_cost(&tx.data)?;
+        let tx = wallet.validate_tx(tx)?;
+        self.handle_transaction(tx.clone())?;
         self.outbox.push(Message::Transaction(tx));
+        Ok(())
     }
 
     /// Broadcasts a block to the network
     pub fn broadcast_block(&mut self, block: Signed<Block>) {
+        let _span = self.span().entered();
         if let Err(err) = self.handle_block(block.clone()) {
-            log::warn!("{}: broadcasting invalid block {err}", self.name);
+            tracing::warn!("broadcasting invalid block {err}");
         }
         self.outbox.push(Message::Block(block));
     }
 
     pub fn step<N: Network<Message>>(&mut self, network: &mut N) -> Option<Duration> {
+        let _span = self.span().entered();
         // First send all outstanding messages to the network
         for message in self.outbox.drain(..) {
             network.send(&message);
         }
 
+        // Drop the transactions that have been pending for too long
+        self.expire_transactions();
+
         // Then handle all pending messages from the network
         while let Some(msg) = network.recv() {
-            match msg {
-                Message::Transaction(tx) => match self.handle_transaction(tx) {
-                    Ok(_) => {}
-                    Err(err) => log::info!("{}: rejected invalid transaction {err}", self.name),
+            // Messages can loop in the network so duplicates are dropped before verification
+            let hash = match &msg {
+                Message::Transaction(tx) => Some(&tx.hash),
+                Message::Block(block) => Some(&block.hash),
+                Message::Ack(ack) => Some(&ack.hash),
+                Message::GetBlocks { .. } | Message::Blocks(_) => None,
+            };
+            if let Some(hash) = hash.filter(|hash| self.seen_messages.contains(hash)) {
+                tracing::trace!("dropping already seen message {hash:?}");
+                continue;
+            }
+            // Newly accepted messages are relayed so that peers not directly connected to the
+            // sender also receive them. The seen messages stop them from looping forever.
+            match &msg {
+                Message::Transaction(tx) => match self.handle_transaction(tx.clone()) {
+                    Ok(_) => {
+                        network.send(&msg);
+                        if let Some(ack) = self.acknowledge(tx) {
+                            self.seen_messages.insert(ack.hash.clone());
+                            network.send(&Message::Ack(ack));
+                        }
+                    }
+                    Err(err) => tracing::info!("rejected invalid transaction {err}"),
+                },
+                Message::Ack(ack) => match self.handle_acknowledgement(ack.clone()) {
+                    Ok(_) => network.send(&msg),
+                    Err(err) => tracing::info!("rejected invalid acknowledgement {err}"),
+                },
+                Message::Block(block) => match self.handle_block(block.clone()) {
+                    Ok(_) => network.send(&msg),
+                    Err(err) => {
+                        tracing::info!("rejected invalid block {err}");
+                        // A block we can't link to our blockchain means we are lagging behind
+                        if matches!(err, Error::UnknownParent(_)) {
+                            network.send(&self.sync_request());
+                        }
+                    }
                 },
-                Message::Block(block) => match self.handle_block(block) {
+                Message::GetBlocks { from } => {
+                    let end = self
+                        .blockchain
+                        .len()
+                        .min(from.saturating_add(MAX_SYNC_BLOCKS));
+                    if let Some(blocks) = self.blockchain.get(*from..end) {
+                        if !blocks.is_empty() {
+                            network.send(&Message::Blocks(blocks.to_vec()));
+                        }
+                    }
+                }
+                Message::Blocks(blocks) => match self.handle_blocks(blocks.clone()) {
+                    // A full response means there may be more blocks to fetch
+                    Ok(_) if blocks.len() == MAX_SYNC_BLOCKS => network.send(&self.sync_request()),
                     Ok(_) => {}
-                    Err(err) => log::info!("{}: rejected invalid block {err}", self.name),
+                    Err(err) => tracing::info!("rejected synced blocks {err}"),
                 },
             }
         }
 
+        // Observers only react to the messages of their peers
+        if self.observer {
+            return None;
+        }
+
+        if let Some(amount) = self.initial_stake {
+            if amount <= self.node_wallet.balance {
+                let tx = self.sign_transaction(self.node_wallet.create_stake_tx(amount));
+                match self.send_transaction(tx) {
+                    Ok(()) => {
+                        tracing::info!("staking {amount}");
+                        self.initial_stake = None;
+                    }
+                    // The mempool may have room for it on a later step
+                    Err(err @ (Error::MempoolFull | Error::NodeDraining)) => {
+                        tracing::debug!("postponing staking {amount}: {err}");
+                    }
+                    Err(err) => {
+                        tracing::warn!("failed to stake {amount}: {err}");
+                        self.initial_stake = None;
+                    }
+                }
+            }
+        }
+
+        let validator = match self.next_validator() {
+            Ok(validator) => validator,
+            Err(err) => {
+                tracing::debug!("not minting: {err}");
+                return None;
+            }
+        };
+
         // TODO: this might have to run multiple times per step if this node is the validator of
         // the next block too.
-        if self.address == self.next_validator() {
+        if self.address == validator {
             let last_block_ts = self.blockchain().last().unwrap().data.timestamp;
-            let next_block_ts = last_block_ts + MINT_INTERVAL;
+            let next_block_ts = last_block_ts + self.mint_interval;
             // A new block is minted if we have enough pending transaction to create a full block
             // or if enough time has passed from the previous mint.
-            if Utc::now() > next_block_ts || self.pending_transactions.len() >= self.capacity {
+            let now = self.now();
+            if now > next_block_ts || self.pending_transactions.len() >= self.capacity {
                 let block = self.mint_block();
-                log::info!("{}: broadcasting minted block {:?}", self.name, block.hash);
+                tracing::info!("broadcasting minted block {:?}", block.hash);
                 self.handle_block(block.clone())
                     .expect("minted block was invalid");
                 network.send(&Message::Block(block));
-                if self.address == self.next_validator() {
-                    Some(MINT_INTERVAL)
+                if self
+                    .next_validator()
+                    .is_ok_and(|validator| validator == self.address)
+                {
+                    Some(self.mint_interval)
                 } else {
                     None
                 }
             } else {
-                Some((next_block_ts - Utc::now()).to_std().unwrap())
+                Some((next_block_ts - now).to_std().unwrap())
             }
         } else {
             None
@@ -364,10 +1595,180 @@ impl Node {
     }
 }
 
+/// Elects the validators of the `length` blocks of an epoch given the state of the wallets when it
+/// starts. Returns an error if no wallet has staked any funds.
+///
+/// The stakes of the wallets are laid out back to back in address order, so that the wallet with
+/// stake `s_i` owns the `s_i` tickets `[s_0 + .. + s_(i-1), s_0 + .. + s_i)` out of `total_stake`.
+/// A `StdRng` seeded with `seed` draws a ticket uniformly from `[0, total_stake)` for the first
+/// block and the tickets of the remaining blocks are spaced evenly after it, wrapping around. Every
+/// node therefore elects the same validators, each wallet is elected with a probability of exactly
+/// `s_i / total_stake` for every block and it gets either the floor or the ceiling of its share of
+/// the epoch. The order of the blocks is then shuffled by the same `StdRng`. Wallets without stake
+/// own no tickets.
+fn elect_validators(
+    wallets: &BTreeMap<Address, Wallet>,
+    seed: &Hash,
+    length: usize,
+) -> Result<Vec<Address>> {
+    let mut rng = StdRng::from_seed(seed.0);
+    let total_stake: u64 = wallets.values().map(|w| w.staked_amount()).sum();
+    if total_stake == 0 {
+        return Err(Error::NoStakers);
+    }
+
+    // Walk the tickets until reaching the wallet that owns the winning one. The remaining offset
+    // is always below the total stake of the wallets not yet visited, so a wallet is found.
+    let owner = |ticket: u64| {
+        let mut winner = ticket;
+        wallets
+            .values()
+            .find_map(|wallet| {
+                if winner < wallet.staked_amount() {
+                    Some(wallet.address.clone())
+                } else {
+                    winner -= wallet.staked_amount();
+                    None
+                }
+            })
+            .unwrap()
+    };
+    let draw = rng.gen_range(0..total_stake);
+    let mut tickets: Vec<u64> = (0..length as u128)
+        .map(|slot| {
+            let spacing = slot * total_stake as u128 / length as u128;
+            ((draw as u128 + spacing) % total_stake as u128) as u64
+        })
+        .collect();
+    tickets.shuffle(&mut rng);
+    let mut validators = Vec::with_capacity(length);
+    for (slot, ticket) in tickets.into_iter().enumerate() {
+        let validator = owner(ticket);
+        // Every node logs the same election for the same seed, which makes diverging wallet
+        // states stand out when comparing the logs of several nodes
+        tracing::trace!(seed = %seed, total_stake, slot, ticket, %validator, "elected validator");
+        validators.push(validator);
+    }
+    Ok(validators)
+}
+
+/// The sum of the balances of the provided wallets.
+fn total_supply(wallets: &BTreeMap<Address, Wallet>) -> u64 {
+    wallets.values().map(|wallet| wallet.balance).sum()
+}
+
+/// The canonical order of the transactions in a block: by sender, then by nonce and finally by hash.
+/// Validators with the same mempool therefore produce the same block.
+fn canonical_order_key(tx: &Signed<Transaction>) -> (&Address, u64, &Hash) {
+    (&tx.data.sender_address, tx.data.nonce, &tx.hash)
+}
+
+/// Checks that `evidence` is a block signed by the validator of a block of `chain` that extends
+/// the same parent but differs from it, and that the validator was not already slashed for that
+/// height. Honest validators sign a single block per height, so every node can verify the
+/// evidence without the state the conflicting block was applied on. Returns the height of the
+/// conflicting block.
+fn check_evidence(chain: &[Signed<Block>], evidence: &Signed<Block>) -> Result<usize> {
+    evidence.verify()?;
+    let offender = &evidence.data.validator;
+    if &Address::from_public_key(&evidence.public_key) != offender {
+        return Err(Error::InvalidEvidence("not signed by its validator"));
+    }
+    let parent_height = chain
+        .iter()
+        .position(|block| block.hash == evidence.data.parent_hash)
+        .ok_or(Error::InvalidEvidence("unknown parent"))?;
+    let height = parent_height + 1;
+    let accepted = chain.get(height).ok_or(Error::InvalidEvidence(
+        "no block was accepted at its height",
+    ))?;
+    if &accepted.data.validator != offender {
+        return Err(Error::InvalidEvidence("not the validator of its height"));
+    }
+    if accepted.hash == evidence.hash {
+        return Err(Error::InvalidEvidence("the block was accepted"));
+    }
+    let already_slashed = chain[height + 1..]
+        .iter()
+        .flat_map(|block| block.data.evidence.iter())
+        .any(|slashed| {
+            slashed.data.parent_hash == evidence.data.parent_hash
+                && &slashed.data.validator == offender
+        });
+    if already_slashed {
+        return Err(Error::InvalidEvidence("the validator is already slashed"));
+    }
+    Ok(height)
+}
+
+/// Checks that a transaction outside the genesis block is signed by its sender and doesn't mint
+/// funds. The transactions of the genesis block are unsigned and never go through this check.
+fn check_sender(tx: &Signed<Transaction>) -> Result<()> {
+    if matches!(tx.data.kind, TransactionKind::Genesis(_, _)) {
+        return Err(Error::SystemTransaction);
+    }
+    if Address::from_public_key(&tx.public_key) != tx.data.sender_address {
+        return Err(Error::ForeignSigner);
+    }
+    Ok(())
+}
+
+/// Checks that the transactions of a block are sorted in canonical order.
+fn check_transaction_order(block: &Block) -> Result<()> {
+    if !block.transactions.is_sorted_by_key(canonical_order_key) {
+        return Err(Error::NonCanonicalOrder);
+    }
+    Ok(())
+}
+
+/// The inclusion status of a transaction as seen by a node.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum TxStatus {
+    /// The transaction is waiting in the mempool.
+    Pending,
+    /// The transaction is included in the block at `block_index`. The number of confirmations is
+    /// the chain height minus the index of that block, so the tip block has one confirmation.
+    Confirmed {
+        block_index: usize,
+        confirmations: usize,
+    },
+    /// The node has never seen the transaction.
+    Unknown,
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum Message {
     Transaction(Signed<Transaction>),
     Block(Signed<Block>),
+    /// A request for the blocks of the blockchain starting at index `from`.
+    GetBlocks {
+        from: usize,
+    },
+    /// Consecutive blocks of the blockchain sent in response to `GetBlocks`.
+    Blocks(Vec<Signed<Block>>),
+    /// The receipt of a message transaction, signed by its receiver.
+    Ack(Signed<Acknowledgement>),
+}
+
+/// A delivery receipt of a message transaction. The node of the receiver signs it when it first
+/// sees the transaction, so the sender can prove the receiver's node saw the message.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Acknowledgement {
+    /// The hash of the acknowledged transaction.
+    pub tx_hash: Hash,
+}
+
+/// Checks that `ack` acknowledges `tx` and is signed by the receiver of `tx`.
+pub fn verify_acknowledgement(
+    ack: &Signed<Acknowledgement>,
+    tx: &Signed<Transaction>,
+) -> Result<()> {
+    ack.verify()?;
+    let signer = Address::from_public_key(&ack.public_key);
+    if ack.data.tx_hash != tx.hash || tx.data.receiver() != Some(signer) {
+        return Err(Error::InvalidAcknowledgement);
+    }
+    Ok(())
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -380,31 +1781,102 @@ pub struct Block {
     pub validator: Address,
     /// The hash of the parent block.
     pub parent_hash: Hash,
+    /// Blocks signed by the validators of earlier blocks that conflict with the block accepted at
+    /// their height, e.g. because they contain invalid transactions. Each one burns
+    /// `slashing_penalty` of the stake of its validator when this block is applied.
+    #[serde(default)]
+    pub evidence: Vec<Signed<Block>>,
+}
+
+impl Block {
+    /// The fees the validator of this block collects under the provided fee schedule.
+    pub fn total_fees(&self, fee_schedule: &FeeSchedule) -> u64 {
+        self.transactions
+            .iter()
+            .map(|tx| tx.data.fees(fee_schedule))
+            .sum()
+    }
+
+    /// A one line summary of this block with the fees charged under the provided fee schedule.
+    pub fn summary(&self, fee_schedule: FeeSchedule) -> BlockSummary<'_> {
+        BlockSummary {
+            block: self,
+            fee_schedule,
+        }
+    }
+}
+
+/// Summarizes a block without listing its transactions, unlike its `Debug` output. The fees are
+/// those of the default fee schedule, see `Block::summary` for other networks.
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.summary(FeeSchedule::default()).fmt(f)
+    }
+}
+
+/// The one line summary of a block returned by `Block::summary`.
+pub struct BlockSummary<'a> {
+    block: &'a Block,
+    fee_schedule: FeeSchedule,
+}
+
+impl fmt::Display for BlockSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let block = self.block;
+        write!(
+            f,
+            "minted at {} by {} on top of {}: {} transactions, {} BCC in fees",
+            block.timestamp.to_rfc3339(),
+            block.validator,
+            block.parent_hash,
+            block.transactions.len(),
+            block.total_fees(&self.fee_schedule),
+        )
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{crypto, network::TestNetwork};
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        cluster::TestCluster,
+        crypto,
+        network::{TestNetwork, TestNetworkBuilder},
+    };
 
     use super::*;
 
+    /// Credits `amount` to the confirmed wallet of `key` on `node`, as if a block had sent it.
+    fn fund(node: &mut Node, key: &PrivateKey, amount: u64) {
+        let address = Address::from_public_key(&key.public_key());
+        let fee_schedule = node.fee_schedule;
+        node.wallets
+            .entry(address.clone())
+            .or_insert_with(|| Wallet::with_fee_schedule(address, fee_schedule))
+            .add_funds(amount);
+    }
+
     #[test]
     fn basic_test() {
         let (mut network1, mut network2) = TestNetwork::new();
 
-        let (node_private_key, node_public_key) = crypto::generate_keypair();
+        let (node_private_key, node_public_key) = crypto::generate_test_keypair();
+        let (_, validator_public_key) = crypto::generate_test_keypair();
         let mut node = Node::new(
             "test_node".into(),
-            node_public_key.clone(),
-            node_private_key,
             node_public_key,
+            node_private_key,
+            validator_public_key,
             1_000_000,
             5,
         );
 
-        // Now create a transaction from a wallet that is not tracked and send it to the node
-        let (user_key, user_public_key) = crypto::generate_keypair();
+        // Now create a transaction from another wallet and send it to the node, which keeps it
+        // until the validator mints it
+        let (user_key, user_public_key) = crypto::generate_test_keypair();
         let user_wallet = Wallet::from_public_key(&user_public_key);
+        fund(&mut node, &user_key, 1000);
         let tx = user_wallet.create_coin_tx(Address::from_public_key(&node.public_key), 42);
         network2.send(&Message::Transaction(user_key.sign(tx)));
         node.step(&mut network1);
@@ -419,43 +1891,1794 @@ mod test {
     }
 
     #[test]
-    fn test_mint_block() {
-        let (mut node_wallet, node_public_key, node_private_key) =
-            crate::wallet::test::setup_default_test_wallet();
-        let (receiver_wallet, _, _) = crate::wallet::test::setup_default_test_wallet();
+    fn test_duplicate_block_message() {
+        let (mut network1, mut network2) = TestNetwork::new();
 
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut validator = Node::new(
+            "validator".into(),
+            validator_public_key.clone(),
+            validator_private_key,
+            validator_public_key.clone(),
+            1_000_000,
+            5,
+        );
         let mut node = Node::new(
             "test_node".into(),
-            node_public_key.clone(),
-            node_private_key.clone(),
-            node_public_key.clone(),
+            node_public_key,
+            node_private_key,
+            validator_public_key,
             1_000_000,
             5,
         );
 
-        const TRANSACTION_COUNT: usize = 7;
-        let coin_amount = 1000;
-        let mut transactions = Vec::new();
+        // The same block is delivered twice
+        let block = validator.mint_block();
+        network2.send(&Message::Block(block.clone()));
+        network2.send(&Message::Block(block.clone()));
+        node.step(&mut network1);
 
-        // Apply more transactions than the block capacity
-        for _ in 0..TRANSACTION_COUNT {
-            let tx = node_wallet
-                .clone()
-                .create_coin_tx(receiver_wallet.address.clone(), coin_amount);
-            let signed_tx = node_private_key.sign(tx.clone());
+        // The block is applied and relayed only once
+        assert_eq!(node.blockchain.len(), 2);
+        assert_eq!(network2.recv(), Some(Message::Block(block)));
+        assert_eq!(network2.recv(), None);
+    }
 
-            node_wallet.apply_tx(signed_tx.clone()).unwrap();
-            node.handle_transaction(signed_tx.clone()).unwrap();
+    #[test]
+    fn test_initial_stake() {
+        let (mut network1, mut network2) = TestNetwork::new();
 
-            if transactions.len() < node.capacity {
-                transactions.push(signed_tx);
-            }
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut validator = Node::new(
+            "validator".into(),
+            validator_public_key.clone(),
+            validator_private_key,
+            validator_public_key.clone(),
+            1_000_000,
+            5,
+        );
+        let mut node = Node::new(
+            "node".into(),
+            node_public_key,
+            node_private_key,
+            validator_public_key,
+            1_000_000,
+            5,
+        );
+        validator.set_mint_interval(Duration::ZERO);
+        node.set_mint_interval(Duration::ZERO);
+        node.set_initial_stake(100);
+
+        // Nothing is staked before the funds arrive
+        node.step(&mut network2);
+        assert!(!node.has_pending_transactions());
+
+        let tx = validator.wallet().create_coin_tx(node.address.clone(), 300);
+        let tx = validator.sign_transaction(tx);
+        validator.wallet_mut().apply_tx(tx.clone()).unwrap();
+        validator.broadcast_transaction(tx).unwrap();
+        for _ in 0..3 {
+            validator.step(&mut network1);
+            node.step(&mut network2);
         }
 
-        let block = node.mint_block();
-        assert_eq!(block.data.transactions.len(), 5);
-        assert_eq!(block.data.transactions, transactions);
-        assert_eq!(block.data.validator, node_wallet.address);
-        assert_eq!(block.data.parent_hash, node.blockchain[0].hash);
+        let stakers = validator.stakers();
+        assert!(
+            stakers.contains(&(node.address.clone(), 100)),
+            "{stakers:?}"
+        );
+        assert_eq!(node.wallet().staked_amount(), 100);
+        // The stake is only submitted once
+        assert_eq!(node.wallet().nonce, 1);
+    }
+
+    #[test]
+    fn test_observer() {
+        let (mut network1, mut network2) = TestNetwork::new();
+
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (observer_private_key, observer_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut validator = Node::new(
+            "validator".into(),
+            validator_public_key.clone(),
+            validator_private_key,
+            validator_public_key.clone(),
+            1_000_000,
+            5,
+        );
+        let mut observer = Node::new(
+            "observer".into(),
+            observer_public_key.clone(),
+            observer_private_key.clone(),
+            validator_public_key,
+            1_000_000,
+            5,
+        );
+        observer.set_observer(true);
+        validator.set_mint_interval(Duration::ZERO);
+        observer.set_mint_interval(Duration::ZERO);
+
+        for _ in 0..5 {
+            let tx = validator
+                .wallet()
+                .create_coin_tx(observer.address.clone(), 10);
+            let tx = validator.sign_transaction(tx);
+            validator.wallet_mut().apply_tx(tx.clone()).unwrap();
+            validator.broadcast_transaction(tx).unwrap();
+            validator.step(&mut network1);
+            assert_eq!(observer.step(&mut network2), None);
+        }
+
+        // The observer tracks the blockchain and the state of the wallets
+        assert_eq!(validator.blockchain.len(), 6);
+        assert_eq!(observer.blockchain, validator.blockchain);
+        assert_eq!(observer.wallet().balance, 50);
+        assert_eq!(observer.metrics().blocks_minted, 0);
+        assert!(observer.verify_chain().is_ok());
+
+        // An observer never mints, even when it is the only staker
+        let mut observer = Node::new(
+            "observer".into(),
+            observer_public_key.clone(),
+            observer_private_key,
+            observer_public_key,
+            1_000_000,
+            5,
+        );
+        observer.set_observer(true);
+        observer.set_mint_interval(Duration::ZERO);
+        observer.step(&mut network2);
+        assert_eq!(observer.blockchain.len(), 1);
+    }
+
+    #[test]
+    fn test_acknowledgement() {
+        let (mut network1, mut network2) = TestNetwork::new();
+
+        let (sender_private_key, sender_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (receiver_private_key, receiver_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut sender = Node::new(
+            "sender".into(),
+            sender_public_key.clone(),
+            sender_private_key,
+            sender_public_key,
+            1_000_000,
+            5,
+        );
+        let mut receiver = Node::new(
+            "receiver".into(),
+            receiver_public_key.clone(),
+            receiver_private_key,
+            sender.public_key.clone(),
+            1_000_000,
+            5,
+        );
+        receiver.set_send_acks(true);
+
+        let tx = sender
+            .wallet()
+            .create_message_tx(receiver.address.clone(), "hello".into());
+        let tx = sender.sign_transaction(tx);
+        sender.wallet_mut().apply_tx(tx.clone()).unwrap();
+        sender.broadcast_transaction(tx.clone()).unwrap();
+        sender.step(&mut network1);
+        receiver.step(&mut network2);
+        sender.step(&mut network1);
+
+        // The sender keeps the receipt signed by the receiver
+        let ack = sender.acknowledgement(&tx.hash).unwrap().clone();
+        assert_eq!(ack.data.tx_hash, tx.hash);
+        assert_eq!(ack.public_key, receiver_public_key);
+        verify_acknowledgement(&ack, &tx).unwrap();
+
+        // A receipt signed by anyone else does not prove the receiver saw the message
+        let (other_private_key, _) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let forged = other_private_key.sign(ack.data.clone());
+        assert!(matches!(
+            verify_acknowledgement(&forged, &tx),
+            Err(Error::InvalidAcknowledgement)
+        ));
+        assert!(sender.handle_acknowledgement(forged).is_err());
+        assert_eq!(sender.acknowledgement(&tx.hash), Some(&ack));
+    }
+
+    #[test]
+    fn test_relay_block() {
+        // A line topology where A and C are only connected through B
+        let mut networks = TestNetwork::from_links(3, &[(0, 1), (1, 2)]);
+
+        let keys: Vec<_> = (0..3)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519))
+            .collect();
+        let validator_public_key = keys[0].1.clone();
+        let mut nodes: Vec<_> = keys
+            .into_iter()
+            .zip(["A", "B", "C"])
+            .map(|((private_key, public_key), name)| {
+                Node::new(
+                    name.into(),
+                    public_key,
+                    private_key,
+                    validator_public_key.clone(),
+                    1_000_000,
+                    5,
+                )
+            })
+            .collect();
+
+        // A mints a block since the genesis block is long past the mint interval
+        nodes[0].step(&mut networks[0]);
+        assert_eq!(nodes[0].blockchain.len(), 2);
+        nodes[1].step(&mut networks[1]);
+        nodes[2].step(&mut networks[2]);
+        assert_eq!(nodes[2].blockchain.len(), 2);
+        assert_eq!(nodes[2].blockchain, nodes[0].blockchain);
+
+        // The block relayed back to A is dropped as a duplicate
+        nodes[0].step(&mut networks[0]);
+        assert_eq!(nodes[0].blockchain.len(), 2);
+    }
+
+    #[test]
+    fn test_lossy_network_sync() {
+        let (mut network1, mut network2) = TestNetworkBuilder::default()
+            .latency(Duration::ZERO, Duration::from_millis(2))
+            .drop_rate(0.3)
+            .seed(3)
+            .pair();
+
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut validator = Node::new(
+            "validator".into(),
+            validator_public_key.clone(),
+            validator_private_key,
+            validator_public_key.clone(),
+            1_000_000,
+            5,
+        );
+        let mut node = Node::new(
+            "node".into(),
+            node_public_key,
+            node_private_key,
+            validator_public_key,
+            1_000_000,
+            5,
+        );
+        validator.set_mint_interval(Duration::ZERO);
+
+        // The validator mints a block on every step while some of the blocks and sync requests
+        // are lost, so the node catches up with the blocks it missed once a later one arrives
+        for _ in 0..500 {
+            if node.blockchain.len() >= 30 {
+                break;
+            }
+            validator.step(&mut network1);
+            network2.await_events(Some(Duration::from_millis(5)));
+            node.step(&mut network2);
+            network1.await_events(Some(Duration::ZERO));
+        }
+        assert!(node.blockchain.len() >= 30);
+        assert_eq!(
+            node.blockchain[..],
+            validator.blockchain[..node.blockchain.len()]
+        );
+        assert!(network1.dropped() > 0);
+    }
+
+    #[test]
+    fn test_wrong_parent() {
+        let mut cluster = TestCluster::new(2, 10_000, 5);
+        for index in 0..2 {
+            let node = cluster.node_mut(index);
+            let tx = node.sign_transaction(node.wallet().create_stake_tx(100));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.broadcast_transaction(tx).unwrap();
+        }
+        cluster.run(3);
+        cluster.settle();
+        // Mint until the next validator is not the validator of the tip, so that a block of the
+        // next validator extending the block below the tip is not an equivocation
+        for _ in 0..100 {
+            let node = &cluster.nodes()[0];
+            let tip = node.blockchain.last().unwrap();
+            if node.next_validator().unwrap() != tip.data.validator {
+                break;
+            }
+            cluster.step();
+            cluster.settle();
+        }
+        let mut nodes = cluster.into_nodes();
+        let mut node = nodes.remove(0);
+        let height = node.blockchain.len();
+        let next_validator = node.next_validator().unwrap();
+        assert_ne!(next_validator, node.blockchain[height - 1].data.validator);
+        let validator = nodes
+            .iter_mut()
+            .chain([&mut node])
+            .find(|node| node.address == next_validator)
+            .unwrap();
+        let mut data = validator.mint_block().data;
+        let private_key = validator.private_key.clone();
+
+        // The block is signed by the expected validator but extends the block below the tip
+        data.parent_hash = node.blockchain[height - 2].hash.clone();
+        let block = private_key.sign(data.clone());
+        assert!(matches!(
+            node.handle_block(block.clone()),
+            Err(Error::InvalidParent(parent, tip))
+                if parent == node.blockchain[height - 2].hash
+                    && tip == node.blockchain[height - 1].hash
+        ));
+        assert_eq!(node.blockchain.len(), height);
+
+        // Only a block extending an unknown block means the node is lagging behind. The node
+        // stops minting so that it only reacts to the blocks.
+        node.set_observer(true);
+        let (mut network, mut peer) = TestNetwork::new();
+        peer.send(&Message::Block(block));
+        node.step(&mut network);
+        assert_eq!(peer.recv(), None);
+
+        data.parent_hash = Hash::digest("unknown");
+        let block = private_key.sign(data);
+        assert!(matches!(
+            node.handle_block(block.clone()),
+            Err(Error::UnknownParent(_))
+        ));
+        peer.send(&Message::Block(block));
+        node.step(&mut network);
+        assert_eq!(peer.recv(), Some(Message::GetBlocks { from: height }));
+    }
+
+    #[test]
+    fn test_block_time_histogram() {
+        let (private_key, public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let genesis = GenesisConfig::new(Address::from_public_key(&public_key), 1000, 5);
+        let mut node = Node::from_genesis("node".into(), public_key, private_key, genesis).unwrap();
+
+        let mut now = node.blockchain[0].data.timestamp;
+        for interval_ms in [50, 1000, 1500, 90_000] {
+            now += chrono::Duration::milliseconds(interval_ms);
+            node.set_simulated_time(now);
+            let block = node.mint_block();
+            node.handle_block(block).unwrap();
+        }
+
+        let block_times = node.metrics().block_times;
+        assert_eq!(block_times.counts, [1, 0, 0, 1, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(block_times.sum_ms, 50 + 1000 + 1500 + 90_000);
+        let cumulative: Vec<_> = block_times.cumulative_counts().collect();
+        assert_eq!(cumulative[0], (Some(100), 1));
+        assert_eq!(cumulative[4], (Some(2_000), 3));
+        assert_eq!(cumulative[9], (None, 4));
+    }
+
+    /// A log writer that keeps everything written to it in memory.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_validator_election_trace() {
+        let keys: Vec<_> = (0..2)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519))
+            .collect();
+        let genesis = GenesisConfig::new(Address::from_public_key(&keys[0].1), 1000, 5);
+        let nodes: Vec<_> = keys
+            .into_iter()
+            .map(|(private_key, public_key)| {
+                Node::from_genesis("node".into(), public_key, private_key, genesis.clone()).unwrap()
+            })
+            .collect();
+
+        let buffer = LogBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
+        let elected: Vec<_> = tracing::subscriber::with_default(subscriber, || {
+            nodes
+                .iter()
+                .map(|node| node.next_validator().unwrap())
+                .collect()
+        });
+
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = logs
+            .lines()
+            .filter(|line| line.contains("elected validator"))
+            .collect();
+        assert_eq!(lines.len(), 2, "{logs}");
+        // Both nodes drew the same ticket from the same seed
+        assert_eq!(lines[0], lines[1]);
+        let tip = &nodes[0].blockchain()[0];
+        assert!(lines[0].contains(&format!("seed={}", tip.hash)), "{logs}");
+        assert!(lines[0].contains("total_stake=1"), "{logs}");
+        assert!(
+            lines[0].contains(&format!("validator={}", elected[0])),
+            "{logs}"
+        );
+        assert_eq!(elected[0], genesis.validator);
+    }
+
+    #[test]
+    fn test_validator_election_fairness() {
+        const ELECTIONS: usize = 50_000;
+        let stakes = [1, 100, 100, 100, 200];
+        let wallets: BTreeMap<_, _> = stakes
+            .iter()
+            .map(|stake| {
+                let (_, public_key) =
+                    crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+                let mut wallet = Wallet::from_public_key(&public_key);
+                wallet.add_funds(1000);
+                wallet.set_stake(*stake);
+                (wallet.address.clone(), wallet)
+            })
+            .collect();
+        let total_stake: u64 = stakes.iter().sum();
+
+        let mut elections: BTreeMap<Address, usize> = BTreeMap::new();
+        for i in 0..ELECTIONS {
+            // Simulated tips with distinct hashes
+            let validators = elect_validators(&wallets, &Hash::digest(i), 1).unwrap();
+            *elections.entry(validators[0].clone()).or_default() += 1;
+        }
+
+        for (address, wallet) in wallets.iter() {
+            let expected = wallet.stake as f64 / total_stake as f64;
+            let actual = elections.get(address).copied().unwrap_or(0) as f64 / ELECTIONS as f64;
+            assert!(
+                (actual - expected).abs() < 0.01,
+                "stake {} was elected {actual} of the time instead of {expected}",
+                wallet.stake
+            );
+        }
+
+        // Wallets without stake are never elected
+        let mut wallets = wallets;
+        for wallet in wallets.values_mut().skip(1) {
+            wallet.set_stake(0);
+        }
+        let staker = wallets.values().next().unwrap().address.clone();
+        for i in 0..100 {
+            let validators = elect_validators(&wallets, &Hash::digest(i), 10).unwrap();
+            assert!(validators.iter().all(|validator| validator == &staker));
+        }
+    }
+
+    #[test]
+    fn test_epoch_election_is_even() {
+        const BLOCKS: usize = 400;
+        const EPOCH_LENGTH: usize = 8;
+        let wallets: BTreeMap<_, _> = (0..4)
+            .map(|_| {
+                let (_, public_key) =
+                    crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+                let mut wallet = Wallet::from_public_key(&public_key);
+                wallet.add_funds(1000);
+                wallet.set_stake(100);
+                (wallet.address.clone(), wallet)
+            })
+            .collect();
+
+        // The largest deviation from an equal share of the blocks of each window of an epoch
+        let max_deviation = |epoch_length: usize| {
+            let validators: Vec<_> = (0..BLOCKS / epoch_length)
+                .flat_map(|epoch| {
+                    elect_validators(&wallets, &Hash::digest(epoch), epoch_length).unwrap()
+                })
+                .collect();
+            assert_eq!(validators.len(), BLOCKS);
+            validators
+                .chunks(EPOCH_LENGTH)
+                .flat_map(|window| {
+                    wallets.keys().map(|address| {
+                        let minted = window.iter().filter(|v| *v == address).count();
+                        minted.abs_diff(EPOCH_LENGTH / wallets.len())
+                    })
+                })
+                .max()
+                .unwrap()
+        };
+        // Equal stakers mint exactly the same number of blocks in every epoch, while drawing a
+        // validator per block lets some of them mint several blocks more than their share
+        assert_eq!(max_deviation(EPOCH_LENGTH), 0);
+        assert!(max_deviation(1) >= 2);
+    }
+
+    #[test]
+    fn test_mint_interval() {
+        let mut nodes: Vec<_> = [Duration::from_millis(20), Duration::from_secs(10)]
+            .into_iter()
+            .map(|interval| {
+                let (private_key, public_key) =
+                    crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+                let mut node = Node::new(
+                    "test_node".into(),
+                    public_key.clone(),
+                    private_key,
+                    public_key,
+                    1_000_000,
+                    5,
+                );
+                node.set_mint_interval(interval);
+                (node, TestNetwork::new())
+            })
+            .collect();
+
+        let start = std::time::Instant::now();
+        while start.elapsed() < Duration::from_millis(300) {
+            for (node, (network, _)) in nodes.iter_mut() {
+                node.step(network);
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+
+        // Both mint right away since the genesis block is old but only the short interval mints
+        // again within the window
+        let short = nodes[0].0.blockchain.len();
+        let long = nodes[1].0.blockchain.len();
+        assert_eq!(long, 2);
+        assert!(short > 5, "minted only {short} blocks");
+    }
+
+    #[test]
+    fn test_validate_genesis() {
+        let (_, public_key) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let address = Address::from_public_key(&public_key);
+        let genesis = GenesisConfig::new(address.clone(), 1000, 5).block();
+        Node::validate_genesis(&genesis).unwrap();
+
+        // Changing the minted amount without updating the hashes
+        let mut tampered = genesis.clone();
+        tampered.data.transactions[0].data.kind =
+            TransactionKind::Genesis(1_000_000, address.clone());
+        assert!(matches!(
+            Node::validate_genesis(&tampered),
+            Err(Error::InvalidGenesis(_))
+        ));
+
+        // A consistently hashed block with a validator
+        let mut data = genesis.data.clone();
+        data.validator = Address::from_public_key(&public_key);
+        let tampered = Signed::new_invalid(data);
+        assert!(matches!(
+            Node::validate_genesis(&tampered),
+            Err(Error::InvalidGenesis(_))
+        ));
+
+        // A consistently hashed block with a parent
+        let mut data = genesis.data.clone();
+        data.parent_hash = genesis.hash.clone();
+        let tampered = Signed::new_invalid(data);
+        assert!(matches!(
+            Node::validate_genesis(&tampered),
+            Err(Error::InvalidGenesis(_))
+        ));
+
+        // A consistently hashed block minting with a coin transaction
+        let mut data = genesis.data.clone();
+        data.transactions[0] = Signed::new_invalid(Transaction {
+            sender_address: Address::invalid(),
+            kind: TransactionKind::Coin(1000, address),
+            nonce: 0,
+        });
+        let tampered = Signed::new_invalid(data);
+        assert!(matches!(
+            Node::validate_genesis(&tampered),
+            Err(Error::InvalidGenesis(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let (private_key, public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (_, receiver_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let receiver = Address::from_public_key(&receiver_public_key);
+        let mut node = Node::new(
+            "test_node".into(),
+            public_key.clone(),
+            private_key.clone(),
+            public_key,
+            1_000_000,
+            5,
+        );
+
+        for _ in 0..5 {
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver.clone(), 100));
+            node.wallet_mut().apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+            let block = node.mint_block();
+            node.handle_block(block).unwrap();
+        }
+        assert_eq!(node.blockchain.len(), 6);
+        node.verify_chain().unwrap();
+
+        // Changing a transaction of a middle block invalidates the block hash
+        let original = node.blockchain[3].clone();
+        node.blockchain[3].data.transactions[0].data.kind = TransactionKind::Coin(1, receiver);
+        assert!(matches!(
+            node.verify_chain(),
+            Err(Error::InvalidChain(3, err)) if matches!(*err, Error::InvalidSignature(_))
+        ));
+
+        // Re-signing the tampered block still leaves a transaction with an invalid signature
+        let tampered = private_key.sign(node.blockchain[3].data.clone());
+        node.blockchain[3] = tampered;
+        assert!(matches!(
+            node.verify_chain(),
+            Err(Error::InvalidChain(3, _))
+        ));
+
+        node.blockchain[3] = original;
+        node.verify_chain().unwrap();
+    }
+
+    #[test]
+    fn test_sync_blocks() {
+        let (mut network1, mut network2) = TestNetwork::new();
+
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut validator = Node::new(
+            "validator".into(),
+            validator_public_key.clone(),
+            validator_private_key,
+            validator_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        // The validator builds a chain while the node is not connected
+        let (mut disconnected, _peer) = TestNetwork::new();
+        validator.set_mint_interval(Duration::ZERO);
+        while validator.blockchain.len() < 11 {
+            validator.step(&mut disconnected);
+        }
+        validator.set_mint_interval(Duration::from_secs(10));
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key,
+            node_private_key,
+            validator_public_key,
+            1_000_000,
+            5,
+        );
+        node.request_sync();
+        node.step(&mut network1);
+        validator.step(&mut network2);
+        node.step(&mut network1);
+
+        assert_eq!(node.blockchain.len(), 11);
+        assert_eq!(node.blockchain, validator.blockchain);
+        assert_eq!(node.total_supply(), validator.total_supply());
+    }
+
+    #[test]
+    fn test_slash_invalid_block() {
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let validator_address = Address::from_public_key(&validator_public_key);
+        let mut genesis = GenesisConfig::new(validator_address.clone(), 1000, 5);
+        genesis.slashing_penalty = 10;
+        let new_node = || {
+            let (private_key, public_key) =
+                crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+            Node::from_genesis("test_node".into(), public_key, private_key, genesis.clone())
+                .unwrap()
+        };
+        let mut node = new_node();
+        let sign_block = |parent: &Signed<Block>, transactions, evidence| {
+            validator_private_key.sign(Block {
+                timestamp: Utc::now(),
+                transactions,
+                validator: validator_address.clone(),
+                parent_hash: parent.hash.clone(),
+                evidence,
+            })
+        };
+
+        // The elected validator includes a transfer of more than its balance
+        let wallet = node.wallets[&validator_address].clone();
+        let tx = wallet.create_coin_tx(node.address.clone(), 5000);
+        let invalid_block = sign_block(
+            &node.blockchain[0],
+            vec![validator_private_key.sign(tx)],
+            vec![],
+        );
+        assert!(matches!(
+            node.handle_block(invalid_block.clone()),
+            Err(Error::InsufficientFunds)
+        ));
+        assert_eq!(node.blockchain.len(), 1);
+        // The penalty is not applied until a block carries the evidence
+        assert_eq!(node.wallets[&validator_address].stake, 1);
+        assert!(node.includable_evidence().is_empty());
+
+        // The validator then mints a valid block at the same height, which stakes more funds
+        let stake_tx = validator_private_key.sign(wallet.create_stake_tx(100));
+        let block = sign_block(&node.blockchain[0], vec![stake_tx], vec![]);
+        node.handle_block(block).unwrap();
+        assert_eq!(node.includable_evidence(), vec![invalid_block.clone()]);
+
+        let supply = node.total_supply();
+        let block = sign_block(&node.blockchain[1], vec![], node.includable_evidence());
+        node.handle_block(block).unwrap();
+        let validator_wallet = &node.wallets[&validator_address];
+        assert_eq!(validator_wallet.stake, 90);
+        assert_eq!(node.total_supply(), supply - 10);
+        assert!(node.includable_evidence().is_empty());
+        node.verify_chain().unwrap();
+
+        // The same block is only punished once
+        let block = sign_block(&node.blockchain[2], vec![], vec![invalid_block]);
+        assert!(matches!(
+            node.handle_block(block),
+            Err(Error::InvalidEvidence(_))
+        ));
+
+        // A node that never received the invalid block applies the same penalty
+        let mut synced_node = new_node();
+        for block in node.blockchain[1..].iter() {
+            synced_node.handle_block(block.clone()).unwrap();
+        }
+        let synced_wallet = &synced_node.wallets[&validator_address];
+        assert_eq!(synced_wallet.stake, 90);
+        assert_eq!(synced_node.total_supply(), node.total_supply());
+    }
+
+    #[test]
+    fn test_validator_equivocation() {
+        let (validator_private_key, validator_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let validator_address = Address::from_public_key(&validator_public_key);
+        let mut validator = Node::new(
+            "validator".into(),
+            validator_public_key.clone(),
+            validator_private_key.clone(),
+            validator_public_key.clone(),
+            1_000_000,
+            5,
+        );
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key,
+            node_private_key,
+            validator_public_key,
+            1_000_000,
+            5,
+        );
+
+        // The validator signs two blocks with different contents at the same height
+        let block = validator.mint_block();
+        let mut data = block.data.clone();
+        data.timestamp += chrono::Duration::seconds(1);
+        let conflicting = validator_private_key.sign(data);
+
+        node.handle_block(block.clone()).unwrap();
+        assert!(matches!(
+            node.handle_block(conflicting.clone()),
+            Err(Error::ValidatorEquivocation(address, 1)) if address == validator_address
+        ));
+        assert_eq!(node.blockchain.len(), 2);
+        assert_eq!(
+            node.equivocations(),
+            [Equivocation {
+                height: 1,
+                accepted: block.clone(),
+                rejected: conflicting,
+            }]
+        );
+
+        // Receiving the accepted block again is not an equivocation
+        assert!(matches!(
+            node.handle_block(block),
+            Err(Error::InvalidParent(_, _))
+        ));
+        assert_eq!(node.equivocations().len(), 1);
+    }
+
+    #[test]
+    fn test_block_summary() {
+        let (private_key, public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut wallet = Wallet::from_public_key(&public_key);
+        wallet.add_funds(10_000);
+        let (_, receiver_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let receiver = Address::from_public_key(&receiver_public_key);
+
+        let coin_tx = private_key.sign(wallet.create_coin_tx(receiver.clone(), 1000));
+        wallet.apply_tx(coin_tx.clone()).unwrap();
+        let message_tx = private_key.sign(wallet.create_message_tx(receiver, "hello".into()));
+        let transactions = vec![coin_tx, message_tx];
+        let block = Block {
+            timestamp: Utc::now(),
+            transactions,
+            validator: wallet.address.clone(),
+            parent_hash: GENESIS_PARENT_HASH,
+            evidence: vec![],
+        };
+
+        // 3% of the coins plus one per message byte
+        let summary = block.to_string();
+        assert!(
+            summary.contains(&format!("by {}", wallet.address)),
+            "{summary}"
+        );
+        assert!(
+            summary.contains("2 transactions, 35 BCC in fees"),
+            "{summary}"
+        );
+        let summary = block.summary(FeeSchedule::new(10)).to_string();
+        assert!(
+            summary.contains("2 transactions, 105 BCC in fees"),
+            "{summary}"
+        );
+        assert!(!summary.contains("hello"));
+    }
+
+    #[test]
+    fn test_mint_block() {
+        let (mut node_wallet, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+        let (receiver_wallet, _, _) = crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        const TRANSACTION_COUNT: usize = 7;
+        let coin_amount = 1000;
+        let mut transactions = Vec::new();
+
+        // Apply more transactions than the block capacity
+        for _ in 0..TRANSACTION_COUNT {
+            let tx = node_wallet
+                .clone()
+                .create_coin_tx(receiver_wallet.address.clone(), coin_amount);
+            let signed_tx = node_private_key.sign(tx.clone());
+
+            node_wallet.apply_tx(signed_tx.clone()).unwrap();
+            node.handle_transaction(signed_tx.clone()).unwrap();
+
+            if transactions.len() < node.capacity {
+                transactions.push(signed_tx);
+            }
+        }
+
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions.len(), 5);
+        assert_eq!(block.data.transactions, transactions);
+        assert_eq!(block.data.validator, node_wallet.address);
+        assert_eq!(block.data.parent_hash, node.blockchain[0].hash);
+    }
+
+    #[test]
+    fn test_block_capacity() {
+        let (private_key, public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let node_address = Address::from_public_key(&public_key);
+        let receivers: Vec<_> = (0..4)
+            .map(|_| {
+                let (_, public_key) =
+                    crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+                Address::from_public_key(&public_key)
+            })
+            .collect();
+
+        // The genesis block mints five balances although the capacity is three
+        let mut genesis = GenesisConfig::new(node_address.clone(), 10_000, 3);
+        genesis
+            .balances
+            .extend(receivers.iter().map(|address| (address.clone(), 100)));
+        let mut node =
+            Node::from_genesis("test_node".into(), public_key, private_key.clone(), genesis)
+                .unwrap();
+        assert_eq!(node.blockchain[0].data.transactions.len(), 5);
+
+        let mut wallet = node.wallet().clone();
+        let transactions: Vec<_> = receivers
+            .iter()
+            .map(|receiver| {
+                let tx = private_key.sign(wallet.create_coin_tx(receiver.clone(), 10));
+                wallet.apply_tx(tx.clone()).unwrap();
+                tx
+            })
+            .collect();
+        let parent_hash = node.blockchain[0].hash.clone();
+        let block = |transactions: &[Signed<Transaction>]| {
+            private_key.sign(Block {
+                timestamp: Utc::now(),
+                transactions: transactions.to_vec(),
+                validator: node_address.clone(),
+                parent_hash: parent_hash.clone(),
+                evidence: vec![],
+            })
+        };
+
+        let oversized = block(&transactions);
+        assert!(matches!(
+            node.handle_block(oversized),
+            Err(Error::BlockOverCapacity(4, 3))
+        ));
+        let full = block(&transactions[..3]);
+        node.handle_block(full).unwrap();
+        assert_eq!(node.blockchain.len(), 2);
+        assert!(node.verify_chain().is_ok());
+    }
+
+    #[test]
+    fn test_mempool_ttl() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key,
+            node_public_key,
+            1_000_000,
+            5,
+        );
+        node.set_mempool_limits(MempoolLimits {
+            ttl: Duration::from_millis(100),
+            ..MempoolLimits::default()
+        });
+        let (mut network, _peer) = TestNetwork::new();
+
+        // Transactions after a gap in the nonces of their sender are never minted
+        let (sender, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        fund(&mut node, &sender, 1000);
+        let receiver = node.address.clone();
+        let stuck_tx = |nonce| {
+            sender.sign(Transaction {
+                sender_address: Address::from_public_key(&sender.public_key()),
+                kind: TransactionKind::Coin(10, receiver.clone()),
+                nonce,
+            })
+        };
+        let old_tx = stuck_tx(5);
+        node.handle_transaction(old_tx.clone()).unwrap();
+        node.step(&mut network);
+        assert_eq!(node.transaction_status(&old_tx.hash), TxStatus::Pending);
+
+        std::thread::sleep(Duration::from_millis(150));
+        let new_tx = stuck_tx(6);
+        node.handle_transaction(new_tx.clone()).unwrap();
+        node.step(&mut network);
+        assert_eq!(node.transaction_status(&old_tx.hash), TxStatus::Unknown);
+        assert_eq!(node.transaction_status(&new_tx.hash), TxStatus::Pending);
+        assert_eq!(node.pending_transaction_count(), 1);
+    }
+
+    #[test]
+    fn test_mempool_rate_limit() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key,
+            node_public_key,
+            1_000_000,
+            5,
+        );
+        node.set_mempool_limits(MempoolLimits {
+            max_per_interval: 2,
+            rate_interval: Duration::from_secs(1),
+            ..MempoolLimits::default()
+        });
+        let start = DateTime::UNIX_EPOCH;
+        node.set_simulated_time(start);
+
+        let (sender, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (other, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        fund(&mut node, &sender, 1000);
+        fund(&mut node, &other, 1000);
+        let receiver = node.address.clone();
+        let coin_tx = |sender: &PrivateKey, nonce| {
+            sender.sign(Transaction {
+                sender_address: Address::from_public_key(&sender.public_key()),
+                kind: TransactionKind::Coin(10, receiver.clone()),
+                nonce,
+            })
+        };
+
+        node.handle_transaction(coin_tx(&sender, 0)).unwrap();
+        node.handle_transaction(coin_tx(&sender, 1)).unwrap();
+        let err = node.handle_transaction(coin_tx(&sender, 2));
+        assert!(matches!(err, Err(Error::RateLimited(_))));
+        // Resending a pending transaction doesn't use the allowance
+        node.handle_transaction(coin_tx(&sender, 1)).unwrap();
+        // Other senders have their own allowance
+        node.handle_transaction(coin_tx(&other, 0)).unwrap();
+
+        // Half the interval refills one transaction of the allowance
+        node.set_simulated_time(start + Duration::from_millis(500));
+        node.handle_transaction(coin_tx(&sender, 2)).unwrap();
+        let err = node.handle_transaction(coin_tx(&sender, 3));
+        assert!(matches!(err, Err(Error::RateLimited(_))));
+    }
+
+    #[test]
+    fn test_mempool_limits() {
+        let (_, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+        node.set_mempool_limits(MempoolLimits {
+            max_transactions: 4,
+            max_per_sender: 2,
+            ..MempoolLimits::default()
+        });
+
+        let senders: Vec<_> = (0..3)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519).0)
+            .collect();
+        for sender in &senders {
+            fund(&mut node, sender, 10_000);
+        }
+        let receiver = node.address.clone();
+        let coin_tx = |sender: &PrivateKey, nonce, amount| {
+            sender.sign(Transaction {
+                sender_address: Address::from_public_key(&sender.public_key()),
+                kind: TransactionKind::Coin(amount, receiver.clone()),
+                nonce,
+            })
+        };
+
+        node.handle_transaction(coin_tx(&senders[0], 0, 100))
+            .unwrap();
+        node.handle_transaction(coin_tx(&senders[0], 1, 100))
+            .unwrap();
+        // A sender can't have more than two pending transactions
+        let err = node.handle_transaction(coin_tx(&senders[0], 2, 100));
+        assert!(matches!(err, Err(Error::MempoolFull)));
+
+        node.handle_transaction(coin_tx(&senders[1], 0, 100))
+            .unwrap();
+        node.handle_transaction(coin_tx(&senders[1], 1, 100))
+            .unwrap();
+        assert_eq!(node.pending_transaction_count(), 4);
+
+        // A transaction paying less than every pending one is rejected
+        let err = node.handle_transaction(coin_tx(&senders[2], 0, 10));
+        assert!(matches!(err, Err(Error::MempoolFull)));
+        assert_eq!(node.pending_transaction_count(), 4);
+
+        // A transaction paying more evicts the cheapest one with the highest nonce
+        let high_fee_tx = coin_tx(&senders[2], 0, 1000);
+        node.handle_transaction(high_fee_tx.clone()).unwrap();
+        assert_eq!(node.pending_transaction_count(), 4);
+        assert_eq!(
+            node.transaction_status(&high_fee_tx.hash),
+            TxStatus::Pending
+        );
+    }
+
+    #[test]
+    fn test_mempool_unaffordable_transactions() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key,
+            node_public_key,
+            1_000_000,
+            5,
+        );
+        node.set_mempool_limits(MempoolLimits {
+            max_transactions: 2,
+            ..MempoolLimits::default()
+        });
+
+        let (funded, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let (poor, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        fund(&mut node, &funded, 1000);
+        fund(&mut node, &poor, 100);
+        let receiver = node.address.clone();
+        let coin_tx = |sender: &PrivateKey, nonce, amount| {
+            sender.sign(Transaction {
+                sender_address: Address::from_public_key(&sender.public_key()),
+                kind: TransactionKind::Coin(amount, receiver.clone()),
+                nonce,
+            })
+        };
+
+        let funded_tx = coin_tx(&funded, 0, 500);
+        node.handle_transaction(funded_tx.clone()).unwrap();
+        let poor_tx = coin_tx(&poor, 0, 50);
+        node.handle_transaction(poor_tx.clone()).unwrap();
+
+        // Claiming higher fees than the sender can pay doesn't evict anything
+        let err = node.handle_transaction(coin_tx(&poor, 1, 1_000_000));
+        assert!(matches!(err, Err(Error::CostExceedsFunds(_, 100))));
+        assert_eq!(node.pending_transaction_count(), 2);
+
+        // A pending transaction whose sender no longer has the funds for it is dropped
+        let poor_address = Address::from_public_key(&poor.public_key());
+        node.wallets.get_mut(&poor_address).unwrap().balance = 10;
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions, [funded_tx]);
+        assert_eq!(node.transaction_status(&poor_tx.hash), TxStatus::Unknown);
+    }
+
+    #[test]
+    fn test_own_transactions_bypass_sender_limits() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key,
+            node_public_key,
+            1_000_000,
+            5,
+        );
+        node.set_mempool_limits(MempoolLimits {
+            max_transactions: 4,
+            max_per_sender: 1,
+            max_per_interval: 1,
+            ..MempoolLimits::default()
+        });
+        let (user, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let receiver = Address::from_public_key(&user.public_key());
+
+        for _ in 0..3 {
+            let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver.clone(), 10));
+            node.send_transaction(tx).unwrap();
+        }
+        assert_eq!(node.wallet().nonce, 3);
+
+        // If any transaction is rejected none of them is kept
+        let mut wallet = node.wallet().clone();
+        let txs: Vec<_> = (0..2)
+            .map(|_| {
+                let tx = node.sign_transaction(wallet.create_coin_tx(receiver.clone(), 10));
+                wallet.apply_tx(tx.clone()).unwrap();
+                tx
+            })
+            .collect();
+        let err = node.broadcast_transactions(&txs);
+        assert!(matches!(err, Err(Error::MempoolFull)));
+        assert_eq!(node.pending_transaction_count(), 3);
+
+        // The wallet is left untouched when the mempool is full
+        let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver.clone(), 10));
+        node.send_transaction(tx.clone()).unwrap();
+        let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver, 10));
+        let err = node.send_transaction(tx);
+        assert!(matches!(err, Err(Error::MempoolFull)));
+        assert_eq!(node.wallet().nonce, 4);
+    }
+
+    /// A log writer appending to a shared buffer.
+    #[derive(Clone, Default)]
+    struct TestWriter(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_log_span_fields() {
+        let (_, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        let writer = TestWriter::default();
+        let make_writer = writer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_ansi(false)
+            .with_writer(move || make_writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let block = node.mint_block();
+            node.handle_block(block).unwrap();
+        });
+
+        let logs = String::from_utf8(writer.0.lock().unwrap().clone()).unwrap();
+        let accepted = logs
+            .lines()
+            .find(|line| line.contains("accepted valid block"))
+            .unwrap();
+        assert!(accepted.contains("name=test_node"), "{accepted}");
+        assert!(accepted.contains("height=1"), "{accepted}");
+    }
+
+    #[test]
+    fn test_transaction_status() {
+        let (node_wallet, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+        let (receiver_wallet, _, _) = crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        let tx = node_wallet.create_coin_tx(receiver_wallet.address.clone(), 1000);
+        let signed_tx = node_private_key.sign(tx);
+        assert_eq!(node.transaction_status(&signed_tx.hash), TxStatus::Unknown);
+
+        node.handle_transaction(signed_tx.clone()).unwrap();
+        assert_eq!(node.transaction_status(&signed_tx.hash), TxStatus::Pending);
+
+        let block = node.mint_block();
+        node.handle_block(block).unwrap();
+        assert_eq!(
+            node.transaction_status(&signed_tx.hash),
+            TxStatus::Confirmed {
+                block_index: 1,
+                confirmations: 1
+            }
+        );
+
+        // Every block on top of the inclusion block adds a confirmation
+        let block = node.mint_block();
+        node.handle_block(block).unwrap();
+        assert_eq!(
+            node.transaction_status(&signed_tx.hash),
+            TxStatus::Confirmed {
+                block_index: 1,
+                confirmations: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_mint_block_prefers_fees() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let node_address = Address::from_public_key(&node_public_key);
+        let senders: Vec<_> = (0..3)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519).0)
+            .collect();
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 1_000_000, 5);
+        for sender in senders.iter() {
+            let address = Address::from_public_key(&sender.public_key());
+            genesis.balances.insert(address, 1_000_000);
+        }
+        let mut node = Node::from_genesis(
+            "test_node".into(),
+            node_public_key,
+            node_private_key,
+            genesis,
+        )
+        .unwrap();
+
+        // The first sender pays 30 per transaction and the others pay nothing
+        let mut high_fee = Vec::new();
+        for (i, sender) in senders.iter().enumerate() {
+            let amount = if i == 0 { 1000 } else { 10 };
+            for nonce in 0..3 {
+                let tx = sender.sign(Transaction {
+                    sender_address: Address::from_public_key(&sender.public_key()),
+                    kind: TransactionKind::Coin(amount, node_address.clone()),
+                    nonce,
+                });
+                if i == 0 {
+                    high_fee.push(tx.clone());
+                }
+                node.handle_transaction(tx).unwrap();
+            }
+        }
+
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions.len(), 5);
+        for tx in high_fee.iter() {
+            assert!(block.data.transactions.contains(tx));
+        }
+        node.handle_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_canonical_transaction_order() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let node_address = Address::from_public_key(&node_public_key);
+        let senders: Vec<_> = (0..3)
+            .map(|_| crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519).0)
+            .collect();
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 1_000_000, 10);
+        for sender in senders.iter() {
+            let address = Address::from_public_key(&sender.public_key());
+            genesis.balances.insert(address, 1_000_000);
+        }
+        let mut node = Node::from_genesis(
+            "test_node".into(),
+            node_public_key,
+            node_private_key.clone(),
+            genesis,
+        )
+        .unwrap();
+
+        // Higher fees are selected first but the block is still sorted canonically
+        for (i, sender) in senders.iter().enumerate() {
+            for nonce in 0..3 {
+                let tx = sender.sign(Transaction {
+                    sender_address: Address::from_public_key(&sender.public_key()),
+                    kind: TransactionKind::Coin(100 * (i as u64 + 1), node_address.clone()),
+                    nonce,
+                });
+                node.handle_transaction(tx).unwrap();
+            }
+        }
+        let block = node.mint_block();
+        let keys: Vec<_> = block
+            .data
+            .transactions
+            .iter()
+            .map(|tx| {
+                (
+                    tx.data.sender_address.clone(),
+                    tx.data.nonce,
+                    tx.hash.clone(),
+                )
+            })
+            .collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys.len(), 9);
+        assert_eq!(keys, sorted);
+
+        // A block in any other order is rejected
+        let mut shuffled = block.data.clone();
+        shuffled.transactions.reverse();
+        let result = node.handle_block(node_private_key.sign(shuffled));
+        assert!(matches!(result, Err(Error::NonCanonicalOrder)));
+        node.handle_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_mint_block_nonce_gap() {
+        let (node_wallet, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+        let (receiver, _, _) = crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        let mut tx0 = node_wallet.create_coin_tx(receiver.address.clone(), 100);
+        tx0.nonce = 0;
+        let mut tx2 = tx0.clone();
+        tx2.nonce = 2;
+        let tx0 = node_private_key.sign(tx0);
+        let tx2 = node_private_key.sign(tx2);
+        node.handle_transaction(tx0.clone()).unwrap();
+        node.handle_transaction(tx2.clone()).unwrap();
+
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions, vec![tx0]);
+        node.handle_block(block).unwrap();
+
+        // The transaction after the gap stays in the mempool
+        assert_eq!(node.transaction_status(&tx2.hash), TxStatus::Pending);
+        let block = node.mint_block();
+        assert!(block.data.transactions.is_empty());
+    }
+
+    #[test]
+    fn test_self_transfer() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let node_address = Address::from_public_key(&node_public_key);
+        let (sender_key, sender_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let sender = Address::from_public_key(&sender_public_key);
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 1000, 5);
+        genesis.balances.insert(sender.clone(), 1000);
+        let mut node = Node::from_genesis(
+            "test_node".into(),
+            node_public_key,
+            node_private_key,
+            genesis,
+        )
+        .unwrap();
+
+        let tx = Wallet::from_address(sender.clone()).create_coin_tx(sender.clone(), 100);
+        node.handle_transaction(sender_key.sign(tx)).unwrap();
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions.len(), 1);
+        node.handle_block(block).unwrap();
+
+        // The sender only loses the fees, which go to the validator
+        assert_eq!(node.wallet_for(&sender).unwrap().balance, 997);
+        assert_eq!(node.wallet_for(&node_address).unwrap().balance, 1003);
+        assert_eq!(node.total_supply(), 2000);
+    }
+
+    #[test]
+    fn test_forged_genesis_transaction() {
+        let (node_private_key, node_public_key) =
+            crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key,
+            1_000_000,
+            5,
+        );
+        let (attacker, _) = crypto::generate_keypair_with(crypto::SignatureScheme::Ed25519);
+        let attacker_address = Address::from_public_key(&attacker.public_key());
+        let supply = node.total_supply();
+
+        // Any key can sign a mint on behalf of the system address
+        let forged = attacker.sign(Transaction {
+            sender_address: Address::invalid(),
+            kind: TransactionKind::Genesis(1_000_000, attacker_address.clone()),
+            nonce: 0,
+        });
+        let err = node.handle_transaction(forged.clone());
+        assert!(matches!(err, Err(Error::SystemTransaction)));
+
+        // Or spend the funds of another wallet
+        let theft = attacker.sign(Transaction {
+            sender_address: node.address.clone(),
+            kind: TransactionKind::Coin(1000, attacker_address),
+            nonce: 0,
+        });
+        let err = node.handle_transaction(theft.clone());
+        assert!(matches!(err, Err(Error::ForeignSigner)));
+        assert_eq!(node.pending_transaction_count(), 0);
+
+        // Blocks carrying either transaction are rejected too
+        for tx in [forged, theft] {
+            let block = node_private_key.sign(Block {
+                timestamp: Utc::now(),
+                transactions: vec![tx],
+                validator: node.address.clone(),
+                parent_hash: node.blockchain[0].hash.clone(),
+                evidence: vec![],
+            });
+            assert!(node.handle_block(block).is_err());
+        }
+        assert_eq!(node.blockchain.len(), 1);
+        assert_eq!(node.total_supply(), supply);
+    }
+
+    #[test]
+    fn test_handle_block_duplicate_nonce() {
+        let (node_wallet, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+        let (receiver, _, _) = crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        let tx1 = node_wallet.create_coin_tx(receiver.address.clone(), 100);
+        let tx2 = node_wallet.create_coin_tx(receiver.address.clone(), 200);
+        // The transactions share their sender and nonce so their canonical order is by hash
+        let mut transactions = vec![node_private_key.sign(tx1), node_private_key.sign(tx2)];
+        transactions.sort_by(|a, b| a.hash.cmp(&b.hash));
+        let block = Block {
+            timestamp: Utc::now(),
+            transactions,
+            validator: node_wallet.address.clone(),
+            parent_hash: node.blockchain[0].hash.clone(),
+            evidence: vec![],
+        };
+
+        let result = node.handle_block(node_private_key.sign(block));
+        assert!(matches!(result, Err(Error::DuplicateNonce(_, 0))));
+        assert_eq!(node.blockchain.len(), 1);
+    }
+
+    #[test]
+    fn test_handle_block_verifies_once() {
+        let (node_wallet, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+        let (receiver, _, _) = crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+        // A node with an empty mempool that will receive the minted block
+        let (peer_private_key, peer_public_key) = crypto::generate_test_keypair();
+        let mut peer = Node::new(
+            "test_peer".into(),
+            peer_public_key,
+            peer_private_key,
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        let mut wallet = node_wallet.clone();
+        for _ in 0..5 {
+            let tx = node_private_key.sign(wallet.create_coin_tx(receiver.address.clone(), 10));
+            wallet.apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx.clone()).unwrap();
+            // Handling an already pending transaction doesn't verify it again
+            let digests = crypto::DIGEST_COUNT.get();
+            node.handle_transaction(tx).unwrap();
+            assert_eq!(crypto::DIGEST_COUNT.get(), digests);
+        }
+
+        // Minting only needs to hash the new block
+        let digests = crypto::DIGEST_COUNT.get();
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions.len(), 5);
+        assert_eq!(crypto::DIGEST_COUNT.get() - digests, 1);
+
+        // The validator already verified all the transactions of the block so it only hashes the
+        // block and derives the address of its signer
+        let digests = crypto::DIGEST_COUNT.get();
+        node.handle_block(block.clone()).unwrap();
+        assert_eq!(crypto::DIGEST_COUNT.get() - digests, 2);
+
+        // A peer that hasn't seen the transactions verifies each of them exactly once, along with
+        // the address of its signer
+        let digests = crypto::DIGEST_COUNT.get();
+        peer.handle_block(block).unwrap();
+        assert_eq!(crypto::DIGEST_COUNT.get() - digests, 2 + 5 * 2);
+        assert_eq!(peer.wallets[&receiver.address].balance, 50);
+        assert_eq!(node.wallets[&receiver.address].balance, 50);
+    }
+
+    #[test]
+    fn test_handle_block_invalid_tx_signature() {
+        let (node_wallet, node_public_key, node_private_key) =
+            crate::wallet::test::setup_default_test_wallet();
+        let (receiver, _, _) = crate::wallet::test::setup_default_test_wallet();
+
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key.clone(),
+            1_000_000,
+            5,
+        );
+
+        // Tamper with the transaction after signing it
+        let mut tx = node_private_key.sign(node_wallet.create_coin_tx(receiver.address, 10));
+        tx.data.kind = TransactionKind::Coin(1000, node_wallet.address.clone());
+
+        let block = Block {
+            timestamp: Utc::now(),
+            transactions: vec![tx],
+            validator: node_wallet.address.clone(),
+            parent_hash: node.blockchain[0].hash.clone(),
+            evidence: vec![],
+        };
+        let result = node.handle_block(node_private_key.sign(block));
+        assert!(matches!(result, Err(Error::InvalidSignature(_))));
+        assert_eq!(node.blockchain.len(), 1);
+    }
+
+    #[test]
+    fn test_step_without_stakers() {
+        let (mut network1, mut network2) = TestNetwork::new();
+
+        let (node_private_key, node_public_key) = crypto::generate_test_keypair();
+        let mut node = Node::new(
+            "test_node".into(),
+            node_public_key.clone(),
+            node_private_key.clone(),
+            node_public_key,
+            1_000_000,
+            5,
+        );
+        // Nobody has staked anything yet
+        for wallet in node.wallets.values_mut() {
+            wallet.stake = 0;
+        }
+        assert!(matches!(node.next_validator(), Err(Error::NoStakers)));
+
+        let tx = node.wallet().create_coin_tx(Address::invalid(), 42);
+        network2.send(&Message::Transaction(node_private_key.sign(tx)));
+        assert_eq!(node.step(&mut network1), None);
+        assert_eq!(node.pending_transactions.len(), 1);
+        assert_eq!(node.blockchain.len(), 1);
+    }
+
+    #[test]
+    fn test_fee_schedule_consistency() {
+        let (node_private_key, node_public_key) = crypto::generate_test_keypair();
+        let (user_private_key, user_public_key) = crypto::generate_test_keypair();
+        let (poor_private_key, poor_public_key) = crypto::generate_test_keypair();
+        let node_address = Address::from_public_key(&node_public_key);
+        let user_address = Address::from_public_key(&user_public_key);
+        let poor_address = Address::from_public_key(&poor_public_key);
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 1_000_000, 5);
+        genesis.balances.insert(user_address.clone(), 10_000);
+        genesis.balances.insert(poor_address.clone(), 1000);
+        genesis.fee_schedule = FeeSchedule::new(10);
+        let mut node = Node::from_genesis(
+            "test_node".into(),
+            node_public_key,
+            node_private_key,
+            genesis,
+        )
+        .unwrap();
+
+        let user_wallet = node.wallets[&user_address].clone();
+        let tx = user_wallet.create_coin_tx(node_address.clone(), 1000);
+        node.handle_transaction(user_private_key.sign(tx)).unwrap();
+        // Affordable with the default 3% fee but not with a 10% fee
+        let poor_wallet = node.wallets[&poor_address].clone();
+        let tx = poor_wallet.create_coin_tx(node_address.clone(), 950);
+        let err = node.handle_transaction(poor_private_key.sign(tx));
+        assert!(matches!(err, Err(Error::CostExceedsFunds(1045, 1000))));
+
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions.len(), 1);
+        node.handle_block(block).unwrap();
+
+        assert_eq!(node.wallets[&user_address].balance, 10_000 - 1000 - 100);
+        assert_eq!(node.wallets[&poor_address].balance, 1000);
+        assert_eq!(node.wallets[&node_address].balance, 1_000_000 + 1000 + 100);
+    }
+
+    #[test]
+    fn test_total_supply_conserved() {
+        let (node_private_key, node_public_key) = crypto::generate_test_keypair();
+        let (user_private_key, user_public_key) = crypto::generate_test_keypair();
+        let node_address = Address::from_public_key(&node_public_key);
+        let user_address = Address::from_public_key(&user_public_key);
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 1_000_000, 3);
+        genesis.balances.insert(user_address.clone(), 10_000);
+        let mut node = Node::from_genesis(
+            "test_node".into(),
+            node_public_key,
+            node_private_key.clone(),
+            genesis,
+        )
+        .unwrap();
+        assert_eq!(node.total_supply(), 1_010_000);
+        assert_eq!(node.total_staked(), 1);
+
+        let mut node_wallet = node.wallets[&node_address].clone();
+        let mut user_wallet = node.wallets[&user_address].clone();
+        for i in 0..5 {
+            let tx =
+                node_private_key.sign(node_wallet.create_coin_tx(user_address.clone(), i * 100));
+            node_wallet.apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+
+            let tx = user_private_key.sign(user_wallet.create_coin_tx(node_address.clone(), 50));
+            user_wallet.apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+        }
+        assert_eq!(node.pending_transaction_count(), 10);
+
+        while node.has_pending_transactions() {
+            let block = node.mint_block();
+            node.handle_block(block).unwrap();
+            assert_eq!(node.total_supply(), 1_010_000);
+        }
+        assert_eq!(node.blockchain.len(), 5);
+    }
+
+    #[test]
+    fn test_handle_block_conserves_supply() {
+        let (node_private_key, node_public_key) = crypto::generate_test_keypair();
+        let (user_private_key, user_public_key) = crypto::generate_test_keypair();
+        let node_address = Address::from_public_key(&node_public_key);
+        let user_address = Address::from_public_key(&user_public_key);
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 1_000_000, 5);
+        genesis.balances.insert(user_address.clone(), 10_000);
+        let mut node = Node::from_genesis(
+            "test_node".into(),
+            node_public_key,
+            node_private_key.clone(),
+            genesis,
+        )
+        .unwrap();
+
+        let mut user_wallet = node.wallets[&user_address].clone();
+        let transactions = [
+            user_wallet.create_coin_tx(node_address.clone(), 1000),
+            user_wallet.create_message_tx(node_address.clone(), "Hello World!".into()),
+            user_wallet.create_coin_tx(node_address.clone(), 333),
+            user_wallet.create_message_tx(node_address.clone(), "Bye!".into()),
+        ];
+        for (nonce, mut tx) in transactions.into_iter().enumerate() {
+            tx.nonce = nonce as u64;
+            let tx = user_private_key.sign(tx);
+            user_wallet.apply_tx(tx.clone()).unwrap();
+            node.handle_transaction(tx).unwrap();
+        }
+
+        let block = node.mint_block();
+        assert_eq!(block.data.transactions.len(), 4);
+        node.handle_block(block).unwrap();
+
+        assert_eq!(node.total_supply(), 1_010_000);
+        // 30 + 12 + 9 + 4 BCC of fees went to the validator
+        assert_eq!(node.wallets[&user_address].balance, 10_000 - 1333 - 55);
+        assert_eq!(node.wallets[&node_address].balance, 1_000_000 + 1333 + 55);
     }
 }
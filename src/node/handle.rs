@@ -0,0 +1,226 @@
+//! An actor interface to a node. The node is owned by a single thread and async tasks send it
+//! requests through a `NodeHandle` instead of locking it, so they never block the runtime while the
+//! node is stepping.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::crypto::{Address, Signed};
+use crate::error::Result;
+use crate::network::Network;
+use crate::node::{Message, Node};
+use crate::wallet::{Transaction, Wallet};
+
+/// The longest the thread running a node waits for network events before serving the pending
+/// requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(15);
+
+/// A request executed on the thread that owns the node.
+type Request = Box<dyn FnOnce(&mut Node) + Send>;
+
+/// A handle to a node owned by another thread. The thread exits once all the handles are dropped.
+#[derive(Clone)]
+pub struct NodeHandle {
+    requests: Sender<Request>,
+}
+
+impl NodeHandle {
+    /// Moves the node to a new thread that only serves the requests of the handles.
+    pub fn spawn(node: Node) -> Self {
+        let (requests, rx) = mpsc::channel::<Request>();
+        std::thread::spawn(move || {
+            let mut node = node;
+            for request in rx {
+                serve(&mut node, request);
+            }
+        });
+        Self { requests }
+    }
+
+    /// Moves the node to a new thread that runs it on the provided network, serving the requests of
+    /// the handles between steps.
+    pub fn spawn_with_network<N>(node: Node, network: N) -> Self
+    where
+        N: Network<Message> + Send + 'static,
+    {
+        let (handle, deferred) = Self::deferred();
+        deferred.start_with_network(node, network);
+        handle
+    }
+
+    /// Creates a handle for a node that doesn't exist yet, e.g. while bootstrap constructs it. The
+    /// requests sent before the node is started wait until it serves them.
+    pub fn deferred() -> (Self, DeferredNode) {
+        let (requests, rx) = mpsc::channel::<Request>();
+        (Self { requests }, DeferredNode { requests: rx })
+    }
+
+    /// Runs `f` on the thread that owns the node and returns its result.
+    pub async fn call<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Node) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let request: Request = Box::new(move |node| {
+            let _ = tx.send(f(node));
+        });
+        self.requests
+            .send(request)
+            .expect("the node thread has exited");
+        rx.await.expect("the node failed to serve the request")
+    }
+
+    /// The wallet of the provided address as of the last accepted block, or the wallet of the node
+    /// if no address is provided.
+    pub async fn get_balance(&self, address: Option<Address>) -> Option<Wallet> {
+        self.call(move |node| match address {
+            Some(address) => node.wallet_for(&address).cloned(),
+            None => Some(node.wallet().clone()),
+        })
+        .await
+    }
+
+    /// Like `get_balance` but with the pending transactions applied. See
+    /// `Node::projected_wallet_for`.
+    pub async fn get_pending_balance(&self, address: Option<Address>) -> Option<Wallet> {
+        self.call(move |node| match address {
+            Some(address) => node.projected_wallet_for(&address),
+            None => Some(node.projected_wallet()),
+        })
+        .await
+    }
+
+    /// Accepts a transaction signed outside of the node and broadcasts it. See
+    /// `Node::submit_transaction`.
+    pub async fn submit_transaction(&self, tx: Signed<Transaction>) -> Result<()> {
+        self.call(move |node| node.submit_transaction(tx)).await
+    }
+}
+
+/// The requests of a `NodeHandle` created before its node.
+pub struct DeferredNode {
+    requests: Receiver<Request>,
+}
+
+impl DeferredNode {
+    /// Moves the node to a new thread that runs it on the provided network, serving the requests
+    /// of the handle, including the ones sent before, between steps.
+    pub fn start_with_network<N>(self, node: Node, network: N)
+    where
+        N: Network<Message> + Send + 'static,
+    {
+        std::thread::spawn(move || run(node, network, self.requests));
+    }
+}
+
+/// Executes a request. A request that panics must not take down the node, so the panic only fails
+/// the request.
+///
+/// The node keeps serving after a panic even though the request may have left it partially
+/// updated. The methods the API calls compute their changes before applying them, e.g.
+/// `handle_block` applies a block to a copy of the wallets and only replaces them once the whole
+/// block is valid, so a panic usually leaves the node as it was. Stopping the node instead would
+/// turn a bug in a single request into an outage of the validator, which stalls the whole network
+/// until it restarts.
+fn serve(node: &mut Node, request: Request) {
+    if panic::catch_unwind(AssertUnwindSafe(|| request(node))).is_err() {
+        tracing::error!("a request to the node panicked");
+    }
+}
+
+fn run<N: Network<Message>>(mut node: Node, mut network: N, requests: Receiver<Request>) {
+    loop {
+        loop {
+            match requests.try_recv() {
+                Ok(request) => serve(&mut node, request),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        }
+        let timeout = node.step(&mut network);
+        let timeout = timeout.map_or(POLL_INTERVAL, |timeout| timeout.min(POLL_INTERVAL));
+        network.await_events(Some(timeout));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::{self, SignatureScheme};
+    use crate::genesis::GenesisConfig;
+    use crate::network::TestNetwork;
+
+    use super::*;
+
+    #[test]
+    fn concurrent_requests() {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let node_address = Address::from_public_key(&public_key);
+        let users: Vec<_> = (0..4)
+            .map(|_| crypto::generate_keypair_with(SignatureScheme::Ed25519))
+            .collect();
+
+        let mut genesis = GenesisConfig::new(node_address.clone(), 10_000, 5);
+        for (_, public_key) in users.iter() {
+            genesis
+                .balances
+                .insert(Address::from_public_key(public_key), 1000);
+        }
+        let node =
+            Node::from_genesis("test_node".into(), public_key, private_key, genesis).unwrap();
+        let (network, _peer) = TestNetwork::new();
+        let handle = NodeHandle::spawn_with_network(node, network);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            // Every user submits transactions while the others read their balance
+            let tasks: Vec<_> = users
+                .into_iter()
+                .map(|(private_key, public_key)| {
+                    let handle = handle.clone();
+                    let node_address = node_address.clone();
+                    tokio::spawn(async move {
+                        let address = Address::from_public_key(&public_key);
+                        for _ in 0..5 {
+                            let wallet = handle.get_balance(Some(address.clone())).await.unwrap();
+                            assert_eq!(wallet.address, address);
+                        }
+                        let mut wallet = handle.get_balance(Some(address)).await.unwrap();
+                        for _ in 0..3 {
+                            let tx = wallet.create_coin_tx(node_address.clone(), 10);
+                            let signed_tx = wallet.sign_tx(tx, &private_key).unwrap();
+                            handle.submit_transaction(signed_tx.clone()).await.unwrap();
+                            wallet.apply_tx(signed_tx).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for task in tasks {
+                task.await.unwrap();
+            }
+
+            // The node confirms all of them in the blocks it mints in the background
+            let mut confirmed = false;
+            for _ in 0..250 {
+                if handle
+                    .call(|node| node.metrics().transactions_processed)
+                    .await
+                    == 12
+                {
+                    confirmed = true;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(confirmed);
+            let wallet = handle.get_balance(None).await.unwrap();
+            let confirmed_wallet = handle.get_balance(Some(node_address.clone())).await;
+            assert_eq!(wallet.address, node_address);
+            assert_eq!(wallet.balance, confirmed_wallet.unwrap().balance);
+            assert!(wallet.balance >= 10_000 + 12 * 10);
+        });
+    }
+}
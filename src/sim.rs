@@ -0,0 +1,405 @@
+//! A reproducible simulation of a network of in-memory nodes for testing consensus.
+//!
+//! The nodes run in simulated time and exchange their messages through a network that delays and
+//! drops them. Everything that varies between runs, the keys of the nodes, the transactions they
+//! send and the faults of the network, is drawn from a single seeded RNG, so a failing seed
+//! reproduces the same run every time. Unlike `TestCluster` the nodes don't receive the messages
+//! in lockstep, which exercises the synchronization of nodes that fall behind.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use thiserror::Error;
+
+use crate::crypto::{self, Address, Hash};
+use crate::genesis::GenesisConfig;
+use crate::network::Network;
+use crate::node::{Message, Node};
+
+/// The simulated time between two steps of the nodes.
+const TICK: Duration = Duration::from_millis(100);
+
+/// The time after which a validator mints a block even if it isn't full.
+const MINT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The amount of BCC every node starts with.
+const FUNDS: u64 = 10_000;
+
+/// The most ticks the nodes get to confirm their stakes before the simulation starts.
+const SETUP_TICKS: usize = 100;
+
+/// The parameters of a simulation.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// The number of nodes.
+    pub peers: usize,
+    /// The maximum number of transactions per block.
+    pub capacity: usize,
+    /// The stake of each node. Nodes with a stake of zero never mint, except for the first node
+    /// which is the genesis validator and keeps its genesis stake.
+    pub stakes: Vec<u64>,
+    /// Seeds the keys of the nodes, the transactions they send and the faults of the network.
+    pub seed: u64,
+    /// The number of ticks simulated once the stakes are confirmed.
+    pub ticks: usize,
+    /// The longest a message may be delayed, in ticks. Messages are delivered on the tick after
+    /// they are sent at the earliest.
+    pub max_delay: usize,
+    /// The probability of a message getting lost on its way to each peer.
+    pub drop_rate: f64,
+    /// The probability of each node sending a transaction on each tick.
+    pub tx_rate: f64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        Self {
+            peers: 4,
+            capacity: 5,
+            stakes: vec![100; 4],
+            seed: 0,
+            ticks: 300,
+            max_delay: 3,
+            drop_rate: 0.05,
+            tx_rate: 0.3,
+        }
+    }
+}
+
+/// A violation of the properties the consensus must uphold.
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SimError {
+    #[error("the stakes were not confirmed within {0} ticks")]
+    SetupTimeout(usize),
+    #[error("node {0} disagrees with node {1} on the block at height {2}")]
+    Fork(usize, usize, usize),
+    #[error("the blockchain did not grow past height {0}")]
+    Stalled(usize),
+}
+
+/// The outcome of a successful simulation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimReport {
+    /// The number of blocks of each node, including the genesis block.
+    pub heights: Vec<usize>,
+    /// The hash of the last block of each node.
+    pub tips: Vec<Hash>,
+    /// The messages that reached a peer.
+    pub delivered: usize,
+    /// The messages that got lost.
+    pub dropped: usize,
+}
+
+/// The network of a simulated node. Messages are only moved between the nodes by the simulation.
+#[derive(Default)]
+struct SimNetwork {
+    inbox: VecDeque<Message>,
+    outbox: Vec<Message>,
+}
+
+impl Network<Message> for SimNetwork {
+    fn await_events(&mut self, _timeout: Option<Duration>) {}
+
+    fn recv(&mut self) -> Option<Message> {
+        self.inbox.pop_front()
+    }
+
+    fn send(&mut self, msg: &Message) {
+        self.outbox.push(msg.clone());
+    }
+}
+
+pub struct Simulation {
+    config: SimConfig,
+    rng: StdRng,
+    nodes: Vec<Node>,
+    networks: Vec<SimNetwork>,
+    /// The messages on their way, keyed by the tick they are delivered on and the order they were
+    /// sent in, along with the index of their recipient.
+    in_flight: BTreeMap<(usize, u64), (usize, Message)>,
+    sent: u64,
+    tick: usize,
+    now: DateTime<Utc>,
+    delivered: usize,
+    dropped: usize,
+}
+
+impl Simulation {
+    /// Constructs the nodes of a simulation. Panics unless there is one stake per node.
+    pub fn new(config: SimConfig) -> Self {
+        assert!(config.peers > 0, "a simulation needs at least one node");
+        assert_eq!(config.stakes.len(), config.peers, "one stake per node");
+        let mut rng = StdRng::seed_from_u64(config.seed);
+        let keys: Vec<_> = (0..config.peers)
+            .map(|_| crypto::keypair_from_seed(rng.gen()))
+            .collect();
+        let validator = Address::from_public_key(&keys[0].1);
+        let genesis = GenesisConfig {
+            balances: keys
+                .iter()
+                .map(|(_, public_key)| (Address::from_public_key(public_key), FUNDS))
+                .collect(),
+            ..GenesisConfig::new(validator, FUNDS, config.capacity)
+        };
+
+        let now = DateTime::UNIX_EPOCH;
+        let nodes = keys
+            .into_iter()
+            .enumerate()
+            .map(|(index, (private_key, public_key))| {
+                let name = format!("sim-{index}");
+                let mut node = Node::from_genesis(name, public_key, private_key, genesis.clone())
+                    .expect("invalid genesis configuration");
+                node.set_mint_interval(MINT_INTERVAL);
+                node.set_simulated_time(now);
+                node
+            })
+            .collect();
+        let networks = (0..config.peers).map(|_| SimNetwork::default()).collect();
+
+        Self {
+            config,
+            rng,
+            nodes,
+            networks,
+            in_flight: BTreeMap::new(),
+            sent: 0,
+            tick: 0,
+            now,
+            delivered: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Runs the whole simulation. The nodes first stake over a reliable network, then send
+    /// transactions over the faulty network for the configured number of ticks and finally settle
+    /// once the faults stop. Returns the first violation of safety or liveness.
+    pub fn run(&mut self) -> Result<SimReport, SimError> {
+        self.setup()?;
+
+        let start_height = self.max_height();
+        for _ in 0..self.config.ticks {
+            self.step(true)?;
+        }
+        if self.max_height() <= start_height {
+            return Err(SimError::Stalled(start_height));
+        }
+
+        self.settle()?;
+        Ok(self.report())
+    }
+
+    /// Makes the nodes stake and waits until all of them know about all the stakes.
+    fn setup(&mut self) -> Result<(), SimError> {
+        for (node, &stake) in self.nodes.iter_mut().zip(&self.config.stakes) {
+            if stake > 0 {
+                let tx = node.sign_transaction(node.wallet().create_stake_tx(stake));
+                node.wallet_mut().apply_tx(tx.clone()).unwrap();
+                node.broadcast_transaction(tx).unwrap();
+            }
+        }
+        for _ in 0..SETUP_TICKS {
+            if self.stakes_confirmed() {
+                return Ok(());
+            }
+            self.step(false)?;
+        }
+        Err(SimError::SetupTimeout(SETUP_TICKS))
+    }
+
+    fn stakes_confirmed(&self) -> bool {
+        self.nodes.iter().all(|node| {
+            self.nodes
+                .iter()
+                .zip(&self.config.stakes)
+                .filter(|(_, &stake)| stake > 0)
+                .all(|(staker, &stake)| {
+                    let wallet = node.wallet_for(staker.address());
+                    wallet.is_some_and(|wallet| wallet.stake == stake)
+                })
+        })
+    }
+
+    /// Keeps the nodes minting over a reliable network so that the nodes that fell behind catch
+    /// up, then stops minting and delivers the messages still on their way.
+    fn settle(&mut self) -> Result<(), SimError> {
+        let ticks_per_block = (MINT_INTERVAL.as_millis() / TICK.as_millis()) as usize;
+        for _ in 0..3 * (ticks_per_block + self.config.max_delay) {
+            self.step(false)?;
+        }
+        for node in self.nodes.iter_mut() {
+            node.set_observer(true);
+        }
+        while !self.in_flight.is_empty() {
+            self.step(false)?;
+        }
+        // The last messages may have triggered responses
+        self.step(false)
+    }
+
+    /// Advances the simulation by one tick. Every node receives the messages due, may send a
+    /// transaction when `faults` are enabled and then steps once, in index order.
+    fn step(&mut self, faults: bool) -> Result<(), SimError> {
+        self.tick += 1;
+        self.now += TICK;
+        while let Some(entry) = self.in_flight.first_entry() {
+            if entry.key().0 > self.tick {
+                break;
+            }
+            let (recipient, msg) = entry.remove();
+            self.networks[recipient].inbox.push_back(msg);
+            self.delivered += 1;
+        }
+
+        for index in 0..self.nodes.len() {
+            if faults && self.rng.gen_bool(self.config.tx_rate) {
+                self.send_transaction(index);
+            }
+            let node = &mut self.nodes[index];
+            node.set_simulated_time(self.now);
+            node.step(&mut self.networks[index]);
+            self.route(index, faults);
+        }
+        self.check_safety()
+    }
+
+    /// Makes the node send a few coins to a random peer, if it can afford them.
+    fn send_transaction(&mut self, index: usize) {
+        if self.nodes.len() < 2 {
+            return;
+        }
+        let mut receiver = self.rng.gen_range(0..self.nodes.len() - 1);
+        if receiver >= index {
+            receiver += 1;
+        }
+        let receiver = self.nodes[receiver].address().clone();
+        let amount = self.rng.gen_range(1..=10);
+        let node = &mut self.nodes[index];
+        let tx = node.sign_transaction(node.wallet().create_coin_tx(receiver, amount));
+        // The transaction is skipped if the node can't afford it or its mempool is full
+        let _ = node.send_transaction(tx);
+    }
+
+    /// Sends the messages of a node to every other node, possibly delaying or dropping them.
+    fn route(&mut self, sender: usize, faults: bool) {
+        for msg in std::mem::take(&mut self.networks[sender].outbox) {
+            for recipient in (0..self.nodes.len()).filter(|&i| i != sender) {
+                let mut delivery = self.tick + 1;
+                if faults {
+                    if self.rng.gen_bool(self.config.drop_rate) {
+                        self.dropped += 1;
+                        continue;
+                    }
+                    delivery += self.rng.gen_range(0..=self.config.max_delay);
+                }
+                self.in_flight
+                    .insert((delivery, self.sent), (recipient, msg.clone()));
+                self.sent += 1;
+            }
+        }
+    }
+
+    /// Checks that the blockchain of every node is a prefix of the longest one. Nodes never roll
+    /// back blocks, so two nodes that disagree on a block stay forked forever.
+    fn check_safety(&self) -> Result<(), SimError> {
+        let (longest, reference) = self
+            .nodes
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, node)| node.height())
+            .unwrap();
+        for (index, node) in self.nodes.iter().enumerate() {
+            let mut blocks = reference.blockchain().iter().zip(node.blockchain());
+            if let Some(height) = blocks.position(|(a, b)| a.hash != b.hash) {
+                return Err(SimError::Fork(index, longest, height));
+            }
+        }
+        Ok(())
+    }
+
+    fn max_height(&self) -> usize {
+        self.nodes.iter().map(Node::height).max().unwrap()
+    }
+
+    /// Reports whether all the nodes have the same blockchain.
+    pub fn is_converged(&self) -> bool {
+        let blockchain = self.nodes[0].blockchain();
+        self.nodes
+            .iter()
+            .all(|node| node.blockchain() == blockchain)
+    }
+
+    fn report(&self) -> SimReport {
+        SimReport {
+            heights: self.nodes.iter().map(Node::height).collect(),
+            tips: self
+                .nodes
+                .iter()
+                .map(|node| node.blockchain().last().unwrap().hash.clone())
+                .collect(),
+            delivered: self.delivered,
+            dropped: self.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fixed_seed_converges() {
+        let config = SimConfig {
+            seed: 42,
+            ..SimConfig::default()
+        };
+        let mut sim = Simulation::new(config.clone());
+        let report = sim.run().unwrap();
+
+        assert!(sim.is_converged());
+        assert!(report.dropped > 0);
+        assert!(report.heights[0] > 20, "{report:?}");
+        let node = &sim.nodes()[0];
+        assert!(node.verify_chain().is_ok());
+        // Every staker got to mint despite the faults
+        for staker in sim.nodes() {
+            assert!(!node.blocks_by_validator(staker.address()).is_empty());
+        }
+
+        // The same seed reproduces the same run
+        let mut again = Simulation::new(config);
+        assert_eq!(again.run().unwrap(), report);
+    }
+
+    #[test]
+    fn uneven_stakes() {
+        let config = SimConfig {
+            peers: 5,
+            capacity: 3,
+            stakes: vec![0, 10, 10, 500, 0],
+            seed: 7,
+            drop_rate: 0.2,
+            ..SimConfig::default()
+        };
+        let mut sim = Simulation::new(config);
+        let report = sim.run().unwrap();
+
+        assert!(sim.is_converged());
+        assert!(report.heights[0] > 10, "{report:?}");
+        let node = &sim.nodes()[0];
+        let minted = |index: usize| {
+            let address = sim.nodes()[index].address();
+            node.blocks_by_validator(address).len()
+        };
+        // The genesis validator only minted until the other stakes were confirmed
+        assert!(minted(3) > minted(1) + minted(2));
+        assert_eq!(minted(4), 0);
+    }
+}
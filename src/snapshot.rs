@@ -0,0 +1,162 @@
+//! Snapshots of the state of the wallets that let a node restart without replaying the whole
+//! blockchain.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{Address, Hash};
+use crate::wallet::Wallet;
+
+/// The state of the wallets as of some block of the blockchain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The number of blocks covered by the snapshot, including the genesis block.
+    pub height: usize,
+    /// The hash of the last block covered by the snapshot.
+    pub tip: Hash,
+    /// The state of each known wallet after applying the covered blocks.
+    pub wallets: BTreeMap<Address, Wallet>,
+    /// The validators elected for the epoch of the block after the snapshot. Empty when that block
+    /// starts a new epoch.
+    #[serde(default)]
+    pub epoch_validators: Vec<Address>,
+}
+
+/// The encoding of a snapshot on disk. The hash detects snapshots that were corrupted or edited.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    hash: Hash,
+    snapshot: Snapshot,
+}
+
+impl Snapshot {
+    /// Writes the JSON encoded snapshot to the provided path, replacing any existing file.
+    pub fn write_to_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let file = SnapshotFile {
+            hash: Hash::digest(self),
+            snapshot: self.clone(),
+        };
+        std::fs::write(path, serde_json::to_string(&file)?)
+    }
+
+    /// Loads a JSON encoded snapshot from the provided path. Returns an error if the snapshot does
+    /// not match its hash.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        let file: SnapshotFile = serde_json::from_str(&data)?;
+        if Hash::digest(&file.snapshot) != file.hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "snapshot does not match its hash",
+            ));
+        }
+        Ok(file.snapshot)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::crypto::{self, SignatureScheme};
+    use crate::genesis::GenesisConfig;
+    use crate::node::Node;
+
+    use super::*;
+
+    #[test]
+    fn restart_from_snapshot() {
+        let (private_key, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let node_address = Address::from_public_key(&public_key);
+        let (_, user_public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let user_address = Address::from_public_key(&user_public_key);
+
+        // The snapshot is taken in the middle of an epoch
+        let genesis = GenesisConfig {
+            epoch_length: 3,
+            ..GenesisConfig::new(node_address.clone(), 10_000, 5)
+        };
+        let new_node = || {
+            let (private_key, public_key) = (private_key.clone(), public_key.clone());
+            Node::from_genesis("test_node".into(), public_key, private_key, genesis.clone())
+                .unwrap()
+        };
+        let mut node = new_node();
+        let mint_blocks = |node: &mut Node, count| {
+            for _ in 0..count {
+                let tx =
+                    node.sign_transaction(node.wallet().create_coin_tx(user_address.clone(), 10));
+                node.wallet_mut().apply_tx(tx.clone()).unwrap();
+                node.handle_transaction(tx).unwrap();
+                let block = node.mint_block();
+                node.handle_block(block).unwrap();
+            }
+        };
+
+        mint_blocks(&mut node, 4);
+        assert_eq!(node.blockchain().len(), 5);
+        let path = std::env::temp_dir().join(format!("snapshot-{}.json", std::process::id()));
+        node.snapshot().write_to_file(&path).unwrap();
+        mint_blocks(&mut node, 3);
+
+        let snapshot = Snapshot::from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(snapshot.height, 5);
+        assert_eq!(snapshot.epoch_validators.len(), 3);
+        let restored = Node::from_snapshot(
+            "test_node".into(),
+            public_key.clone(),
+            private_key.clone(),
+            genesis.clone(),
+            snapshot,
+            node.blockchain().to_vec(),
+        )
+        .unwrap();
+        // Only the blocks after the snapshot were replayed
+        assert_eq!(restored.metrics().blocks_accepted, 3);
+
+        let mut replayed = new_node();
+        replayed
+            .handle_blocks(node.blockchain()[1..].to_vec())
+            .unwrap();
+        assert_eq!(replayed.metrics().blocks_accepted, 7);
+
+        for other in [&restored, &replayed] {
+            assert_eq!(other.blockchain(), node.blockchain());
+            assert!(other.verify_chain().is_ok());
+            for address in [&node_address, &user_address] {
+                let expected = node.wallet_for(address).unwrap();
+                let wallet = other.wallet_for(address).unwrap();
+                assert_eq!(wallet.balance, expected.balance);
+                assert_eq!(wallet.stake, expected.stake);
+                assert_eq!(wallet.nonce, expected.nonce);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupted_snapshot() {
+        let (_, public_key) = crypto::generate_keypair_with(SignatureScheme::Ed25519);
+        let address = Address::from_public_key(&public_key);
+        let mut wallet = Wallet::from_address(address.clone());
+        wallet.add_funds(100);
+        let snapshot = Snapshot {
+            height: 1,
+            tip: Hash::default(),
+            wallets: BTreeMap::from([(address, wallet)]),
+            epoch_validators: vec![],
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("snapshot-corrupt-{}.json", std::process::id()));
+        snapshot.write_to_file(&path).unwrap();
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert!(data.contains("\"balance\":100"), "{data}");
+        std::fs::write(&path, data.replace("\"balance\":100", "\"balance\":900")).unwrap();
+
+        let err = Snapshot::from_file(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
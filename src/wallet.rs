@@ -1,9 +1,40 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
-use crate::crypto::{Address, PublicKey, Signed};
+use crate::crypto::{Address, EncryptedMessage, PrivateKey, PublicKey, Signed};
 use crate::error::{Error, Result};
 
-const FEE_PERCENT: u64 = 3;
+/// The fee model of a network.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    /// The percentage of the transferred amount charged as fees for coin transactions.
+    pub percent: u64,
+}
+
+impl Default for FeeSchedule {
+    fn default() -> Self {
+        Self { percent: 3 }
+    }
+}
+
+impl FeeSchedule {
+    pub fn new(percent: u64) -> Self {
+        Self { percent }
+    }
+
+    /// The fees of a coin transaction transferring `amount`. The percentage is rounded down so
+    /// small transfers are free. Saturates instead of overflowing for huge amounts.
+    pub fn coin_fee(&self, amount: u64) -> u64 {
+        let fee = u128::from(amount) * u128::from(self.percent) / 100;
+        u64::try_from(fee).unwrap_or(u64::MAX)
+    }
+
+    /// The fees of a message of `length` bytes.
+    pub fn message_fee(&self, length: usize) -> u64 {
+        u64::try_from(length).unwrap_or(u64::MAX)
+    }
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Wallet {
@@ -15,15 +46,24 @@ pub struct Wallet {
     pub stake: u64,
     /// An auto-increment nonce used to sign transactions.
     pub nonce: u64,
+    /// The fee model used to charge the transactions of this wallet. Serialized along with the
+    /// wallet so that clients compute costs with the fees of the network.
+    #[serde(default)]
+    pub fee_schedule: FeeSchedule,
 }
 
 impl Wallet {
     pub fn from_address(address: Address) -> Self {
+        Self::with_fee_schedule(address, FeeSchedule::default())
+    }
+
+    pub fn with_fee_schedule(address: Address, fee_schedule: FeeSchedule) -> Self {
         Self {
             address,
             balance: 0,
             stake: 0,
             nonce: 0,
+            fee_schedule,
         }
     }
 
@@ -52,57 +92,141 @@ impl Wallet {
     /// Validates the provided transaction given the current wallet's state.
     pub fn validate_tx(&mut self, tx: Signed<Transaction>) -> Result<Signed<Transaction>> {
         tx.verify()?;
+        self.check_tx(&tx.data)?;
+        Ok(tx)
+    }
+
+    /// Checks that the provided transaction can be applied given the current wallet's state. The
+    /// signature of the transaction is not verified.
+    fn check_tx(&self, tx: &Transaction) -> Result<()> {
+        // Only the system mints funds out of thin air
+        if matches!(tx.kind, TransactionKind::Genesis(_, _))
+            && tx.sender_address != Address::invalid()
+        {
+            return Err(Error::SystemTransaction);
+        }
+        // Nobody holds the key of the invalid address so anything sent to it is lost for good
+        if tx.receiver() == Some(Address::invalid()) {
+            return Err(Error::InvalidRecipient);
+        }
         // If this is our transaction we must also verify that we have sufficient funds.
-        if tx.data.sender_address == self.address {
-            if tx.data.nonce < self.nonce {
-                return Err(Error::NonceReused(tx.data.nonce, self.nonce));
+        if tx.sender_address == self.address {
+            if tx.nonce < self.nonce {
+                return Err(Error::NonceReused(tx.nonce, self.nonce));
             }
-            let fees = tx.data.fees();
-            match &tx.data.kind {
-                TransactionKind::Coin(amount, _) => {
-                    if amount + fees > self.available_funds() {
+            let fees = tx.fees(&self.fee_schedule);
+            match &tx.kind {
+                // The cost saturates so huge amounts can't overflow
+                TransactionKind::Coin(_, _) => {
+                    if tx.cost(&self.fee_schedule) > self.available_funds() {
                         return Err(Error::InsufficientFunds);
                     }
                 }
-                TransactionKind::Message(_, _) => {
+                TransactionKind::Message(_, _) | TransactionKind::EncryptedMessage(_, _) => {
                     if fees > self.available_funds() {
                         return Err(Error::InsufficientFunds);
                     }
                 }
+                TransactionKind::Genesis(_, _) => {}
                 TransactionKind::Stake(amount) => {
-                    // Can only stake up to the current balance
+                    // A stake transaction sets the absolute stake rather than adding to it and
+                    // pays no fees, so the whole balance can be staked. The outflows of the
+                    // sender's earlier transactions are already subtracted from the balance since
+                    // transactions are applied in nonce order.
                     if *amount > self.balance {
                         return Err(Error::InsufficientFunds);
                     }
                 }
             }
         }
-        Ok(tx)
+        Ok(())
+    }
+
+    /// Checks that the sender can afford the cost of the provided transaction, including the fees
+    /// implied by its length in the case of messages. Used to reject a transaction before it is
+    /// broadcast instead of when a validator applies it.
+    pub fn check_cost(&self, tx: &Transaction) -> Result<()> {
+        let cost = tx.cost(&self.fee_schedule);
+        if cost > self.available_funds() {
+            return Err(Error::CostExceedsFunds(cost, self.available_funds()));
+        }
+        Ok(())
     }
 
     /// Applies the provided transaction, provided it's valid
     /// transaction is valid. Returns an error if the transaction is invalid.
     pub fn apply_tx(&mut self, tx: Signed<Transaction>) -> Result<()> {
-        let tx = self.validate_tx(tx)?.data;
+        tx.verify()?;
+        self.apply_verified_tx(&tx.data)
+    }
+
+    /// Applies the provided transaction whose signature has already been verified by the caller.
+    /// Returns an error if the transaction is invalid given the current wallet's state.
+    pub fn apply_verified_tx(&mut self, tx: &Transaction) -> Result<()> {
+        self.check_tx(tx)?;
         // If this is our transaction we must subtract the money moved and fees from our balance.
         if tx.sender_address == self.address {
             self.nonce = tx.nonce + 1;
-            self.balance -= tx.fees();
+            self.balance -= tx.fees(&self.fee_schedule);
             match tx.kind {
                 TransactionKind::Coin(amount, _) => self.balance -= amount,
-                TransactionKind::Message(_, _) => {}
+                TransactionKind::Message(_, _)
+                | TransactionKind::EncryptedMessage(_, _)
+                | TransactionKind::Genesis(_, _) => {}
                 TransactionKind::Stake(amount) => self.stake = amount,
             }
         }
         // Finally, if this transaction moves money into this wallet we must add it to our balance.
-        if let TransactionKind::Coin(amount, receiver) = tx.kind {
-            if receiver == self.address {
+        // For a self-transfer this returns the amount subtracted above so only the fees are lost.
+        if let TransactionKind::Coin(amount, receiver)
+        | TransactionKind::Genesis(amount, receiver) = &tx.kind
+        {
+            if receiver == &self.address {
                 self.balance += amount;
             }
         }
         Ok(())
     }
 
+    /// Signs a transaction of this wallet locally, so that the private key never has to be handed
+    /// to a node. Returns an error if the transaction or the key do not belong to this wallet.
+    pub fn sign_tx(
+        &self,
+        tx: Transaction,
+        private_key: &PrivateKey,
+    ) -> Result<Signed<Transaction>> {
+        if tx.sender_address != self.address {
+            return Err(Error::ForeignSigner);
+        }
+        let signed_tx = private_key.sign(tx);
+        if Address::from_public_key(&signed_tx.public_key) != self.address {
+            return Err(Error::ForeignSigner);
+        }
+        Ok(signed_tx)
+    }
+
+    /// The largest amount a coin transaction can transfer given the available funds, after
+    /// accounting for the fees it is charged.
+    pub fn max_coin_amount(&self) -> u64 {
+        let funds = self.available_funds();
+        // Everything is computed in u128 since the funds can be close to `u64::MAX`, where
+        // multiplying them overflows and the cost of a transaction saturates
+        let cost = |amount| u128::from(amount) + u128::from(self.fee_schedule.coin_fee(amount));
+        let funds_wide = u128::from(funds);
+        // The fees are rounded down so the exact answer can be slightly above this estimate
+        let estimate = funds_wide * 100 / (100 + u128::from(self.fee_schedule.percent));
+        let mut amount = u64::try_from(estimate).expect("the estimate is at most the funds");
+        while amount < funds && cost(amount + 1) <= funds_wide {
+            amount += 1;
+        }
+        amount
+    }
+
+    /// Creates a coin transaction that sends all the available funds, minus the fees.
+    pub fn create_max_coin_tx(&self, receiver: Address) -> Transaction {
+        self.create_coin_tx(receiver, self.max_coin_amount())
+    }
+
     pub fn create_coin_tx(&self, receiver: Address, amount: u64) -> Transaction {
         self.create_tx(TransactionKind::Coin(amount, receiver))
     }
@@ -111,6 +235,18 @@ impl Wallet {
         self.create_tx(TransactionKind::Message(message, receiver))
     }
 
+    /// Creates a message transaction whose message can only be read by the holder of the private
+    /// key corresponding to `receiver`.
+    pub fn create_encrypted_message_tx(
+        &self,
+        receiver: &PublicKey,
+        message: &str,
+    ) -> Result<Transaction> {
+        let encrypted = receiver.encrypt(message)?;
+        let receiver = Address::from_public_key(receiver);
+        Ok(self.create_tx(TransactionKind::EncryptedMessage(encrypted, receiver)))
+    }
+
     pub fn create_stake_tx(&self, amount: u64) -> Transaction {
         self.create_tx(TransactionKind::Stake(amount))
     }
@@ -123,6 +259,24 @@ impl Wallet {
         assert!(amount <= self.balance);
         self.stake = amount;
     }
+
+    /// Burns up to `amount` of the staked funds. Returns the amount actually burned.
+    pub fn slash(&mut self, amount: u64) -> u64 {
+        let slashed = amount.min(self.stake);
+        self.stake -= slashed;
+        self.balance -= slashed;
+        slashed
+    }
+}
+
+impl fmt::Display for Wallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "address:   {}", self.address)?;
+        writeln!(f, "balance:   {} BCC", self.balance)?;
+        writeln!(f, "available: {} BCC", self.available_funds())?;
+        writeln!(f, "staked:    {} BCC", self.staked_amount())?;
+        write!(f, "nonce:     {}", self.nonce)
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
@@ -141,36 +295,46 @@ pub enum TransactionKind {
     Coin(u64, Address),
     /// A message transaction transferring the specified message to the receiver.
     Message(String, Address),
+    /// A message transaction whose message is encrypted so that only the receiver can read it.
+    EncryptedMessage(EncryptedMessage, Address),
     // A staking transaction locking up the specified amount.
     Stake(u64),
+    /// A transaction of the genesis block minting the specified amount to the receiver. It is
+    /// only valid when sent by the system address and charges no fees.
+    Genesis(u64, Address),
 }
 
 impl Transaction {
-    /// Calculates the cost of this transaction.
-    pub fn cost(&self) -> u64 {
+    /// Calculates the cost of this transaction under the provided fee schedule.
+    pub fn cost(&self, fee_schedule: &FeeSchedule) -> u64 {
         let value = match &self.kind {
             TransactionKind::Coin(amount, _) => *amount,
             TransactionKind::Message(_, _) => 0,
+            TransactionKind::EncryptedMessage(_, _) => 0,
             TransactionKind::Stake(_) => 0,
+            TransactionKind::Genesis(_, _) => 0,
         };
-        self.fees() + value
+        self.fees(fee_schedule).saturating_add(value)
     }
 
-    /// Calculates the required fees of this transaction.
-    pub fn fees(&self) -> u64 {
+    /// Calculates the required fees of this transaction under the provided fee schedule.
+    pub fn fees(&self, fee_schedule: &FeeSchedule) -> u64 {
         match &self.kind {
             // TODO: should we charge a minimum amount when the calculation rounds down to zero?
-            TransactionKind::Coin(amount, _) => (amount * FEE_PERCENT) / 100,
-            TransactionKind::Message(msg, _) => msg.len() as u64,
-            TransactionKind::Stake(_) => 0,
+            TransactionKind::Coin(amount, _) => fee_schedule.coin_fee(*amount),
+            TransactionKind::Message(msg, _) => fee_schedule.message_fee(msg.len()),
+            // The plaintext length is unknown so the ciphertext is charged instead
+            TransactionKind::EncryptedMessage(msg, _) => fee_schedule.message_fee(msg.size()),
+            TransactionKind::Stake(_) | TransactionKind::Genesis(_, _) => 0,
         }
     }
 
     pub fn receiver(&self) -> Option<Address> {
         match &self.kind {
-            TransactionKind::Coin(_, receiver) | TransactionKind::Message(_, receiver) => {
-                Some(receiver.clone())
-            }
+            TransactionKind::Coin(_, receiver)
+            | TransactionKind::Message(_, receiver)
+            | TransactionKind::EncryptedMessage(_, receiver)
+            | TransactionKind::Genesis(_, receiver) => Some(receiver.clone()),
             TransactionKind::Stake(_) => None,
         }
     }
@@ -184,10 +348,10 @@ pub mod test {
 
     /// Creates a test wallet with 1M BCC as initial funds
     pub fn setup_test_wallet(initial_balance: u64) -> (Wallet, PublicKey, PrivateKey) {
-        let (wallet_key, wallet_public_key) = crate::crypto::generate_keypair();
+        let (wallet_key, wallet_public_key) = crate::crypto::generate_test_keypair();
         let mut wallet = Wallet::from_public_key(&wallet_public_key);
         // Create Alice's keypair and give some initial funds to the test wallet
-        let (funder_key, funder_public_key) = crate::crypto::generate_keypair();
+        let (funder_key, funder_public_key) = crate::crypto::generate_test_keypair();
         let initial_funds = Transaction {
             sender_address: Address::from_public_key(&funder_public_key),
             kind: TransactionKind::Coin(initial_balance, wallet.address.clone()),
@@ -201,6 +365,20 @@ pub mod test {
         setup_test_wallet(1_000_000)
     }
 
+    #[test]
+    fn test_fee_schedule_serialization() {
+        let (wallet, _, _) = setup_default_test_wallet();
+        let wallet = Wallet {
+            fee_schedule: FeeSchedule::new(10),
+            ..wallet
+        };
+
+        let decoded: Wallet =
+            serde_json::from_str(&serde_json::to_string(&wallet).unwrap()).unwrap();
+        assert_eq!(decoded.fee_schedule, FeeSchedule::new(10));
+        assert_eq!(decoded.max_coin_amount(), wallet.max_coin_amount());
+    }
+
     #[test]
     fn test_coin_transaction() {
         let (mut sender_wallet, _, sender_key) = setup_default_test_wallet();
@@ -219,7 +397,7 @@ pub mod test {
                 nonce: 0,
             }
         );
-        assert_eq!(tx.fees(), 3);
+        assert_eq!(tx.fees(&FeeSchedule::default()), 3);
 
         // Apply the transaction to the sender wallet and verify funds adjust correctly.
         sender_wallet.apply_tx(signed_tx.clone()).unwrap();
@@ -251,7 +429,7 @@ pub mod test {
                 nonce: 0,
             }
         );
-        assert_eq!(tx.fees(), expected_fees);
+        assert_eq!(tx.fees(&FeeSchedule::default()), expected_fees);
 
         // Apply the transaction to the sender wallet and verify funds adjust correctly.
         sender_wallet.apply_tx(signed_tx.clone()).unwrap();
@@ -264,6 +442,53 @@ pub mod test {
         assert_eq!(receiver_wallet.nonce, 0);
     }
 
+    #[test]
+    fn test_encrypted_message_transaction() {
+        let (mut sender_wallet, _, sender_key) = setup_default_test_wallet();
+        let (mut receiver_wallet, receiver_public_key, receiver_key) = setup_default_test_wallet();
+        let (_, _, third_party_key) = setup_default_test_wallet();
+
+        let message = "Hello World!";
+        let tx = sender_wallet
+            .create_encrypted_message_tx(&receiver_public_key, message)
+            .unwrap();
+        let TransactionKind::EncryptedMessage(encrypted, receiver) = &tx.kind else {
+            panic!("expected an encrypted message");
+        };
+        assert_eq!(receiver, &receiver_wallet.address);
+        assert!(!encrypted
+            .ciphertext
+            .windows(message.len())
+            .any(|w| w == message.as_bytes()));
+
+        // The fees are charged on the ciphertext
+        let expected_fees = encrypted.size() as u64;
+        assert!(expected_fees > message.len() as u64);
+        assert_eq!(tx.fees(&FeeSchedule::default()), expected_fees);
+
+        // Only the receiver can read the message
+        assert_eq!(receiver_key.decrypt(encrypted).unwrap(), message);
+        assert!(matches!(
+            third_party_key.decrypt(encrypted),
+            Err(Error::Encryption(_))
+        ));
+
+        let signed_tx = sender_key.sign(tx.clone());
+        sender_wallet.apply_tx(signed_tx.clone()).unwrap();
+        assert_eq!(sender_wallet.available_funds(), 1_000_000 - expected_fees);
+        assert_eq!(sender_wallet.nonce, 1);
+        receiver_wallet.apply_tx(signed_tx).unwrap();
+        assert_eq!(receiver_wallet.available_funds(), 1_000_000);
+
+        // Ed25519 keys can't be used for encryption
+        let (_, ed25519_public_key) =
+            crate::crypto::generate_keypair_with(crate::crypto::SignatureScheme::Ed25519);
+        assert!(matches!(
+            sender_wallet.create_encrypted_message_tx(&ed25519_public_key, message),
+            Err(Error::Encryption(_))
+        ));
+    }
+
     #[test]
     fn test_stake_transaction() {
         let (mut sender_wallet, _, sender_key) = setup_default_test_wallet();
@@ -281,7 +506,7 @@ pub mod test {
                 nonce: 0,
             }
         );
-        assert_eq!(tx.fees(), 0);
+        assert_eq!(tx.fees(&FeeSchedule::default()), 0);
 
         // Apply the transaction to the sender wallet and verify funds adjust correctly.
         sender_wallet.apply_tx(signed_tx.clone()).unwrap();
@@ -290,6 +515,139 @@ pub mod test {
         assert_eq!(sender_wallet.nonce, 1);
     }
 
+    #[test]
+    fn test_huge_coin_transaction() {
+        let (mut wallet, _, wallet_key) = setup_test_wallet(1000);
+        let (receiver, _, _) = setup_test_wallet(0);
+
+        let tx = wallet.create_coin_tx(receiver.address.clone(), u64::MAX);
+        let result = wallet.apply_tx(wallet_key.sign(tx));
+        assert!(matches!(result, Err(Error::InsufficientFunds)));
+        assert_eq!(wallet.balance, 1000);
+    }
+
+    #[test]
+    fn test_genesis_transaction() {
+        let (mut wallet, _, wallet_key) = setup_test_wallet(1000);
+        let mint = Transaction {
+            sender_address: Address::invalid(),
+            kind: TransactionKind::Genesis(500, wallet.address.clone()),
+            nonce: 0,
+        };
+        assert_eq!(mint.fees(&FeeSchedule::new(50)), 0);
+        assert_eq!(mint.cost(&FeeSchedule::new(50)), 0);
+        wallet.apply_verified_tx(&mint).unwrap();
+        assert_eq!(wallet.balance, 1500);
+
+        // A normal address can't mint funds, neither for itself nor for others
+        let (mut receiver_wallet, _, _) = setup_test_wallet(0);
+        let tx = wallet.create_tx(TransactionKind::Genesis(
+            500,
+            receiver_wallet.address.clone(),
+        ));
+        let signed_tx = wallet_key.sign(tx);
+        let result = wallet.apply_tx(signed_tx.clone());
+        assert!(matches!(result, Err(Error::SystemTransaction)));
+        let result = receiver_wallet.apply_tx(signed_tx);
+        assert!(matches!(result, Err(Error::SystemTransaction)));
+        assert_eq!(wallet.balance, 1500);
+        assert_eq!(wallet.nonce, 0);
+        assert_eq!(receiver_wallet.balance, 0);
+    }
+
+    #[test]
+    fn test_invalid_recipient() {
+        let (mut wallet, _, wallet_key) = setup_test_wallet(1000);
+        let txs = [
+            wallet.create_coin_tx(Address::invalid(), 100),
+            wallet.create_message_tx(Address::invalid(), "hello".into()),
+        ];
+        for tx in txs {
+            let result = wallet.apply_tx(wallet_key.sign(tx));
+            assert!(matches!(result, Err(Error::InvalidRecipient)));
+        }
+        assert_eq!(wallet.balance, 1000);
+        assert_eq!(wallet.nonce, 0);
+    }
+
+    #[test]
+    fn test_max_coin_tx() {
+        let cases = [
+            (3, 1000, 10),
+            (3, 100, 0),
+            (50, 101, 0),
+            (0, 7, 2),
+            (3, u64::MAX, 0),
+            (0, u64::MAX, 0),
+        ];
+        for (percent, balance, stake) in cases {
+            let mut wallet =
+                Wallet::with_fee_schedule(Address::invalid(), FeeSchedule::new(percent));
+            wallet.add_funds(balance);
+            wallet.set_stake(stake);
+            let tx = wallet.create_max_coin_tx(Address::invalid());
+            let TransactionKind::Coin(amount, _) = tx.kind else {
+                panic!("expected a coin transaction");
+            };
+            assert!(tx.cost(&wallet.fee_schedule) <= wallet.available_funds());
+            // Only the dust left by the rounding of the fees can't be sent
+            let dust = wallet.available_funds() - tx.cost(&wallet.fee_schedule);
+            assert!(dust <= 1, "{percent}% of {balance} left {dust} dust");
+            // The cost of a larger transaction saturates for huge balances so it's computed here
+            if amount < u64::MAX {
+                let fees = wallet.fee_schedule.coin_fee(amount + 1);
+                let larger_cost = u128::from(amount) + 1 + u128::from(fees);
+                assert!(larger_cost > u128::from(wallet.available_funds()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let mut wallet = Wallet::from_address(Address::invalid());
+        wallet.add_funds(1000);
+        wallet.set_stake(10);
+        let output = wallet.to_string();
+        assert!(output.contains(&Address::invalid().to_string()), "{output}");
+        assert!(output.contains("balance:   1000 BCC"), "{output}");
+        assert!(output.contains("available: 990 BCC"), "{output}");
+        assert!(output.contains("staked:    10 BCC"), "{output}");
+        assert!(output.contains("nonce:     0"), "{output}");
+    }
+
+    #[test]
+    fn test_stake_after_pending_transfer() {
+        let (mut sender_wallet, _, sender_key) = setup_test_wallet(1000);
+        let (receiver_wallet, _, _receiver_key) = setup_default_test_wallet();
+        let transfer = sender_wallet.create_coin_tx(receiver_wallet.address.clone(), 300);
+        sender_wallet.apply_tx(sender_key.sign(transfer)).unwrap();
+        let remaining = 1000 - 300 - 9;
+        assert_eq!(sender_wallet.balance, remaining);
+
+        // Staking the original balance would overcommit the funds moved by the transfer
+        let mut overcommitted = sender_wallet.clone();
+        let tx = overcommitted.create_stake_tx(1000);
+        let result = overcommitted.apply_tx(sender_key.sign(tx));
+        assert!(matches!(result, Err(Error::InsufficientFunds)));
+        assert_eq!(overcommitted.stake, 0);
+        assert_eq!(overcommitted.nonce, 1);
+
+        // The whole remaining balance can be staked
+        let tx = sender_wallet.create_stake_tx(remaining);
+        sender_wallet.apply_tx(sender_key.sign(tx)).unwrap();
+        assert_eq!(sender_wallet.stake, remaining);
+        assert_eq!(sender_wallet.available_funds(), 0);
+
+        // Lowering the stake releases funds again
+        let tx = sender_wallet.create_stake_tx(100);
+        sender_wallet.apply_tx(sender_key.sign(tx)).unwrap();
+        assert_eq!(sender_wallet.available_funds(), remaining - 100);
+
+        let transfer = sender_wallet.create_coin_tx(receiver_wallet.address.clone(), remaining);
+        let result = sender_wallet.apply_tx(sender_key.sign(transfer));
+        assert!(matches!(result, Err(Error::InsufficientFunds)));
+    }
+
     #[test]
     fn test_coin_insufficient_funds() {
         let (mut sender_wallet, _, sender_key) = setup_default_test_wallet();
@@ -314,6 +672,8 @@ pub mod test {
         let tx = sender_wallet.create_message_tx(receiver_wallet.address.clone(), message);
         let signed_tx = sender_key.sign(tx.clone());
 
+        let result = sender_wallet.check_cost(&tx);
+        assert!(matches!(result, Err(Error::CostExceedsFunds(24, 23))));
         let result = sender_wallet.apply_tx(signed_tx.clone());
         assert!(matches!(result, Err(Error::InsufficientFunds)));
         assert_eq!(sender_wallet.nonce, 0);
@@ -331,4 +691,53 @@ pub mod test {
         assert!(matches!(result, Err(Error::InsufficientFunds)));
         assert_eq!(sender_wallet.nonce, 0);
     }
+
+    #[test]
+    fn test_fee_schedules() {
+        let (receiver_wallet, _, _) = setup_default_test_wallet();
+
+        for (percent, expected_fees) in [(0, 0), (3, 30), (10, 100)] {
+            let fee_schedule = FeeSchedule::new(percent);
+            let (mut sender_wallet, _, sender_key) = setup_default_test_wallet();
+            sender_wallet.fee_schedule = fee_schedule;
+
+            let tx = sender_wallet.create_coin_tx(receiver_wallet.address.clone(), 1000);
+            assert_eq!(tx.fees(&fee_schedule), expected_fees);
+            assert_eq!(tx.cost(&fee_schedule), 1000 + expected_fees);
+
+            sender_wallet.apply_tx(sender_key.sign(tx)).unwrap();
+            assert_eq!(
+                sender_wallet.available_funds(),
+                1_000_000 - 1000 - expected_fees
+            );
+        }
+
+        // The fees of huge transfers saturate instead of overflowing
+        let max_tx = receiver_wallet.create_coin_tx(receiver_wallet.address.clone(), u64::MAX);
+        assert_eq!(max_tx.fees(&FeeSchedule::new(200)), u64::MAX);
+        assert_eq!(max_tx.cost(&FeeSchedule::default()), u64::MAX);
+    }
+
+    #[test]
+    fn test_fee_schedule_insufficient_funds() {
+        let (mut sender_wallet, _, sender_key) = setup_test_wallet(1000);
+        let (receiver_wallet, _, _) = setup_default_test_wallet();
+
+        // Affordable with the default 3% fee but not with a 10% fee
+        let tx = sender_wallet.create_coin_tx(receiver_wallet.address.clone(), 950);
+        sender_wallet.fee_schedule = FeeSchedule::new(10);
+        let result = sender_wallet.apply_tx(sender_key.sign(tx));
+        assert!(matches!(result, Err(Error::InsufficientFunds)));
+    }
+
+    #[test]
+    fn test_self_transfer() {
+        let (mut wallet, _, key) = setup_test_wallet(1000);
+
+        let tx = wallet.create_coin_tx(wallet.address.clone(), 100);
+        wallet.apply_tx(key.sign(tx)).unwrap();
+        // Only the 3% fee is lost
+        assert_eq!(wallet.balance, 997);
+        assert_eq!(wallet.nonce, 1);
+    }
 }